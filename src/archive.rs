@@ -0,0 +1,316 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{create_dir_all, read_dir, File};
+use std::io::{copy, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Component, Path};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Is an archive's name one that means it should be compressed?
+fn is_gzip_name(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".gz") || name.ends_with(".tgz")
+}
+
+/// The name that a file or directory has inside of an archive.
+/// An archive of a directory holds the directory itself, and
+/// not only the files in it.
+fn archive_name(src: &Path) -> Result<String> {
+    match src.file_name() {
+        Some(name) => Ok(name.to_string_lossy().to_string()),
+        None => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "can't tell what to name the archived files",
+        )),
+    }
+}
+
+/// The entries of a directory in the order that they're archived in.
+/// Symbolic links are left out, since what they point to can be
+/// outside of the sandbox that the directory is in.
+fn archived_entries(dir: &Path) -> Result<Vec<std::fs::DirEntry>> {
+    let mut entries = vec![];
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_symlink() {
+            entries.push(entry);
+        }
+    }
+    entries.sort_by_key(|entry| entry.file_name());
+    Ok(entries)
+}
+
+/// Check that extracting a file to a path inside of a directory writes
+/// it there, rather than through a symbolic link to somewhere else, and
+/// make the directories that it goes in
+fn check_extract_path(dest: &Path, path: &Path) -> Result<()> {
+    let outside = || {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} would be extracted outside of the directory",
+                path.display()
+            ),
+        )
+    };
+    if path
+        .components()
+        .any(|part| !matches!(part, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(outside());
+    }
+    let target = dest.join(path);
+    if target.is_symlink() {
+        return Err(outside());
+    }
+    if let Some(parent) = target.parent() {
+        // The directories that are already there are checked before any
+        // are made, since one of them can be a link to somewhere else
+        let existing = parent
+            .ancestors()
+            .find(|dir| dir.symlink_metadata().is_ok())
+            .unwrap_or(dest);
+        if !existing.canonicalize()?.starts_with(dest.canonicalize()?) {
+            return Err(outside());
+        }
+        create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+/// Compress a file or directory into a zip archive
+pub fn create_zip(src: &Path, dest: &Path) -> Result<()> {
+    let name = archive_name(src)?;
+    let mut zip = ZipWriter::new(File::create(dest)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    add_to_zip(&mut zip, src, &name, options)?;
+    zip.finish().map_err(Error::other)?;
+    Ok(())
+}
+
+fn add_to_zip<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    path: &Path,
+    name: &str,
+    options: SimpleFileOptions,
+) -> Result<()> {
+    if path.is_dir() {
+        zip.add_directory(name, options).map_err(Error::other)?;
+        for entry in archived_entries(path)? {
+            let entry_name = format!("{}/{}", name, entry.file_name().to_string_lossy());
+            add_to_zip(zip, &entry.path(), &entry_name, options)?;
+        }
+    } else {
+        zip.start_file(name, options).map_err(Error::other)?;
+        copy(&mut File::open(path)?, zip)?;
+    }
+    Ok(())
+}
+
+/// Extract the files in a zip archive into a directory. Files that
+/// would be put outside of the directory are refused, and symbolic
+/// links are left out.
+pub fn extract_zip(archive: &Path, dest: &Path) -> Result<()> {
+    let mut zip = ZipArchive::new(File::open(archive)?).map_err(Error::other)?;
+    create_dir_all(dest)?;
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i).map_err(Error::other)?;
+        if file.is_symlink() {
+            continue;
+        }
+        let path = match file.enclosed_name() {
+            Some(path) => path,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} would be extracted outside of the directory",
+                        file.name()
+                    ),
+                ))
+            }
+        };
+        check_extract_path(dest, &path)?;
+        if file.is_dir() {
+            create_dir_all(dest.join(&path))?;
+        } else {
+            copy(&mut file, &mut File::create(dest.join(&path))?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Put a file or directory into a tar archive, which is
+/// compressed with gzip if its name ends in `.gz` or `.tgz`
+pub fn create_tar(src: &Path, dest: &Path) -> Result<()> {
+    let name = archive_name(src)?;
+    let file = File::create(dest)?;
+    if is_gzip_name(dest) {
+        let encoder = GzEncoder::new(file, Compression::default());
+        write_tar(encoder, src, &name)?.finish()?;
+    } else {
+        write_tar(file, src, &name)?;
+    }
+    Ok(())
+}
+
+fn write_tar<W: Write>(writer: W, src: &Path, name: &str) -> Result<W> {
+    let mut tar = tar::Builder::new(writer);
+    add_to_tar(&mut tar, src, name)?;
+    tar.into_inner()
+}
+
+fn add_to_tar<W: Write>(tar: &mut tar::Builder<W>, path: &Path, name: &str) -> Result<()> {
+    if path.is_dir() {
+        tar.append_dir(name, path)?;
+        for entry in archived_entries(path)? {
+            let entry_name = format!("{}/{}", name, entry.file_name().to_string_lossy());
+            add_to_tar(tar, &entry.path(), &entry_name)?;
+        }
+    } else {
+        tar.append_path_with_name(path, name)?;
+    }
+    Ok(())
+}
+
+/// Extract the files in a tar archive into a directory. Archives
+/// compressed with gzip are recognized no matter what they're named.
+/// Files that would be put outside of the directory are refused,
+/// and symbolic and hard links are left out.
+pub fn extract_tar(archive: &Path, dest: &Path) -> Result<()> {
+    let mut file = File::open(archive)?;
+    let mut magic = [0; 2];
+    let is_gzip = file.read(&mut magic)? == 2 && magic == [0x1f, 0x8b];
+    file.seek(SeekFrom::Start(0))?;
+    if is_gzip {
+        unpack_tar(tar::Archive::new(GzDecoder::new(file)), dest)
+    } else {
+        unpack_tar(tar::Archive::new(file), dest)
+    }
+}
+
+fn unpack_tar<R: Read>(mut archive: tar::Archive<R>, dest: &Path) -> Result<()> {
+    create_dir_all(dest)?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let kind = entry.header().entry_type();
+        if kind.is_symlink() || kind.is_hard_link() {
+            continue;
+        }
+        check_extract_path(dest, &entry.path()?)?;
+        entry.unpack_in(dest)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::path::PathBuf;
+
+    /// Make an empty directory for a test to use
+    fn scratch_dir() -> PathBuf {
+        let dir = temp_dir().join(format!("dune-test-{}", uuid::Uuid::new_v4()));
+        create_dir_all(&dir).expect("the directory should be made");
+        dir
+    }
+
+    /// Make a tar archive with a file at a path, which is written
+    /// into the header as is so that it can be one that `tar` refuses
+    fn tar_with_path(archive: &Path, path: &str) {
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let mut tar = tar::Builder::new(File::create(archive).unwrap());
+        tar.append(&header, &b"hello"[..]).unwrap();
+        tar.finish().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nothing_is_made_through_a_linked_directory() {
+        let dir = scratch_dir();
+        let outside = scratch_dir();
+        let dest = dir.join("dest");
+        create_dir_all(&dest).unwrap();
+        std::os::unix::fs::symlink(&outside, dest.join("link")).unwrap();
+        let archive = dir.join("a.tar");
+        tar_with_path(&archive, "link/made/file.txt");
+
+        assert!(extract_tar(&archive, &dest).is_err());
+        assert!(!outside.join("made").exists());
+    }
+
+    #[test]
+    fn tar_entries_outside_of_the_directory_are_refused() {
+        let dir = scratch_dir();
+        let archive = dir.join("a.tar");
+        for path in ["../evil.txt", "inside/../../evil.txt", "/tmp/evil.txt"] {
+            tar_with_path(&archive, path);
+            let result = extract_tar(&archive, &dir.join("dest"));
+            assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
+        }
+        assert!(!dir.join("evil.txt").exists());
+    }
+
+    #[test]
+    fn zip_entries_outside_of_the_directory_are_refused() {
+        let dir = scratch_dir();
+        let archive = dir.join("a.zip");
+        let mut zip = ZipWriter::new(File::create(&archive).unwrap());
+        zip.start_file("../evil.txt", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"hello").unwrap();
+        zip.finish().unwrap();
+
+        let result = extract_zip(&archive, &dir.join("dest"));
+        assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
+        assert!(!dir.join("evil.txt").exists());
+    }
+
+    #[test]
+    fn link_entries_are_left_out() {
+        let dir = scratch_dir();
+        let mut tar = tar::Builder::new(File::create(dir.join("a.tar")).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        tar.append_link(&mut header, "tar-link", "/").unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+        let mut zip = ZipWriter::new(File::create(dir.join("a.zip")).unwrap());
+        zip.add_symlink("zip-link", "/", SimpleFileOptions::default())
+            .unwrap();
+        zip.finish().unwrap();
+
+        let dest = dir.join("dest");
+        extract_tar(&dir.join("a.tar"), &dest).unwrap();
+        extract_zip(&dir.join("a.zip"), &dest).unwrap();
+        assert!(dest.join("tar-link").symlink_metadata().is_err());
+        assert!(dest.join("zip-link").symlink_metadata().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_are_left_out_of_archives() {
+        let dir = scratch_dir();
+        let src = dir.join("src");
+        create_dir_all(&src).unwrap();
+        std::fs::write(src.join("file.txt"), "hello").unwrap();
+        std::os::unix::fs::symlink("/", src.join("link")).unwrap();
+
+        create_tar(&src, &dir.join("a.tgz")).unwrap();
+        create_zip(&src, &dir.join("a.zip")).unwrap();
+        extract_tar(&dir.join("a.tgz"), &dir.join("from-tar")).unwrap();
+        extract_zip(&dir.join("a.zip"), &dir.join("from-zip")).unwrap();
+        for dest in ["from-tar", "from-zip"] {
+            let dest = dir.join(dest);
+            assert!(dest.join("src/file.txt").is_file());
+            assert!(dest.join("src/link").symlink_metadata().is_err());
+        }
+    }
+}
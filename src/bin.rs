@@ -1,8 +1,288 @@
 extern crate dune;
-use dune::{Error, Shell, INFO, LOGO};
+use dune::{
+    check, debug, format, parse_ast, profile, Error, Severity, Shell, ShellBuilder, INFO, LOGO,
+};
+
+use std::env::{args, current_dir};
+use std::fs::{read_to_string, write};
+use std::io::{stdin, Read};
+use std::process::exit;
+
+/// The flags that can be given before the command or script
+struct Options {
+    /// Don't let the shell access anything outside of the current directory
+    sandbox: bool,
+    /// Load the parts of the standard library written in dune
+    stdlib: bool,
+    /// Print the syntax tree of the program instead of running it
+    parse_only: bool,
+    /// Stop the program when one of the programs it runs fails
+    strict: bool,
+}
+
+/// Create a shell with the options given, and exit
+/// with a nonzero status if it can't be made
+fn build_shell(builder: ShellBuilder, options: &Options) -> Shell {
+    let built = builder
+        .sandboxed(options.sandbox)
+        .stdlib(options.stdlib)
+        .build();
+    match built {
+        Ok(mut shell) => {
+            shell.is_strict = options.strict;
+            shell
+        }
+        Err(e) => {
+            eprintln!("dune: stdlib: {}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Create a shell in the current directory. A sandboxed
+/// shell can't access anything outside of that directory.
+fn shell_in_current_dir(options: &Options) -> Shell {
+    match current_dir() {
+        Ok(dir) => build_shell(Shell::builder().directory(dir), options),
+        Err(e) => {
+            eprintln!("dune: {}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Print the syntax tree of a program, and exit with
+/// a nonzero status if it can't be parsed
+fn print_ast(source: &str) {
+    match parse_ast(source) {
+        Ok(suite) => println!("{:#?}", suite),
+        Err(e) => {
+            eprintln!("Error: {}", e.report(source));
+            exit(1);
+        }
+    }
+}
+
+/// Read all of the standard input, for a program given through a pipe
+fn read_stdin() -> String {
+    let mut source = String::new();
+    if let Err(e) = stdin().read_to_string(&mut source) {
+        eprintln!("dune: {}", e);
+        exit(1);
+    }
+    source
+}
+
+/// Format scripts, and print them unless `--write` is given, which
+/// writes them back to their files instead. The standard input
+/// is formatted if no scripts are given.
+fn format_files(args: &[String]) {
+    let in_place = args
+        .first()
+        .is_some_and(|arg| arg == "--write" || arg == "-w");
+    let paths = if in_place { &args[1..] } else { args };
+    if paths.is_empty() {
+        let source = read_stdin();
+        match format(&source) {
+            Ok(formatted) => print!("{}", formatted),
+            Err(e) => {
+                eprintln!("Error: {}", e.report(&source));
+                exit(1);
+            }
+        }
+    }
+
+    for path in paths {
+        let source = match read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("dune: {}: {}", path, e);
+                exit(1);
+            }
+        };
+        let formatted = match format(&source) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("Error: {}: {}", path, e.report(&source));
+                exit(1);
+            }
+        };
+        if !in_place {
+            print!("{}", formatted);
+        } else if formatted != source {
+            if let Err(e) = write(path, formatted) {
+                eprintln!("dune: {}: {}", path, e);
+                exit(1);
+            }
+        }
+    }
+}
+
+/// Check scripts for problems without running them, and exit
+/// with a nonzero status if any of them would cause errors
+fn check_files(options: &Options, paths: &[String]) {
+    if paths.is_empty() {
+        eprintln!("dune: check requires a script");
+        exit(2);
+    }
+
+    let shell = shell_in_current_dir(options);
+    let mut has_errors = false;
+    for path in paths {
+        let source = match read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("dune: {}: {}", path, e);
+                exit(1);
+            }
+        };
+        match check(&source, &shell) {
+            Ok(diagnostics) => {
+                for diagnostic in diagnostics {
+                    has_errors |= diagnostic.severity == Severity::Error;
+                    eprintln!("{}: {}", path, diagnostic.report(&source));
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {}", path, e.report(&source));
+                has_errors = true;
+            }
+        }
+    }
+    if has_errors {
+        exit(1);
+    }
+}
+
+/// Read a script, and exit with a nonzero status if it can't be read
+fn read_script(path: &str) -> String {
+    let mut source = match read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("dune: {}: {}", path, e);
+            exit(1);
+        }
+    };
+    // Skip the shebang line so that scripts can be run directly,
+    // but keep its newline so the line numbers stay the same
+    if source.starts_with("#!") {
+        source = source.split_off(source.find('\n').unwrap_or(source.len()));
+    }
+    source
+}
+
+/// Run a script under the debugger, which stops it at breakpoints
+/// and asks the user what to do at each one
+fn debug_script(options: &Options, args: &[String]) {
+    let path = match args.first() {
+        Some(path) => path,
+        None => {
+            eprintln!("dune: debug requires a script");
+            exit(2);
+        }
+    };
+    let source = read_script(path);
+    let mut shell = shell_in_current_dir(options);
+    shell.set_script(path);
+    shell.set_args(path, &args[1..]);
+    if let Err(e) = debug(&mut shell, &source) {
+        eprintln!("Error: {}", e.report(&source));
+        exit(1);
+    }
+    exit(shell.exit_status);
+}
+
+/// Run a script, and then show the lines and
+/// functions that it spent the most time on
+fn profile_script(options: &Options, args: &[String]) {
+    let path = match args.first() {
+        Some(path) => path,
+        None => {
+            eprintln!("dune: profile requires a script");
+            exit(2);
+        }
+    };
+    let source = read_script(path);
+    let mut shell = shell_in_current_dir(options);
+    shell.set_script(path);
+    shell.set_args(path, &args[1..]);
+    if let Err(e) = profile(&mut shell, &source) {
+        eprintln!("Error: {}", e.report(&source));
+        exit(1);
+    }
+    exit(shell.exit_status);
+}
+
+/// Execute a program non-interactively in the current directory, and
+/// exit with a nonzero status if it fails or gives one to `exit`
+fn execute(options: &Options, source: &str, script: Option<&str>, arg0: &str, args: &[String]) {
+    if options.parse_only {
+        return print_ast(source);
+    }
+    let mut shell = shell_in_current_dir(options);
+    if let Some(path) = script {
+        shell.set_script(path);
+    }
+    shell.set_args(arg0, args);
+    if let Err(e) = shell.execute_source(source) {
+        eprintln!("Error: {}", e.report(source));
+        exit(1);
+    }
+    exit(shell.exit_status);
+}
 
 fn main() -> Result<(), Error> {
-    println!("{}\n{}", INFO, LOGO);
-    Shell::new().run();
+    let mut args = args().skip(1).collect::<Vec<String>>();
+    let mut options = Options {
+        sandbox: false,
+        stdlib: true,
+        parse_only: false,
+        strict: false,
+    };
+    while let Some(flag) = args.first() {
+        match flag.as_str() {
+            "--sandbox" => options.sandbox = true,
+            "--no-stdlib" => options.stdlib = false,
+            "--parse-only" => options.parse_only = true,
+            "--strict" => options.strict = true,
+            _ => break,
+        }
+        args.remove(0);
+    }
+
+    match args.first().map(String::as_str) {
+        // Like other shells, the first argument after the
+        // command string is used as the name of the program
+        Some("-c") => match args.get(1) {
+            Some(command) => match args.get(2) {
+                Some(arg0) => execute(&options, command, None, arg0, &args[3..]),
+                None => execute(&options, command, None, "dune", &[]),
+            },
+            None => {
+                eprintln!("dune: -c requires an argument");
+                exit(2);
+            }
+        },
+        Some("fmt") => format_files(&args[1..]),
+        Some("check") => check_files(&options, &args[1..]),
+        Some("debug") => debug_script(&options, &args[1..]),
+        Some("profile") => profile_script(&options, &args[1..]),
+        Some(path) => execute(&options, &read_script(path), Some(path), path, &args[1..]),
+        // There's no program to parse in the interactive shell,
+        // so the program is read from the standard input instead
+        None if options.parse_only => print_ast(&read_stdin()),
+        None => {
+            println!("{}\n{}", INFO, LOGO);
+            // The interactive shell starts in the home directory,
+            // unless it's sandboxed to the current directory
+            let mut shell = if options.sandbox {
+                shell_in_current_dir(&options)
+            } else {
+                build_shell(Shell::builder(), &options)
+            };
+            shell.run();
+            exit(shell.exit_status);
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,301 @@
+use crate::parser::parse_ast;
+use crate::shell::{find_program, is_result_name, program_names, Shell};
+use crate::tokens::{
+    most_similar, Builtin, Error, Expr, FnCall, FunctionDef, Identifier, Name, Span, Suite, Value,
+};
+use crate::visit::{walk_expr, walk_value, Visit};
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// The variables that the shell reads itself, which
+/// are used even if the program never reads them
+const SHELL_VARIABLES: &[&str] = &["prompt", "theme", "AUTO_CD", "REPORT_TIME", "RM_TRASH"];
+
+/// How serious a problem found by `check` is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    /// Code that fails when it runs
+    Error,
+    /// Code that runs, but probably doesn't do what was meant
+    Warning,
+}
+
+/// A problem found in a program without running it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The code that has the problem
+    pub span: Span,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+impl Diagnostic {
+    /// Describe the problem, and show where it is in the source
+    pub fn report(&self, source: &str) -> String {
+        format!("{}{}", self, self.span.show(source))
+    }
+}
+
+/// Find the problems in a program without running any of it: names
+/// that aren't defined by the program or the shell, commands that
+/// aren't programs, variables that are never used, and code that
+/// can't be reached because it comes after `exit`, `return`, `break`,
+/// or `continue`. Names are looked up in the shell too, so a shell
+/// with the standard library loaded knows about its functions.
+pub fn check(source: &str, shell: &Shell) -> Result<Vec<Diagnostic>, Error> {
+    let Suite(exprs) = parse_ast(source)?;
+    let mut checker = Checker {
+        shell,
+        known: shell.names().into_iter().collect(),
+        programs: None,
+        scopes: vec![],
+        span: Span::default(),
+        diagnostics: vec![],
+    };
+    checker.visit_function(&[], &Suite(exprs));
+    checker
+        .diagnostics
+        .sort_by_key(|diagnostic| diagnostic.span.start);
+    Ok(checker.diagnostics)
+}
+
+/// The names defined in a function, or in the whole program.
+/// A name can be used before the code that defines it, such as
+/// a function that calls another one defined after it.
+#[derive(Default)]
+struct Definitions {
+    defined: BTreeSet<String>,
+    /// The variables that aren't functions, and where each is first assigned
+    assigned: Vec<(String, Span)>,
+    used: BTreeSet<String>,
+    /// Whether a file is imported without a name, which can define any name
+    imports_all: bool,
+    /// Where the statement whose names are being found is
+    span: Span,
+}
+
+/// Finds the names defined in a suite, and in the suites nested in it,
+/// but not in the functions in it, which have their own names
+impl Visit for Definitions {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Spanned(span, _) => self.span = *span,
+            Expr::Assignment(Name::Name(Identifier(name)), value) => {
+                self.defined.insert(name.to_string());
+                let is_function = matches!(unspanned(value), Value::Function(_));
+                if !is_function
+                    && !self
+                        .assigned
+                        .iter()
+                        .any(|(other, _)| other == name.as_str())
+                {
+                    self.assigned.push((name.to_string(), self.span));
+                }
+            }
+            Expr::FunctionDef(FunctionDef(Name::Name(Identifier(name)), _))
+            | Expr::ForLoop(Identifier(name), ..)
+            | Expr::Import(_, Some(Identifier(name))) => {
+                self.defined.insert(name.to_string());
+            }
+            Expr::Import(_, None) => self.imports_all = true,
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+
+    fn visit_function(&mut self, _params: &[Identifier], _body: &Suite) {}
+}
+
+/// A value without the spans around it
+fn unspanned(mut value: &Value) -> &Value {
+    while let Value::Spanned(_, inner) = value {
+        value = inner;
+    }
+    value
+}
+
+/// Whether nothing after an expression in the same suite can run
+fn ends_suite(expr: &Expr) -> bool {
+    match expr {
+        Expr::Spanned(_, expr) => ends_suite(expr),
+        Expr::Return(_) | Expr::Break | Expr::Continue => true,
+        Expr::Value(value) | Expr::Silent(value) => match unspanned(value) {
+            Value::Builtin(Builtin::Exit) => true,
+            Value::FnCall(FnCall(function, _)) => {
+                *unspanned(function) == Value::Builtin(Builtin::Exit)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+struct Checker<'a> {
+    shell: &'a Shell,
+    /// The names defined in the shell
+    known: BTreeSet<String>,
+    /// The programs on `PATH`, which are only listed if they're needed
+    programs: Option<Vec<String>>,
+    /// The names defined in each function the code being checked is in,
+    /// with the innermost function last
+    scopes: Vec<Definitions>,
+    /// Where the code being checked is
+    span: Span,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, severity: Severity, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            message,
+            span,
+        });
+    }
+
+    /// Find the name that is closest to a misspelled one,
+    /// and optionally look at the programs on `PATH` too
+    fn similar(&mut self, name: &str, with_programs: bool) -> Option<String> {
+        let mut names = self.known.iter().cloned().collect::<Vec<_>>();
+        for definitions in &self.scopes {
+            names.extend(definitions.defined.iter().cloned());
+        }
+        if with_programs {
+            names.extend(self.programs.get_or_insert_with(program_names).clone());
+        }
+        most_similar(name, names)
+    }
+
+    /// Record that a name is used, and whether it's defined
+    fn is_defined(&mut self, name: &str) -> bool {
+        for definitions in self.scopes.iter_mut().rev() {
+            if definitions.defined.contains(name) {
+                definitions.used.insert(name.to_string());
+                return true;
+            }
+        }
+        // The results of earlier commands are defined when the code runs
+        self.known.contains(name)
+            || is_result_name(name)
+            || self.scopes.iter().any(|scope| scope.imports_all)
+    }
+}
+
+impl Visit for Checker<'_> {
+    /// Check the body of a function, or of the whole program
+    fn visit_function(&mut self, params: &[Identifier], body: &Suite) {
+        let mut definitions = Definitions::default();
+        definitions
+            .defined
+            .extend(params.iter().map(|Identifier(param)| param.to_string()));
+        definitions.span = self.span;
+        definitions.visit_suite(body);
+        self.scopes.push(definitions);
+        self.visit_suite(body);
+
+        if let Some(definitions) = self.scopes.pop() {
+            for (name, span) in definitions.assigned {
+                // Variables in capitals are settings, like `REPORT_TIME`
+                let is_exempt = !name.chars().any(char::is_lowercase)
+                    || SHELL_VARIABLES.contains(&name.as_str());
+                if !is_exempt && !definitions.used.contains(&name) {
+                    self.report(
+                        Severity::Warning,
+                        span,
+                        format!("unused variable `{}`", name),
+                    );
+                }
+            }
+        }
+    }
+
+    fn visit_suite(&mut self, Suite(exprs): &Suite) {
+        let mut is_reachable = true;
+        // The unreachable code in a suite is only reported once
+        let mut is_reported = false;
+        for expr in exprs {
+            if let (false, false, Expr::Spanned(span, _)) = (is_reachable, is_reported, expr) {
+                self.report(Severity::Warning, *span, String::from("unreachable code"));
+                is_reported = true;
+            }
+            self.visit_expr(expr);
+            is_reachable &= !ends_suite(expr);
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Spanned(span, _) => self.span = *span,
+            Expr::Command(Identifier(name), _)
+                if !self.is_defined(name)
+                    && find_program(&self.shell.directory, name).is_none() =>
+            {
+                let message = match self.similar(name, true) {
+                    Some(similar) => {
+                        format!("unknown command `{}`, did you mean `{}`?", name, similar)
+                    }
+                    None => format!("unknown command `{}`", name),
+                };
+                self.report(Severity::Error, self.span, message);
+            }
+            // A name by itself can also be a program to run
+            Expr::Value(value) | Expr::Silent(value) => {
+                if let Value::Name(Name::Name(Identifier(name))) = unspanned(value) {
+                    let is_program = find_program(&self.shell.directory, name).is_some();
+                    if !self.is_defined(name) && !is_program {
+                        let similar = self.similar(name, true);
+                        let message =
+                            Error::UndefinedVariable(name.to_string(), similar).to_string();
+                        self.report(Severity::Error, value.span(), message);
+                    }
+                    return;
+                }
+            }
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+
+    fn visit_value(&mut self, value: &Value) {
+        match value {
+            Value::Spanned(span, _) => self.span = *span,
+            Value::Name(Name::Name(Identifier(name))) if !self.is_defined(name) => {
+                let similar = self.similar(name, false);
+                let message = Error::UndefinedVariable(name.to_string(), similar).to_string();
+                self.report(Severity::Error, self.span, message);
+            }
+            Value::FnCall(FnCall(function, args)) => {
+                if let Value::Name(Name::Name(Identifier(name))) = unspanned(function) {
+                    if !self.is_defined(name) {
+                        let span = match **function {
+                            Value::Spanned(span, _) => span,
+                            _ => self.span,
+                        };
+                        let message = match self.similar(name, false) {
+                            Some(similar) => {
+                                format!("unknown function `{}`, did you mean `{}`?", name, similar)
+                            }
+                            None => format!("unknown function `{}`", name),
+                        };
+                        self.report(Severity::Error, span, message);
+                    }
+                    for arg in args {
+                        self.visit_value(arg);
+                    }
+                    return;
+                }
+            }
+            _ => {}
+        }
+        walk_value(self, value);
+    }
+}
@@ -0,0 +1,64 @@
+use std::env::var_os;
+use std::io::{stdout, IsTerminal};
+
+/// The colors that text and its background can be, with
+/// the ANSI code that gives text each color
+pub const COLORS: &[(&str, u8)] = &[
+    ("black", 30),
+    ("red", 31),
+    ("green", 32),
+    ("yellow", 33),
+    ("blue", 34),
+    ("magenta", 35),
+    ("cyan", 36),
+    ("white", 37),
+];
+
+/// The ways that text can be styled other than its color,
+/// with the ANSI code for each style
+pub const STYLES: &[(&str, u8)] = &[
+    ("bold", 1),
+    ("dim", 2),
+    ("italic", 3),
+    ("underline", 4),
+    ("blink", 5),
+    ("reverse", 7),
+    ("strikethrough", 9),
+];
+
+/// Get the ANSI code for a color, or for the background
+/// color when `background` is set
+pub fn color_code(name: &str, background: bool) -> Option<u8> {
+    COLORS
+        .iter()
+        .find(|(color, _)| *color == name)
+        .map(|(_, code)| if background { code + 10 } else { *code })
+}
+
+/// Get the ANSI code for a style, such as `bold`
+pub fn style_code(name: &str) -> Option<u8> {
+    STYLES
+        .iter()
+        .find(|(style, _)| *style == name)
+        .map(|(_, code)| *code)
+}
+
+/// Should text be colored? Colors are only written to a terminal,
+/// so that they don't end up in files or other programs' input,
+/// and they can be turned off by setting `NO_COLOR`.
+pub fn colors_enabled() -> bool {
+    stdout().is_terminal() && var_os("NO_COLOR").is_none()
+}
+
+/// Wrap text in the ANSI codes that give it a color or style,
+/// unless colors are turned off
+pub fn paint(text: &str, codes: &[u8]) -> String {
+    if codes.is_empty() || !colors_enabled() {
+        return text.to_string();
+    }
+    let codes = codes
+        .iter()
+        .map(|code| code.to_string())
+        .collect::<Vec<String>>();
+    format!("\x1b[{}m{}\x1b[0m", codes.join(";"), text)
+}
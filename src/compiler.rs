@@ -0,0 +1,591 @@
+use crate::debugger::reach_statement;
+use crate::interrupt::is_interrupted;
+use crate::profiler::record_statement;
+use crate::shell::{Frame, Shell};
+use crate::symbol::Symbol;
+use crate::tokens::{
+    is_true, Builtin, Error, Execute, Expr, FnCall, Identifier, Name, Pattern, Signal, Span, Suite,
+    Value,
+};
+use std::cell::OnceCell;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::mem::{replace, take};
+use std::vec::IntoIter;
+use xmachine::Ref;
+
+/// A step of compiled code. The control flow of the code is turned into
+/// jumps, so that running it doesn't walk the syntax tree, and the other
+/// values and expressions are run as they are.
+#[derive(Clone, Debug)]
+pub enum Instruction {
+    /// The start of a statement, where the code stops if Ctrl-C was
+    /// pressed, the shell is exiting, or a signal was raised
+    Step,
+    /// Push a value that isn't compiled any further
+    Eval(Value),
+    /// Run an expression that isn't compiled any further
+    Run(Expr),
+    /// Store the value on top of the stack in a name
+    Assign(Name),
+    /// Go to an instruction
+    Jump(usize),
+    /// Pop a value, and go to an instruction if it's false
+    JumpIfFalse(usize),
+    /// Keep the value on top of the stack and go to an instruction
+    /// if it's false, or pop it and go on if it's true, like `&&`
+    And(usize),
+    /// Keep the value on top of the stack and go to an instruction
+    /// if it's true, or pop it and go on if it's false, like `||`
+    Or(usize),
+    /// Set aside the stack, so that a function only sees its arguments
+    Isolate,
+    /// Put back the stack set aside by the last `Isolate`,
+    /// under the values that the function left behind
+    Restore,
+    /// Put back the stack set aside by the last `Isolate`,
+    /// and drop the values left since then
+    Discard,
+    /// Drop the values left since the last `Isolate`, which a loop
+    /// does each time around, since a loop isn't shown
+    Clear,
+    /// Call the function on top of the stack
+    Call(Frame),
+    Builtin(Builtin),
+    /// Pop a list to loop over
+    Iterate,
+    /// Store the next item of the list being looped over in a variable,
+    /// or go to an instruction if there are none left
+    Next(Symbol, usize),
+    /// Stop looping over the last list
+    EndIterate,
+    /// Pop a value, and go to the instruction of the first pattern it
+    /// matches, or to the last instruction if it matches none of them
+    Match(Vec<(Pattern, usize)>, usize),
+    /// Stop running, and raise a signal for the code that ran this
+    Signal(Signal),
+}
+
+/// Code compiled into a flat list of instructions, each with where its
+/// code is in the source. Running it doesn't walk the syntax tree for
+/// its control flow, and what a call needs to know about the function
+/// it calls is worked out once, when the code is compiled.
+#[derive(Clone, Debug, Default)]
+pub struct Code(pub Vec<(Instruction, Option<Span>)>);
+
+/// Compile the body of a function, or a program
+pub fn compile(Suite(exprs): &Suite) -> Code {
+    let mut compiler = Compiler::default();
+    for expr in exprs {
+        compiler.expr(expr);
+    }
+    compiler.code
+}
+
+/// Compile a single expression, such as a loop
+pub fn compile_expr(expr: &Expr) -> Code {
+    let mut compiler = Compiler::default();
+    compiler.expr(expr);
+    compiler.code
+}
+
+/// The code that a loop is compiled to the first time it's run, which
+/// is kept with the loop so that it isn't compiled again. It isn't part
+/// of what the loop means, so a copy of a loop starts without it, and
+/// loops are compared without it.
+#[derive(Default)]
+pub struct LoopCode(OnceCell<Code>);
+
+impl LoopCode {
+    /// Run a loop, given with where it is so the debugger can stop in it
+    pub fn run(&self, expr: &Expr, shell: &mut Shell) -> Result<(), Error> {
+        self.0.get_or_init(|| compile_expr(expr)).execute(shell)
+    }
+}
+
+impl Clone for LoopCode {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl PartialEq for LoopCode {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl PartialOrd for LoopCode {
+    fn partial_cmp(&self, _: &Self) -> Option<Ordering> {
+        Some(Ordering::Equal)
+    }
+}
+
+impl Debug for LoopCode {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str("LoopCode")
+    }
+}
+
+/// The instructions that `break` and `continue` go to in a loop
+struct Loop {
+    /// The jumps made by `break`, which go to the end of the loop
+    /// once it's known where that is
+    breaks: Vec<usize>,
+    continue_to: usize,
+}
+
+#[derive(Default)]
+struct Compiler {
+    code: Code,
+    /// Where the code being compiled is
+    span: Option<Span>,
+    /// The loops that the code being compiled is in, innermost last
+    loops: Vec<Loop>,
+}
+
+impl Compiler {
+    /// Where the next instruction will be
+    fn here(&self) -> usize {
+        self.code.0.len()
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        self.code.0.push((instruction, self.span));
+        self.here() - 1
+    }
+
+    /// Make a jump that was emitted before go to the next instruction
+    fn patch(&mut self, jump: usize) {
+        let target = self.here();
+        match &mut self.code.0[jump].0 {
+            Instruction::Jump(to)
+            | Instruction::JumpIfFalse(to)
+            | Instruction::And(to)
+            | Instruction::Or(to)
+            | Instruction::Next(_, to)
+            | Instruction::Match(_, to) => *to = target,
+            _ => {}
+        }
+    }
+
+    fn suite(&mut self, Suite(exprs): &Suite) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    /// Compile the body of a loop, and make its `break`s go to the end
+    fn loop_body(&mut self, body: &Suite, continue_to: usize) -> Vec<usize> {
+        self.loops.push(Loop {
+            breaks: vec![],
+            continue_to,
+        });
+        self.suite(body);
+        self.emit(Instruction::Jump(continue_to));
+        self.loops.pop().map(|l| l.breaks).unwrap_or_default()
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        if let Expr::Spanned(span, expr) = expr {
+            let outer = self.span.replace(*span);
+            self.expr(expr);
+            self.span = outer;
+            return;
+        }
+        self.emit(Instruction::Step);
+        match expr {
+            Expr::Assignment(name, value) => {
+                self.value(value);
+                self.emit(Instruction::Assign(name.clone()));
+            }
+            Expr::WhileLoop(condition, body, _) => {
+                self.emit(Instruction::Isolate);
+                let start = self.emit(Instruction::Clear);
+                self.emit(Instruction::Step);
+                self.value(condition);
+                let exit = self.emit(Instruction::JumpIfFalse(0));
+                let breaks = self.loop_body(body, start);
+                self.patch(exit);
+                for jump in breaks {
+                    self.patch(jump);
+                }
+                self.emit(Instruction::Discard);
+            }
+            Expr::ForLoop(Identifier(store), list, body, _) => {
+                self.value(list);
+                self.emit(Instruction::Iterate);
+                self.emit(Instruction::Isolate);
+                let next = self.emit(Instruction::Clear);
+                self.emit(Instruction::Step);
+                let exit = self.emit(Instruction::Next(store.clone(), 0));
+                let breaks = self.loop_body(body, next);
+                self.patch(exit);
+                for jump in breaks {
+                    self.patch(jump);
+                }
+                self.emit(Instruction::Discard);
+                self.emit(Instruction::EndIterate);
+            }
+            Expr::IfThenElse(condition, then_body, else_body) => {
+                self.value(condition);
+                let to_else = self.emit(Instruction::JumpIfFalse(0));
+                self.suite(then_body);
+                let to_end = self.emit(Instruction::Jump(0));
+                self.patch(to_else);
+                self.suite(else_body);
+                self.patch(to_end);
+            }
+            Expr::Match(value, arms) => {
+                self.value(value);
+                let table = self.emit(Instruction::Match(vec![], 0));
+                let mut targets = vec![];
+                let mut to_end = vec![];
+                for (pattern, body) in arms {
+                    targets.push((pattern.clone(), self.here()));
+                    self.suite(body);
+                    to_end.push(self.emit(Instruction::Jump(0)));
+                }
+                if let Instruction::Match(arms, _) = &mut self.code.0[table].0 {
+                    *arms = targets;
+                }
+                self.patch(table);
+                for jump in to_end {
+                    self.patch(jump);
+                }
+            }
+            Expr::Return(value) => {
+                self.value(value);
+                self.emit(Instruction::Signal(Signal::Return));
+            }
+            Expr::Break => {
+                let jump = self.emit(Instruction::Jump(0));
+                match self.loops.last_mut() {
+                    Some(l) => l.breaks.push(jump),
+                    // Outside of a loop, the code that ran this is stopped instead
+                    None => self.code.0[jump].0 = Instruction::Signal(Signal::Break),
+                }
+            }
+            Expr::Continue => match self.loops.last().map(|l| l.continue_to) {
+                Some(to) => {
+                    self.emit(Instruction::Jump(to));
+                }
+                None => {
+                    self.emit(Instruction::Signal(Signal::Continue));
+                }
+            },
+            // A name by itself can also be a program to run
+            Expr::Value(value) | Expr::Silent(value) if value.as_identifier().is_none() => {
+                self.value(value)
+            }
+            // In a function, `;` doesn't change what the function returns
+            Expr::Silent(value) => {
+                self.emit(Instruction::Run(Expr::Value(value.clone())));
+            }
+            expr => {
+                self.emit(Instruction::Run(expr.clone()));
+            }
+        }
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Spanned(span, value) => {
+                let outer = self.span.replace(*span);
+                self.value(value);
+                self.span = outer;
+            }
+            Value::FnCall(FnCall(function, args)) => {
+                self.emit(Instruction::Isolate);
+                // The first argument ends up on top
+                for arg in args.iter().rev() {
+                    self.value(arg);
+                }
+                match &**function {
+                    Value::Builtin(builtin) => {
+                        self.emit(Instruction::Builtin(builtin.clone()));
+                    }
+                    function => {
+                        self.value(function);
+                        // The frame has the whole call, so that errors
+                        // inside of the function show where it was called
+                        self.emit(Instruction::Call(Frame {
+                            function: function.callee_name(),
+                            span: self.span.unwrap_or_else(|| function.span()),
+                        }));
+                    }
+                }
+                self.emit(Instruction::Restore);
+            }
+            Value::And(lhs, rhs) => {
+                self.value(lhs);
+                let to_end = self.emit(Instruction::And(0));
+                self.value(rhs);
+                self.patch(to_end);
+            }
+            Value::Or(lhs, rhs) => {
+                self.value(lhs);
+                let to_end = self.emit(Instruction::Or(0));
+                self.value(rhs);
+                self.patch(to_end);
+            }
+            value => {
+                self.emit(Instruction::Eval(value.clone()));
+            }
+        }
+    }
+}
+
+/// What running compiled code keeps track of besides the shell
+#[derive(Default)]
+struct Vm {
+    /// The stacks set aside for the functions being called
+    stacks: Vec<Vec<Ref<xmachine::Value>>>,
+    /// The items left in the lists being looped over
+    lists: Vec<IntoIter<Ref<xmachine::Value>>>,
+}
+
+impl Vm {
+    fn run(&mut self, Code(code): &Code, shell: &mut Shell) -> Result<(), Error> {
+        let mut next = 0;
+        while let Some((instruction, span)) = code.get(next) {
+            next += 1;
+            let result = self.step(instruction, span, shell, &mut next);
+            match result {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    return Err(match span {
+                        Some(span) => e.at(*span),
+                        None => e,
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run an instruction, and return whether to keep running
+    fn step(
+        &mut self,
+        instruction: &Instruction,
+        span: &Option<Span>,
+        shell: &mut Shell,
+        next: &mut usize,
+    ) -> Result<bool, Error> {
+        match instruction {
+            Instruction::Step => {
+                if is_interrupted() {
+                    return Err(Error::Interrupted);
+                }
+                if shell.signal.is_some() || shell.is_done {
+                    return Ok(false);
+                }
+                if let Some(span) = span {
+                    record_statement(*span);
+                    reach_statement(shell, *span)?;
+                }
+            }
+            Instruction::Eval(value) => value.execute(shell)?,
+            Instruction::Run(expr) => expr.execute(shell)?,
+            Instruction::Assign(name) => name.assign(shell)?,
+            Instruction::Jump(to) => *next = *to,
+            Instruction::JumpIfFalse(to) => {
+                if !shell.machine.pop().is_some_and(|v| is_true(&v)) {
+                    *next = *to;
+                }
+            }
+            Instruction::And(to) => match shell.machine.pop() {
+                Some(v) if is_true(&v) => {}
+                Some(v) => {
+                    shell.machine.push(v);
+                    *next = *to;
+                }
+                None => {
+                    shell.machine.return_value(0);
+                    *next = *to;
+                }
+            },
+            Instruction::Or(to) => match shell.machine.pop() {
+                Some(v) if is_true(&v) => {
+                    shell.machine.push(v);
+                    *next = *to;
+                }
+                _ => {}
+            },
+            Instruction::Isolate => self.stacks.push(take(&mut shell.machine.stack)),
+            Instruction::Restore => self.restore(shell),
+            Instruction::Discard => {
+                if let Some(outer) = self.stacks.pop() {
+                    shell.machine.stack = outer;
+                }
+            }
+            Instruction::Clear => shell.machine.stack.clear(),
+            Instruction::Call(frame) => shell.call_in_frame(frame.clone())?,
+            Instruction::Builtin(builtin) => builtin.execute(shell)?,
+            Instruction::Iterate => {
+                let items = match shell.machine.pop() {
+                    Some(v) => match (*v).clone() {
+                        xmachine::Value::List(items) => items,
+                        other => {
+                            return Err(Error::TypeError(format!(
+                                "can't iterate over non-list {}",
+                                other
+                            )))
+                        }
+                    },
+                    None => vec![],
+                };
+                self.lists.push(items.into_iter());
+            }
+            Instruction::Next(store, to) => match self.lists.last_mut().and_then(Iterator::next) {
+                Some(item) => shell.scope.define(store.clone(), item),
+                None => *next = *to,
+            },
+            Instruction::EndIterate => {
+                self.lists.pop();
+            }
+            Instruction::Match(arms, to) => {
+                let value = shell.machine.pop().unwrap_or_else(xmachine::Value::none);
+                *next = arms
+                    .iter()
+                    .find(|(pattern, _)| pattern.matches(&value))
+                    .map_or(*to, |(_, target)| *target);
+            }
+            Instruction::Signal(signal) => {
+                shell.signal = Some(*signal);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Put back the last stack that was set aside
+    fn restore(&mut self, shell: &mut Shell) {
+        if let Some(outer) = self.stacks.pop() {
+            let mut inner = replace(&mut shell.machine.stack, outer);
+            shell.machine.stack.append(&mut inner);
+        }
+    }
+}
+
+impl Execute for Code {
+    fn execute(&self, shell: &mut Shell) -> Result<(), Error> {
+        let mut vm = Vm::default();
+        let result = vm.run(self, shell);
+        // Code that stopped in the middle of calling a
+        // function still gives back the stacks it set aside
+        while !vm.stacks.is_empty() {
+            vm.restore(shell);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::Shell;
+    use xmachine::Value;
+
+    /// Run a program, and get the numbers left by its top level statements
+    fn numbers(source: &str) -> Vec<f64> {
+        let mut shell = Shell::new();
+        shell
+            .load_stdlib()
+            .expect("the standard library should load");
+        shell
+            .eval(source)
+            .expect("the program should run")
+            .into_iter()
+            .map(|value| match value {
+                Value::Number(n) => n,
+                value => panic!("expected a number, got {}", value),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn break_and_continue_leave_the_innermost_loop() {
+        let source = "
+            fn count() {
+                total = 0
+                items = list.range(0, 5)
+                for i in items {
+                    for j in items {
+                        if j > i { break }
+                        if j == 1 { continue }
+                        total = add(total, 1)
+                    }
+                }
+                return total
+            }
+            count()
+        ";
+        assert_eq!(numbers(source), vec![11.0]);
+    }
+
+    #[test]
+    fn return_leaves_every_loop() {
+        let source = "
+            fn search(n) {
+                items = list.range(0, 10)
+                for i in items {
+                    while true {
+                        if i == n { return i }
+                        break
+                    }
+                }
+                return -1
+            }
+            search(3)
+            search(20)
+        ";
+        assert_eq!(numbers(source), vec![3.0, -1.0]);
+    }
+
+    #[test]
+    fn loops_at_the_top_level() {
+        // These are run by the code kept with each loop
+        let source = "
+            i = 0
+            while true {
+                i = add(i, 1)
+                if i < 3 { continue }
+                break
+            }
+            i
+            items = list.range(0, 3)
+            for j in items { i = add(i, j) }
+            i
+        ";
+        assert_eq!(numbers(source), vec![3.0, 6.0]);
+    }
+
+    #[test]
+    fn match_runs_the_first_arm_that_matches() {
+        let source = "
+            fn describe(x) {
+                match x {
+                    1 => { return 10 }
+                    [1, _] => { return 20 }
+                    _ => { return 30 }
+                }
+            }
+            describe(1)
+            describe(list.range(1, 3))
+            describe(5)
+            fn first_not_skipped(n) {
+                items = list.range(n, 10)
+                for i in items {
+                    match i {
+                        3 => { continue }
+                        4 => { continue }
+                        _ => { return i }
+                    }
+                }
+            }
+            first_not_skipped(3)
+        ";
+        assert_eq!(numbers(source), vec![10.0, 20.0, 30.0, 5.0]);
+    }
+}
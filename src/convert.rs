@@ -0,0 +1,179 @@
+use crate::tokens::Error;
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Error as _, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt::{Formatter, Result as FmtResult};
+use toml::Value as Toml;
+use xmachine::{Ref, Value};
+
+/// The data that a dune value can hold, without the functions and errors
+/// that only mean something inside of a running shell. It can be written
+/// and read with any serde format, which is how data is exchanged with
+/// scripts, such as by the JSON builtins or by programs that embed dune.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DuneValue {
+    None,
+    Number(f64),
+    String(String),
+    List(Vec<DuneValue>),
+    Dict(BTreeMap<String, DuneValue>),
+}
+
+impl DuneValue {
+    /// Get the data in a dune value. Functions and
+    /// errors have no equivalent, so they can't be converted.
+    pub fn from_value(value: &Value) -> Result<Self, Error> {
+        Ok(match value {
+            Value::None => Self::None,
+            Value::Number(n) => Self::Number(*n),
+            Value::String(s) => Self::String(s.clone()),
+            Value::List(items) => Self::List(
+                items
+                    .iter()
+                    .map(|item| Self::from_value(item))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Tree(entries) => Self::Dict(
+                entries
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), Self::from_value(value)?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            Value::Function(_) => {
+                return Err(Error::TypeError(String::from(
+                    "can't convert a function to data",
+                )))
+            }
+            Value::Error(e) => {
+                return Err(Error::TypeError(format!(
+                    "can't convert the error `{}` to data",
+                    e
+                )))
+            }
+        })
+    }
+
+    /// Make a dune value that holds this data
+    pub fn into_value(self) -> Ref<Value> {
+        match self {
+            Self::None => Value::none(),
+            Self::Number(n) => Value::number(n),
+            Self::String(s) => Value::string(s),
+            Self::List(items) => Ref::new(Value::List(
+                items.into_iter().map(Self::into_value).collect(),
+            )),
+            Self::Dict(entries) => Ref::new(Value::Tree(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into_value()))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Get the data in a TOML document. Dates and times
+    /// are kept as the strings they were written as.
+    pub fn from_toml(toml: Toml) -> Self {
+        match toml {
+            Toml::String(s) => Self::String(s),
+            Toml::Integer(n) => Self::Number(n as f64),
+            Toml::Float(n) => Self::Number(n),
+            Toml::Boolean(b) => Self::Number(b as i32 as f64),
+            Toml::Datetime(date) => Self::String(date.to_string()),
+            Toml::Array(items) => Self::List(items.into_iter().map(Self::from_toml).collect()),
+            Toml::Table(entries) => Self::Dict(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, Self::from_toml(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl Serialize for DuneValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::None => serializer.serialize_unit(),
+            // Whole numbers are written without a fractional part
+            Self::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+                serializer.serialize_i64(*n as i64)
+            }
+            Self::Number(n) if n.is_finite() => serializer.serialize_f64(*n),
+            Self::Number(n) => Err(S::Error::custom(format!("can't convert {} to data", n))),
+            Self::String(s) => serializer.serialize_str(s),
+            Self::List(items) => serializer.collect_seq(items),
+            Self::Dict(entries) => serializer.collect_map(entries),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DuneValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(DuneValueVisitor)
+    }
+}
+
+/// Reads data into a dune value. Maps become dictionaries,
+/// booleans become 1 or 0, and null becomes none.
+struct DuneValueVisitor;
+
+impl<'de> Visitor<'de> for DuneValueVisitor {
+    type Value = DuneValue;
+
+    fn expecting(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "data that dune can hold")
+    }
+
+    fn visit_unit<E>(self) -> Result<DuneValue, E> {
+        Ok(DuneValue::None)
+    }
+
+    fn visit_none<E>(self) -> Result<DuneValue, E> {
+        Ok(DuneValue::None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<DuneValue, D::Error> {
+        DuneValue::deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<DuneValue, E> {
+        Ok(DuneValue::Number(b as i32 as f64))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> Result<DuneValue, E> {
+        Ok(DuneValue::Number(n as f64))
+    }
+
+    fn visit_u64<E>(self, n: u64) -> Result<DuneValue, E> {
+        Ok(DuneValue::Number(n as f64))
+    }
+
+    fn visit_f64<E>(self, n: f64) -> Result<DuneValue, E> {
+        Ok(DuneValue::Number(n))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<DuneValue, E> {
+        Ok(DuneValue::String(s.to_string()))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<DuneValue, E> {
+        Ok(DuneValue::String(s))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<DuneValue, A::Error> {
+        let mut items = vec![];
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(DuneValue::List(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<DuneValue, A::Error> {
+        let mut entries = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry()? {
+            entries.insert(key, value);
+        }
+        Ok(DuneValue::Dict(entries))
+    }
+}
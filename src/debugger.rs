@@ -0,0 +1,265 @@
+use crate::color::colors_enabled;
+use crate::display::{pretty, terminal_width};
+use crate::input::Line;
+use crate::parser::parse;
+use crate::shell::Shell;
+use crate::tokens::{Error, Execute, Span};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashSet};
+
+thread_local! {
+    /// The debugger running the script, if a script is being debugged
+    static DEBUGGER: RefCell<Option<Debugger>> = const { RefCell::new(None) };
+}
+
+/// The commands that can be given when the script is stopped
+const COMMANDS: &str = "\
+step, s          run to the next statement
+next, n          run to the next statement, over the functions it calls
+finish, f        run until the current function returns
+continue, c      run to the next breakpoint
+break, b LINE    stop at a line, or list the breakpoints without a line
+delete, d LINE   stop stopping at a line
+list, l          show the lines around the current one
+print, p CODE    run code where the script stopped, and show its result
+vars             show the variables defined where the script stopped
+stack            show the values on the stack
+where            show the functions being called
+quit, q          stop the script
+";
+
+/// Where the debugger should stop the script next
+#[derive(Clone, Copy)]
+enum Mode {
+    /// Stop only at breakpoints
+    Continue,
+    /// Stop at the next statement
+    Step,
+    /// Stop at the next statement that is at most this many calls deep
+    Next(usize),
+    /// Stop at the next statement that is fewer than this many calls deep
+    Finish(usize),
+}
+
+/// Runs a script one statement at a time when asked to, and stops it
+/// at breakpoints so that the user can look at its variables and stack
+struct Debugger {
+    source: String,
+    /// Where each statement of the script is, which are the places
+    /// that it can stop. Code from other files, like imports, isn't
+    /// stopped in, since its lines aren't lines of the script.
+    statements: HashSet<(usize, usize)>,
+    /// The lines that have a statement on them
+    lines: BTreeSet<usize>,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    /// The statement that was reached last, and how many calls deep it was.
+    /// A loop reaches its first line again when it checks if it should
+    /// go around, and that isn't stopped at twice when it starts.
+    last: Option<(Span, usize)>,
+    /// The command that an empty line runs again
+    last_command: String,
+}
+
+/// Run a script in a shell under the debugger, stopping before its first
+/// statement so that breakpoints can be set. The user is asked what to
+/// do each time the script stops, using the shell's input.
+pub fn debug(shell: &mut Shell, source: &str) -> Result<(), Error> {
+    let mut debugger = Debugger {
+        source: source.to_string(),
+        statements: HashSet::new(),
+        lines: BTreeSet::new(),
+        breakpoints: BTreeSet::new(),
+        mode: Mode::Step,
+        last: None,
+        last_command: String::new(),
+    };
+    for span in parse(source)?.statements() {
+        debugger.statements.insert((span.start, span.end));
+        debugger.lines.insert(span.line);
+    }
+    shell.write("Type `help` to see the debugger's commands.\n");
+
+    DEBUGGER.with(|slot| *slot.borrow_mut() = Some(debugger));
+    let result = shell.execute_source(source);
+    DEBUGGER.with(|slot| *slot.borrow_mut() = None);
+    result
+}
+
+/// Called before each statement is run, to stop the script there if
+/// the debugger should. The debugger is set aside while it's stopped,
+/// so the code that the user runs then doesn't stop itself.
+pub fn reach_statement(shell: &mut Shell, span: Span) -> Result<(), Error> {
+    match DEBUGGER.with(|slot| slot.borrow_mut().take()) {
+        Some(mut debugger) => {
+            let result = debugger.reach(shell, span);
+            DEBUGGER.with(|slot| *slot.borrow_mut() = Some(debugger));
+            result
+        }
+        None => Ok(()),
+    }
+}
+
+impl Debugger {
+    /// Stop before a statement if a breakpoint is on its line,
+    /// or if the last command was to stop at a statement like it
+    fn reach(&mut self, shell: &mut Shell, span: Span) -> Result<(), Error> {
+        let depth = shell.call_stack.len();
+        if !self.statements.contains(&(span.start, span.end)) || self.last == Some((span, depth)) {
+            return Ok(());
+        }
+        self.last = Some((span, depth));
+
+        let should_stop = match self.mode {
+            Mode::Continue => false,
+            Mode::Step => true,
+            Mode::Next(max) => depth <= max,
+            Mode::Finish(limit) => depth < limit,
+        };
+        if should_stop || self.breakpoints.contains(&span.line) {
+            self.stop(shell, span.line)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Ask the user what to do until they let the script go on
+    fn stop(&mut self, shell: &mut Shell, line: usize) -> Result<(), Error> {
+        self.show_lines(shell, line, line);
+        let depth = shell.call_stack.len();
+        loop {
+            let command = match shell.read_line("(debug) ") {
+                Line::Text(command) if command.trim().is_empty() => self.last_command.clone(),
+                Line::Text(command) => command.trim().to_string(),
+                Line::Interrupted => continue,
+                Line::End => return Err(Error::Interrupted),
+            };
+            self.last_command = command.clone();
+
+            let (name, arg) = match command.split_once(char::is_whitespace) {
+                Some((name, arg)) => (name, arg.trim()),
+                None => (command.as_str(), ""),
+            };
+            self.mode = match name {
+                "step" | "s" => Mode::Step,
+                "next" | "n" => Mode::Next(depth),
+                "finish" | "f" => Mode::Finish(depth),
+                "continue" | "c" => Mode::Continue,
+                "quit" | "q" => return Err(Error::Interrupted),
+                _ => {
+                    self.run_command(shell, name, arg, line);
+                    continue;
+                }
+            };
+            return Ok(());
+        }
+    }
+
+    /// Run a command that looks at the script, rather than running it
+    fn run_command(&mut self, shell: &mut Shell, name: &str, arg: &str, line: usize) {
+        match name {
+            "break" | "b" if arg.is_empty() => {
+                if self.breakpoints.is_empty() {
+                    shell.write("There are no breakpoints.\n");
+                }
+                for line in &self.breakpoints {
+                    shell.write(&format!("Breakpoint at line {}\n", line));
+                }
+            }
+            "break" | "b" => match arg.parse::<usize>() {
+                // A line without a statement stops at the next one that has one
+                Ok(wanted) => match self.lines.range(wanted..).next() {
+                    Some(&line) => {
+                        self.breakpoints.insert(line);
+                        shell.write(&format!("Breakpoint at line {}\n", line));
+                    }
+                    None => shell.write_err(&format!("There's no code at line {}\n", wanted)),
+                },
+                Err(_) => shell.write_err(&format!("`{}` is not a line number\n", arg)),
+            },
+            "delete" | "d" => match arg.parse::<usize>() {
+                Ok(line) if self.breakpoints.remove(&line) => {
+                    shell.write(&format!("Deleted the breakpoint at line {}\n", line))
+                }
+                Ok(line) => shell.write_err(&format!("There's no breakpoint at line {}\n", line)),
+                Err(_) => shell.write_err(&format!("`{}` is not a line number\n", arg)),
+            },
+            "list" | "l" => self.show_lines(shell, line.saturating_sub(5).max(1), line + 5),
+            "print" | "p" => {
+                let height = shell.machine.stack.len();
+                match parse(arg).and_then(|suite| suite.execute(shell)) {
+                    Ok(()) => {
+                        for value in shell.machine.stack.split_off(height) {
+                            show_value(shell, &value);
+                        }
+                    }
+                    Err(e) => {
+                        shell.machine.stack.truncate(height);
+                        shell.write_err(&format!("Error: {}\n", e.report(arg)));
+                    }
+                }
+            }
+            "vars" => {
+                let variables = shell.scope.variables();
+                if variables.is_empty() {
+                    shell.write("No variables are defined here.\n");
+                }
+                for (name, value) in variables {
+                    let text = pretty(&value, terminal_width(), colors_enabled());
+                    shell.write(&format!("{} = {}\n", name, text));
+                }
+            }
+            "stack" => {
+                if shell.machine.stack.is_empty() {
+                    shell.write("The stack is empty.\n");
+                }
+                // The top of the stack is shown first
+                for value in shell.machine.stack.clone().iter().rev() {
+                    show_value(shell, value);
+                }
+            }
+            "where" => {
+                let frames = shell.call_stack.clone();
+                if frames.is_empty() {
+                    shell.write("No functions are being called.\n");
+                }
+                for frame in frames.iter().rev() {
+                    shell.write(&format!(
+                        "in {}, called at line {}\n",
+                        frame.function, frame.span.line
+                    ));
+                }
+            }
+            "help" | "h" => shell.write(COMMANDS),
+            _ => shell.write_err(&format!("Unknown command `{}`, try `help`\n", name)),
+        }
+    }
+
+    /// Show the lines of the script from one line to another,
+    /// marking the line that the script stopped at
+    fn show_lines(&self, shell: &Shell, first: usize, last: usize) {
+        let current = self.last.map(|(span, _)| span.line);
+        for (i, text) in self.source.lines().enumerate() {
+            let number = i + 1;
+            if number < first || number > last {
+                continue;
+            }
+            let marker = if Some(number) == current { "->" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&number) {
+                "*"
+            } else {
+                " "
+            };
+            shell.write(&format!(
+                "{}{}{:>4} | {}\n",
+                marker, breakpoint, number, text
+            ));
+        }
+    }
+}
+
+/// Show a value on a line of its own
+fn show_value(shell: &Shell, value: &xmachine::Value) {
+    let text = pretty(value, terminal_width(), colors_enabled());
+    shell.write(&format!("{}\n", text));
+}
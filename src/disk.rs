@@ -0,0 +1,154 @@
+use crate::tokens::Error;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+
+/// The space on a mounted filesystem, as listed by `df`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiskSpace {
+    /// The device or name of the filesystem
+    pub filesystem: String,
+    /// Where the filesystem is mounted
+    pub mount: PathBuf,
+    /// The size of the filesystem in bytes
+    pub total: u64,
+    /// The bytes that aren't used, including those reserved for root
+    pub free: u64,
+    /// The bytes that can be used by everyone
+    pub available: u64,
+}
+
+/// Add up the sizes of the files under a path. Symbolic links are
+/// counted as themselves, and the files they point to aren't. Files
+/// under the path that can't be read are given to `skip` and left out.
+pub fn disk_usage(
+    path: &Path,
+    skip: &mut dyn FnMut(&Path, std::io::Error),
+) -> std::io::Result<u64> {
+    let metadata = path.symlink_metadata()?;
+    let mut total = metadata.len();
+    if metadata.is_dir() {
+        for entry in read_dir(path)? {
+            let size = match entry {
+                Ok(entry) => {
+                    let path = entry.path();
+                    disk_usage(&path, skip).map_err(|e| (path, e))
+                }
+                Err(e) => Err((path.to_path_buf(), e)),
+            };
+            match size {
+                Ok(size) => total += size,
+                Err((path, e)) => skip(&path, e),
+            }
+        }
+    }
+    Ok(total)
+}
+
+/// Get the space on the filesystem that a path is on. The
+/// filesystem isn't named, and its mount is the path itself.
+#[cfg(unix)]
+pub fn disk_space(path: &Path) -> Result<DiskSpace, Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let error = |e: std::io::Error| Error::IoError(format!("df: {}: {}", path.display(), e));
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| error(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)))?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return Err(error(std::io::Error::last_os_error()));
+    }
+    let block_size = stats.f_frsize as u64;
+    Ok(DiskSpace {
+        filesystem: String::new(),
+        mount: path.to_path_buf(),
+        total: stats.f_blocks as u64 * block_size,
+        free: stats.f_bfree as u64 * block_size,
+        available: stats.f_bavail as u64 * block_size,
+    })
+}
+
+/// Get the space on the filesystem that a path is on
+#[cfg(not(unix))]
+pub fn disk_space(_path: &Path) -> Result<DiskSpace, Error> {
+    Err(Error::IoError(String::from(
+        "df is not supported on this platform",
+    )))
+}
+
+/// Get the space on the filesystem that a path is on, and find where
+/// it's mounted if the mounted filesystems can be listed
+pub fn disk_containing(path: &Path) -> Result<DiskSpace, Error> {
+    let path = path
+        .canonicalize()
+        .map_err(|e| Error::IoError(format!("df: {}: {}", path.display(), e)))?;
+    let mounted = disks()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|disk| path.starts_with(&disk.mount))
+        .max_by_key(|disk| disk.mount.components().count());
+    match mounted {
+        Some(disk) => Ok(disk),
+        None => disk_space(&path),
+    }
+}
+
+/// List the space on each mounted filesystem. Filesystems with no size,
+/// like `/proc`, only describe the system and are left out.
+#[cfg(target_os = "linux")]
+pub fn disks() -> Result<Vec<DiskSpace>, Error> {
+    let mounts = std::fs::read_to_string("/proc/self/mounts")
+        .map_err(|e| Error::IoError(format!("df: {}", e)))?;
+    let mut disks = vec![];
+    for line in mounts.lines() {
+        let mut fields = line.split_whitespace().map(unescape_mount);
+        let (filesystem, mount) = match (fields.next(), fields.next()) {
+            (Some(filesystem), Some(mount)) => (filesystem, mount),
+            _ => continue,
+        };
+        // A filesystem that can't be read is left out, like `df` does
+        match disk_space(Path::new(&mount)) {
+            Ok(space) if space.total > 0 => disks.push(DiskSpace {
+                filesystem,
+                ..space
+            }),
+            _ => {}
+        }
+    }
+    Ok(disks)
+}
+
+/// List the space on each mounted filesystem
+#[cfg(not(target_os = "linux"))]
+pub fn disks() -> Result<Vec<DiskSpace>, Error> {
+    Err(Error::IoError(String::from(
+        "df: listing filesystems is not supported on this platform, give a path instead",
+    )))
+}
+
+/// The names in the mount table write spaces and other
+/// whitespace as octal escapes, like `\040`
+#[cfg(target_os = "linux")]
+fn unescape_mount(field: &str) -> String {
+    let mut result = vec![];
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 4).and_then(|digits| {
+            std::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 8).ok())
+        });
+        match (bytes[i], escape) {
+            (b'\\', Some(byte)) => {
+                result.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                result.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&result).to_string()
+}
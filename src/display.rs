@@ -0,0 +1,212 @@
+use crate::color::paint;
+use std::collections::BTreeMap;
+use std::env::var;
+use xmachine::{Ref, Value};
+
+/// How far the items of a list or dictionary are indented
+/// when they don't fit on one line
+const INDENT: usize = 4;
+
+/// The number of characters that fit on a line of the terminal.
+/// If it can't be found, `COLUMNS` is used, and then 80.
+pub fn terminal_width() -> usize {
+    #[cfg(unix)]
+    {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+            && size.ws_col > 0
+        {
+            return size.ws_col as usize;
+        }
+    }
+    var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|columns| *columns > 0)
+        .unwrap_or(80)
+}
+
+/// Lay out pieces of text in as many columns as fit in a width, filling
+/// each column from top to bottom like `ls` does. Each piece is given
+/// with the number of characters it shows, since colored text contains
+/// codes that take up no space.
+pub fn columns(items: &[(String, usize)], width: usize) -> String {
+    const GAP: usize = 2;
+    if items.is_empty() {
+        return String::new();
+    }
+
+    // Use the fewest rows that let every column fit
+    let mut rows = 1;
+    let widths = loop {
+        let widths = items
+            .chunks(rows)
+            .map(|column| column.iter().map(|(_, len)| *len).max().unwrap_or(0))
+            .collect::<Vec<usize>>();
+        let total = widths.iter().sum::<usize>() + GAP * (widths.len() - 1);
+        if total <= width || rows >= items.len() {
+            break widths;
+        }
+        rows += 1;
+    };
+
+    let mut result = String::new();
+    for row in 0..rows {
+        let mut line = String::new();
+        for (column, column_width) in widths.iter().enumerate() {
+            if let Some((text, len)) = items.get(column * rows + row) {
+                line += text;
+                line += &" ".repeat(column_width - len + GAP);
+            }
+        }
+        result += line.trim_end();
+        result += "\n";
+    }
+    result
+}
+
+/// Show a value so that it is easy to read. Lists and dictionaries that
+/// don't fit in the width are spread over several lines and indented,
+/// and the strings inside of them are quoted. A string on its own is
+/// shown as it is, so that text can be printed.
+pub fn pretty(value: &Value, width: usize, color: bool) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        value => show(value, 0, 0, width, color),
+    }
+}
+
+/// Show a value that starts at a column of a line, where the line
+/// it starts on is indented by `indent`
+fn show(value: &Value, indent: usize, column: usize, width: usize, color: bool) -> String {
+    if column + inline(value, false).chars().count() <= width {
+        return inline(value, color);
+    }
+    let pad = " ".repeat(indent + INDENT);
+    match value {
+        Value::List(items) if !items.is_empty() => {
+            let mut result = String::from("[\n");
+            for item in items {
+                let item = show(item, indent + INDENT, indent + INDENT, width, color);
+                result += &format!("{}{},\n", pad, item);
+            }
+            result + &" ".repeat(indent) + "]"
+        }
+        Value::Tree(entries) if !entries.is_empty() => {
+            let mut result = String::from("{\n");
+            for (key, item) in entries {
+                let key = format!("{:?}: ", key);
+                let column = indent + INDENT + key.chars().count();
+                let item = show(item, indent + INDENT, column, width, color);
+                result += &format!("{}{}{},\n", pad, key, item);
+            }
+            result + &" ".repeat(indent) + "}"
+        }
+        value => inline(value, color),
+    }
+}
+
+/// Show a value on a single line
+fn inline(value: &Value, color: bool) -> String {
+    let codes: &[u8] = match value {
+        Value::String(_) => &[32],
+        Value::Number(_) => &[33],
+        Value::None | Value::Error(_) => &[2],
+        _ => &[],
+    };
+    let text = match value {
+        Value::String(text) => format!("{:?}", text),
+        Value::List(items) => {
+            let items = items
+                .iter()
+                .map(|item| inline(item, color))
+                .collect::<Vec<String>>();
+            return format!("[{}]", items.join(", "));
+        }
+        Value::Tree(entries) => {
+            let entries = entries
+                .iter()
+                .map(|(key, item)| format!("{:?}: {}", key, inline(item, color)))
+                .collect::<Vec<String>>();
+            return format!("{{{}}}", entries.join(", "));
+        }
+        value => value.to_string(),
+    };
+    if color {
+        paint(&text, codes)
+    } else {
+        text
+    }
+}
+
+/// Lay out a list of dictionaries as a table, with a column for each
+/// key and a header naming the columns. Numbers are aligned to the right.
+pub fn table(rows: &[BTreeMap<String, Ref<Value>>], color: bool) -> String {
+    let mut headers: Vec<&String> = vec![];
+    for row in rows {
+        for key in row.keys() {
+            if !headers.contains(&key) {
+                headers.push(key);
+            }
+        }
+    }
+    let cells = rows
+        .iter()
+        .map(|row| {
+            headers
+                .iter()
+                .map(|key| match row.get(*key).map(|value| &**value) {
+                    Some(Value::Number(n)) => (n.to_string(), true),
+                    Some(Value::None) | None => (String::new(), false),
+                    Some(value) => (value.to_string(), false),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let widths = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            cells
+                .iter()
+                .map(|row| row[i].0.chars().count())
+                .chain(Some(header.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<usize>>();
+
+    let mut result = String::new();
+    let line = |cells: Vec<String>| cells.join("  ").trim_end().to_string() + "\n";
+    result += &line(
+        headers
+            .iter()
+            .zip(&widths)
+            .map(|(header, width)| {
+                let padding = " ".repeat(width - header.chars().count());
+                if color {
+                    paint(header, &[1]) + &padding
+                } else {
+                    format!("{}{}", header, padding)
+                }
+            })
+            .collect(),
+    );
+    result += &line(widths.iter().map(|width| "-".repeat(*width)).collect());
+    for row in cells {
+        result += &line(
+            row.into_iter()
+                .zip(&widths)
+                .map(|((text, is_number), width)| {
+                    let padding = " ".repeat(width - text.chars().count());
+                    if is_number {
+                        padding + &text
+                    } else {
+                        text + &padding
+                    }
+                })
+                .collect(),
+        );
+    }
+    result
+}
@@ -0,0 +1,622 @@
+use crate::lexer::{tokenize, TokenKind};
+use crate::parser::parse_ast;
+use crate::tokens::{
+    Builtin, Error, Expr, FnCall, Function, FunctionDef, Identifier, Literal, Name, Pattern, Span,
+    Suite, Value, BUILTINS,
+};
+use std::collections::VecDeque;
+
+/// The indentation of each level of a block
+const INDENT: &str = "    ";
+
+/// How tightly a value binds, so that parentheses are only
+/// written where the value would be parsed differently without them
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    Or,
+    And,
+    Comparison,
+    Atom,
+}
+
+/// The comparison operators, and the builtins that they call
+const COMPARISONS: &[(&str, &str)] = &[
+    ("eq", "=="),
+    ("neq", "!="),
+    ("le", "<="),
+    ("ge", ">="),
+    ("lt", "<"),
+    ("gt", ">"),
+];
+
+/// Format the source code of a program with consistent indentation,
+/// spacing, and quoting. Comments and single blank lines between
+/// statements are kept, and each statement is put on its own line.
+pub fn format(source: &str) -> Result<String, Error> {
+    let Suite(exprs) = parse_ast(source)?;
+    // The parser throws comments away, so they are found separately
+    let tokens = tokenize(source);
+    let mut formatter = Formatter {
+        source,
+        comments: tokens
+            .iter()
+            .filter(|(kind, _)| *kind == TokenKind::Comment)
+            .map(|(_, span)| (span.start, &source[span.start..span.end]))
+            .collect(),
+        braces: tokens
+            .iter()
+            .filter(|(kind, span)| {
+                *kind == TokenKind::Punctuation && &source[span.start..span.end] == "}"
+            })
+            .map(|(_, span)| span.start)
+            .collect(),
+        out: String::new(),
+        depth: 0,
+        pos: 0,
+        fresh: true,
+    };
+    for expr in &exprs {
+        formatter.statement(expr);
+    }
+    formatter.comments_before(source.len());
+    if !formatter.out.is_empty() {
+        formatter.out.push('\n');
+    }
+    Ok(formatter.out)
+}
+
+/// Write a string as a string literal
+fn quote(text: &str) -> String {
+    let mut result = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            '\r' => result += "\\r",
+            '\t' => result += "\\t",
+            '\0' => result += "\\0",
+            ch if ch.is_control() => result += &format!("\\u{{{:x}}}", ch as u32),
+            ch => result.push(ch),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Write a word given to a program, which is only
+/// quoted if it wouldn't be one word without quotes
+fn command_word(word: &str) -> String {
+    let needs_quotes = word.is_empty()
+        || word.starts_with(['#', '"'])
+        || word.contains(|ch: char| ch.is_whitespace() || ch == ';' || ch == '}');
+    if needs_quotes {
+        quote(word)
+    } else {
+        word.to_string()
+    }
+}
+
+/// The comparison operator that a function call is written with, if any
+fn comparison_op(FnCall(function, args): &FnCall) -> Option<&'static str> {
+    match unspanned(function) {
+        Value::Name(Name::Name(Identifier(name))) if args.len() == 2 => COMPARISONS
+            .iter()
+            .find(|(builtin, _)| *builtin == name.as_str())
+            .map(|(_, op)| *op),
+        _ => None,
+    }
+}
+
+/// A value without the spans around it
+fn unspanned(mut value: &Value) -> &Value {
+    while let Value::Spanned(_, inner) = value {
+        value = inner;
+    }
+    value
+}
+
+fn precedence(value: &Value) -> Precedence {
+    match value {
+        Value::Spanned(_, value) => precedence(value),
+        Value::Or(_, _) => Precedence::Or,
+        Value::And(_, _) => Precedence::And,
+        Value::FnCall(call) if comparison_op(call).is_some() => Precedence::Comparison,
+        _ => Precedence::Atom,
+    }
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    /// The comments that haven't been written yet
+    comments: VecDeque<(usize, &'a str)>,
+    /// Where each closing brace is in the source
+    braces: Vec<usize>,
+    out: String,
+    /// How many blocks deep the code being written is
+    depth: usize,
+    /// Where in the source the last thing written ends
+    pos: usize,
+    /// Whether nothing has been written in the current block yet
+    fresh: bool,
+}
+
+impl Formatter<'_> {
+    /// Start a new line, keeping a blank line before
+    /// the code at `at` if there was one in the source
+    fn new_line(&mut self, at: usize) {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+            let between = self.source.get(self.pos..at).unwrap_or("");
+            if !self.fresh && between.matches('\n').count() > 1 {
+                self.out.push('\n');
+            }
+        }
+        self.out += &INDENT.repeat(self.depth);
+        self.fresh = false;
+    }
+
+    /// Write the comments that come before a place in the source.
+    /// A comment at the end of a line of code stays on that line.
+    fn comments_before(&mut self, limit: usize) {
+        while let Some(&(at, text)) = self.comments.front() {
+            if at >= limit {
+                break;
+            }
+            self.comments.pop_front();
+            let same_line = !self.fresh
+                && !self.out.is_empty()
+                && self
+                    .source
+                    .get(self.pos..at)
+                    .is_some_and(|between| !between.contains('\n'));
+            if same_line {
+                self.out.push(' ');
+            } else {
+                self.new_line(at);
+            }
+            self.out += text;
+            self.pos = self.pos.max(at + text.len());
+        }
+    }
+
+    fn statement(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Spanned(span, expr) => {
+                self.comments_before(span.start);
+                self.new_line(span.start);
+                self.pos = span.start;
+                self.expr(expr, span.end);
+                self.pos = span.end;
+                // The command of an alias is the rest of its line,
+                // even if it looks like it has a comment in it
+                if let Expr::Alias(_, _) = **expr {
+                    while self.comments.front().is_some_and(|(at, _)| *at < span.end) {
+                        self.comments.pop_front();
+                    }
+                }
+            }
+            expr => {
+                self.new_line(self.pos);
+                self.expr(expr, self.pos);
+            }
+        }
+    }
+
+    /// Find the `}` that closes the block being written, which
+    /// is the first one after `from` that isn't in a comment
+    fn closing_brace(&self, from: usize, end: usize) -> usize {
+        self.braces
+            .iter()
+            .copied()
+            .find(|brace| (from..end).contains(brace))
+            .unwrap_or(end)
+    }
+
+    /// Write a block of statements. The comments that are after
+    /// every statement but before the block closes go at its end.
+    fn suite(&mut self, Suite(exprs): &Suite, end: usize) {
+        let close = self.closing_brace(self.pos, end);
+        let has_comments = self.comments.front().is_some_and(|(at, _)| *at < close);
+        if exprs.is_empty() && !has_comments {
+            self.out += "{}";
+            self.pos = end.min(close + 1);
+            return;
+        }
+
+        self.out.push('{');
+        self.depth += 1;
+        self.fresh = true;
+        for expr in exprs {
+            self.statement(expr);
+        }
+        let close = self.closing_brace(self.pos, end);
+        self.comments_before(close);
+        self.depth -= 1;
+        self.out.push('\n');
+        self.out += &INDENT.repeat(self.depth);
+        self.out.push('}');
+        self.pos = end.min(close + 1);
+    }
+
+    fn expr(&mut self, expr: &Expr, end: usize) {
+        match expr {
+            Expr::Assignment(name, value) => {
+                self.name(name);
+                self.out += " = ";
+                self.value(value, Precedence::Or, end);
+            }
+            Expr::WhileLoop(condition, body, _) => {
+                self.out += "while ";
+                self.value(condition, Precedence::Or, end);
+                self.out.push(' ');
+                self.suite(body, end);
+            }
+            Expr::ForLoop(Identifier(name), list, body, _) => {
+                self.out += &format!("for {} in ", name);
+                self.value(list, Precedence::Atom, end);
+                self.out.push(' ');
+                self.suite(body, end);
+            }
+            Expr::IfThenElse(condition, then_body, else_body) => {
+                self.out += "if ";
+                self.value(condition, Precedence::Or, end);
+                self.out.push(' ');
+                self.suite(then_body, end);
+                if !else_body.0.is_empty() {
+                    self.out += " else ";
+                    self.suite(else_body, end);
+                }
+            }
+            Expr::Match(value, arms) => {
+                self.out += "match ";
+                self.value(value, Precedence::Or, end);
+                self.out += " {";
+                self.depth += 1;
+                self.fresh = true;
+                for (pattern, body) in arms {
+                    self.new_line(self.pos);
+                    self.pattern(pattern);
+                    self.out += " => ";
+                    self.suite(body, end);
+                }
+                self.depth -= 1;
+                self.out.push('\n');
+                self.out += &INDENT.repeat(self.depth);
+                self.out.push('}');
+            }
+            Expr::Import(path, name) => {
+                self.out += &format!("import {}", quote(path));
+                if let Some(Identifier(name)) = name {
+                    self.out += &format!(" as {}", name);
+                }
+            }
+            Expr::Alias(Identifier(name), command) => {
+                self.out += &format!("alias {} = {}", name, command)
+            }
+            Expr::Unalias(Identifier(name)) => self.out += &format!("unalias {}", name),
+            Expr::Command(Identifier(name), args) => {
+                self.out += name;
+                for arg in args {
+                    self.out.push(' ');
+                    self.out += &command_word(arg);
+                }
+            }
+            Expr::FunctionDef(FunctionDef(name, function)) => {
+                self.out += "fn ";
+                self.name(name);
+                self.function(function, end);
+            }
+            Expr::Return(value) => {
+                self.out += "return ";
+                self.value(value, Precedence::Or, end);
+            }
+            Expr::Break => self.out += "break",
+            Expr::Continue => self.out += "continue",
+            Expr::Value(value) => {
+                if !self.builtin_command(value) {
+                    self.value(value, Precedence::Or, end)
+                }
+            }
+            Expr::Silent(value) => {
+                if !self.builtin_command(value) {
+                    self.value(value, Precedence::Or, end)
+                }
+                self.out.push(';');
+            }
+            Expr::Spanned(_, expr) => self.expr(expr, end),
+        }
+    }
+
+    /// Write a call to a builtin with simple arguments like a command,
+    /// as in `cd dir` or `echo "hello"`, if it is one
+    fn builtin_command(&mut self, value: &Value) -> bool {
+        let (builtin, args) = match unspanned(value) {
+            Value::FnCall(FnCall(function, args)) => match unspanned(function) {
+                Value::Builtin(builtin) if !args.is_empty() => (builtin, args),
+                _ => return false,
+            },
+            _ => return false,
+        };
+        let is_simple = |arg: &Value| match unspanned(arg) {
+            Value::Literal(_) | Value::Name(Name::Name(_)) => true,
+            Value::Name(Name::DotName(head, _)) => {
+                matches!(unspanned(head), Value::Name(Name::Name(_)))
+            }
+            _ => false,
+        };
+        if !args.iter().all(is_simple) {
+            return false;
+        }
+
+        self.value(&Value::Builtin(builtin.clone()), Precedence::Atom, self.pos);
+        if *builtin == Builtin::ChangeDir
+            && matches!(args.as_slice(), [arg] if *unspanned(arg) == Value::Literal(Literal::String(String::from("-"))))
+        {
+            self.out += " -";
+            return true;
+        }
+        for arg in args {
+            self.out.push(' ');
+            self.value(arg, Precedence::Atom, self.pos);
+        }
+        true
+    }
+
+    /// Write the parameters and body of a function
+    fn function(&mut self, Function(params, body): &Function, end: usize) {
+        self.out += &format!("({}) ", parameters(params));
+        self.suite(body, end);
+    }
+
+    /// A function literal that was written on one line, like
+    /// `fn(x) { return x }`, stays on one line if its body
+    /// is a single statement without a block of its own
+    fn is_one_line(&self, Function(_, body): &Function, span: Span) -> bool {
+        let Suite(body) = &**body;
+        let is_simple = match body.as_slice() {
+            [Expr::Spanned(_, expr)] => !matches!(
+                **expr,
+                Expr::WhileLoop(..)
+                    | Expr::ForLoop(..)
+                    | Expr::IfThenElse(..)
+                    | Expr::Match(..)
+                    | Expr::FunctionDef(..)
+            ),
+            _ => false,
+        };
+        is_simple
+            && self
+                .source
+                .get(span.start..span.end)
+                .is_some_and(|source| !source.contains('\n'))
+            && !self
+                .comments
+                .iter()
+                .any(|(at, _)| (span.start..span.end).contains(at))
+    }
+
+    /// Write a value, in parentheses if it binds more loosely than `min`
+    fn value(&mut self, value: &Value, min: Precedence, end: usize) {
+        if precedence(value) < min {
+            self.out.push('(');
+            self.value(value, Precedence::Or, end);
+            self.out.push(')');
+            return;
+        }
+
+        match value {
+            Value::Name(name) => self.name(name),
+            Value::Literal(literal) => self.out += &literal_source(literal),
+            Value::FnCall(call) => {
+                let FnCall(function, args) = call;
+                if let Some(op) = comparison_op(call) {
+                    self.value(&args[0], Precedence::Atom, end);
+                    self.out += &format!(" {} ", op);
+                    self.value(&args[1], Precedence::Atom, end);
+                    return;
+                }
+                self.callee(function, end);
+                self.out.push('(');
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.out += ", ";
+                    }
+                    self.value(arg, Precedence::Or, end);
+                }
+                self.out.push(')');
+            }
+            Value::Builtin(builtin) => {
+                if let Some((name, _)) = BUILTINS.iter().find(|(_, b)| b == builtin) {
+                    self.out += name;
+                }
+            }
+            Value::Function(function) => {
+                self.out += "fn";
+                self.function(function, end);
+            }
+            Value::And(lhs, rhs) => {
+                self.value(lhs, Precedence::And, end);
+                self.out += " && ";
+                self.value(rhs, Precedence::Comparison, end);
+            }
+            Value::Or(lhs, rhs) => {
+                self.value(lhs, Precedence::Or, end);
+                self.out += " || ";
+                self.value(rhs, Precedence::And, end);
+            }
+            Value::Spanned(span, value) => match &**value {
+                Value::Function(function @ Function(params, body))
+                    if self.is_one_line(function, *span) =>
+                {
+                    self.out += &format!("fn({}) {{ ", parameters(params));
+                    self.expr(&body.0[0], span.end);
+                    self.out += " }";
+                }
+                value => self.value(value, min, span.end),
+            },
+        }
+    }
+
+    /// Write the function being called. Only names and
+    /// builtins can be called without parentheses around them.
+    fn callee(&mut self, function: &Value, end: usize) {
+        match unspanned(function) {
+            Value::Name(_) | Value::Builtin(_) => self.value(function, Precedence::Atom, end),
+            _ => {
+                self.out.push('(');
+                self.value(function, Precedence::Or, end);
+                self.out.push(')');
+            }
+        }
+    }
+
+    fn name(&mut self, name: &Name) {
+        match name {
+            Name::Name(Identifier(name)) => self.out += name,
+            Name::DotName(head, identifiers) => {
+                self.name_head(head);
+                for Identifier(name) in identifiers {
+                    self.out.push('.');
+                    self.out += name;
+                }
+            }
+            Name::IndexName(head, indices) => {
+                self.name_head(head);
+                for index in indices {
+                    self.out.push('[');
+                    self.value(index, Precedence::Or, self.pos);
+                    self.out.push(']');
+                }
+            }
+        }
+    }
+
+    /// Write the value that a dotted or indexed name starts with. Only
+    /// identifiers and strings can start one without parentheses.
+    fn name_head(&mut self, head: &Value) {
+        match unspanned(head) {
+            Value::Name(Name::Name(_)) | Value::Literal(Literal::String(_)) => {
+                self.value(head, Precedence::Atom, self.pos)
+            }
+            _ => {
+                self.out.push('(');
+                self.value(head, Precedence::Or, self.pos);
+                self.out.push(')');
+            }
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Literal(literal) => self.out += &literal_source(literal),
+            Pattern::List(patterns) => {
+                self.out.push('[');
+                for (i, pattern) in patterns.iter().enumerate() {
+                    if i > 0 {
+                        self.out += ", ";
+                    }
+                    self.pattern(pattern);
+                }
+                self.out.push(']');
+            }
+            Pattern::Wildcard => self.out.push('_'),
+        }
+    }
+}
+
+/// Write the parameters of a function, separated by commas
+fn parameters(params: &[Identifier]) -> String {
+    params
+        .iter()
+        .map(|Identifier(param)| param.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Write a literal the way it's written in source code
+fn literal_source(literal: &Literal) -> String {
+    match literal {
+        Literal::String(text) => quote(text),
+        Literal::Number(n) => n.to_string(),
+        Literal::Bool(b) => b.to_string(),
+        Literal::None => String::from("none"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+    use crate::parser::parse_ast;
+    use crate::tokens::{Expr, Suite, Value};
+    use crate::visit::{walk_expr_mut, walk_value_mut, VisitMut};
+
+    /// Takes out where each part of a program is, which
+    /// changes when the program is formatted
+    struct RemoveSpans;
+
+    impl VisitMut for RemoveSpans {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            if let Expr::Spanned(_, inner) = expr {
+                *expr = (**inner).clone();
+                return self.visit_expr_mut(expr);
+            }
+            walk_expr_mut(self, expr);
+        }
+
+        fn visit_value_mut(&mut self, value: &mut Value) {
+            if let Value::Spanned(_, inner) = value {
+                *value = (**inner).clone();
+                return self.visit_value_mut(value);
+            }
+            walk_value_mut(self, value);
+        }
+    }
+
+    fn syntax_tree(source: &str) -> Suite {
+        let mut suite = parse_ast(source).expect("the program should parse");
+        RemoveSpans.visit_suite_mut(&mut suite);
+        suite
+    }
+
+    #[test]
+    fn formatting_keeps_what_the_program_means() {
+        let source = r#"# Count the words
+fn count(  items ) {
+  total=0
+    for item in items { if item=="skip" {continue}
+  total = add(total,1) }
+  return total
+}
+
+match count(list.range(0,3)) { 3 => { println("three") } _ => {} }
+git status --short
+x = "a b";
+"#;
+        let expected = r#"# Count the words
+fn count(items) {
+    total = 0
+    for item in items {
+        if item == "skip" {
+            continue
+        }
+        total = add(total, 1)
+    }
+    return total
+}
+
+match count(list.range(0, 3)) {
+    3 => {
+        println("three")
+    }
+    _ => {}
+}
+git status --short
+x = "a b"
+"#;
+        let formatted = format(source).expect("the program should be formatted");
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).ok().as_deref(), Some(expected));
+        assert_eq!(syntax_tree(&formatted), syntax_tree(source));
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The directories that have been visited, with how many times each was
+/// visited and when it was last visited, in seconds since the Unix epoch
+pub type Visits = BTreeMap<String, (f64, f64)>;
+
+/// When the visits to every directory add up to more than this, they are
+/// all made smaller, so that directories that aren't visited anymore are
+/// eventually forgotten
+const MAX_TOTAL_VISITS: f64 = 9000.0;
+
+/// Count a visit to a directory
+pub fn record_visit(visits: &mut Visits, dir: &str, now: f64) {
+    let entry = visits.entry(dir.to_string()).or_insert((0.0, now));
+    entry.0 += 1.0;
+    entry.1 = now;
+
+    let total = visits.values().map(|(count, _)| count).sum::<f64>();
+    if total > MAX_TOTAL_VISITS {
+        for (count, _) in visits.values_mut() {
+            *count *= 0.99;
+        }
+        visits.retain(|_, (count, _)| *count >= 1.0);
+    }
+}
+
+/// Score a directory by how often and how recently it was visited,
+/// so that a directory visited a lot last year loses to one that
+/// is visited a few times every day
+pub fn frecency(count: f64, last: f64, now: f64) -> f64 {
+    let age = now - last;
+    let weight = if age < 60.0 * 60.0 {
+        4.0
+    } else if age < 60.0 * 60.0 * 24.0 {
+        2.0
+    } else if age < 60.0 * 60.0 * 24.0 * 7.0 {
+        0.5
+    } else {
+        0.25
+    };
+    count * weight
+}
+
+/// Does a directory match the words of a query? Each word must be in the
+/// path, in the same order as the query, ignoring case. The last word must
+/// be in the directory's own name, so `proj` finds `~/code/my-project`
+/// rather than everything inside of it.
+pub fn matches_query(dir: &str, words: &[String]) -> bool {
+    let dir = dir.to_lowercase();
+    let name_start = dir.rfind(['/', '\\']).map(|i| i + 1).unwrap_or(0);
+    let mut rest = 0;
+    for (i, word) in words.iter().enumerate() {
+        let word = word.to_lowercase();
+        let start = if i + 1 == words.len() {
+            name_start.max(rest)
+        } else {
+            rest
+        };
+        match dir[start..].find(&word) {
+            Some(at) => rest = start + at + word.len(),
+            None => return false,
+        }
+    }
+    true
+}
+
+/// List the directories that match a query, best first. The
+/// directories that no longer exist are left out.
+pub fn ranked_matches(visits: &Visits, words: &[String], now: f64) -> Vec<(PathBuf, f64)> {
+    let mut matches = visits
+        .iter()
+        .filter(|(dir, _)| matches_query(dir, words))
+        .map(|(dir, (count, last))| (PathBuf::from(dir), frecency(*count, *last, now)))
+        .filter(|(dir, _)| dir.is_dir())
+        .collect::<Vec<_>>();
+    matches.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    matches
+}
@@ -0,0 +1,44 @@
+use crate::tokens::Error;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+
+/// The names of the hashing algorithms that can be used
+pub const ALGORITHMS: &[&str] = &["sha256", "md5"];
+
+/// Hash some data with an algorithm, and give the hash in hexadecimal
+pub fn hash_bytes(algorithm: &str, data: &[u8]) -> Result<String, Error> {
+    hash_reader(algorithm, data)
+}
+
+/// Hash everything that can be read from a reader, such as a file,
+/// without reading it all into memory at once
+pub fn hash_reader(algorithm: &str, reader: impl Read) -> Result<String, Error> {
+    match algorithm {
+        "sha256" => digest::<Sha256>(reader),
+        "md5" => digest::<Md5>(reader),
+        _ => Err(Error::TypeError(format!(
+            "unknown hash algorithm {}, expected one of {}",
+            algorithm,
+            ALGORITHMS.join(", ")
+        ))),
+    }
+}
+
+fn digest<D: Digest>(mut reader: impl Read) -> Result<String, Error> {
+    let mut hasher = D::new();
+    let mut buffer = [0; 8192];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => hasher.update(&buffer[..count]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Error::IoError(e.to_string())),
+        }
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
@@ -0,0 +1,436 @@
+use crate::tokens::{most_similar, Error};
+
+/// The documentation for a builtin, shown by `help`
+pub struct Help {
+    /// How the builtin is called, like `ls(dir?)`. Arguments
+    /// that can be left out end with a question mark.
+    pub usage: &'static str,
+    /// Other names for the same builtin
+    pub aliases: &'static [&'static str],
+    pub description: &'static str,
+}
+
+impl Help {
+    /// The name that the builtin is called by
+    pub fn name(&self) -> &'static str {
+        let end = self.usage.find(['(', ' ']).unwrap_or(self.usage.len());
+        &self.usage[..end]
+    }
+
+    /// Is this the builtin with a name, or one of its aliases?
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name() == name || self.aliases.contains(&name)
+    }
+
+    /// Show the usage of the builtin with its description
+    pub fn show(&self) -> String {
+        let mut result = format!("{}\n    {}\n", self.usage, self.description);
+        if !self.aliases.is_empty() {
+            result += &format!("    Also called {}.\n", self.aliases.join(", "));
+        }
+        result
+    }
+}
+
+const fn help(usage: &'static str, description: &'static str) -> Help {
+    Help {
+        usage,
+        aliases: &[],
+        description,
+    }
+}
+
+const fn aliased(
+    usage: &'static str,
+    aliases: &'static [&'static str],
+    description: &'static str,
+) -> Help {
+    Help {
+        usage,
+        aliases,
+        description,
+    }
+}
+
+/// The documentation for every builtin, in the groups that `help` lists them in
+pub const HELP: &[(&str, &[Help])] = &[
+    (
+        "Files",
+        &[
+            aliased("ls(dir?)", &["dir"], "List the names of the files in a directory."),
+            help("ls_each(dir, function)", "Call a function with the name of each file in a directory as it's read. The function can return `false` to stop."),
+            help("lsl(dir?)", "List the files in a directory as dictionaries of their name, size, modification time, and permissions."),
+            help("stat(path)", "Describe a file with a dictionary of its size, type, times, permissions, and owner."),
+            aliased("walk(dir?, depth?)", &["tree"], "List the paths under a directory, at most `depth` levels down."),
+            help("find(dir, predicate)", "List the paths under a directory whose descriptions the predicate function is true for."),
+            help("grep(pattern, file_or_text)", "List the lines of a file or string that contain a pattern."),
+            help("cat(path)", "Read a file as a string."),
+            help("head(file_or_text, n?)", "Get the first `n` lines of a file or string, 10 if `n` is left out."),
+            help("tail(file_or_text, n?)", "Get the last `n` lines of a file or string, 10 if `n` is left out."),
+            help("count_lines(file_or_text)", "Count the lines of a file or string."),
+            help("csv_read(path)", "Read a CSV file as a list of dictionaries, one for each row."),
+            help("csv_write(path, rows)", "Write a list of dictionaries to a CSV file."),
+            help("cp(src, dest)", "Copy a file or directory."),
+            help("mv(old, new)", "Move or rename a file or directory."),
+            help("rm(path, force?)", "Remove a file or directory, asking first if a directory isn't empty. Scripts need `force` to remove one. With `RM_TRASH` set, it's moved to the trash."),
+            help("restore(path)", "Bring back a file that `rm` moved to the trash."),
+            help("mkdir(path)", "Make a directory, along with the directories it's in."),
+            aliased("mkf(path)", &["touch"], "Make an empty file."),
+            help("symlink(target, link)", "Make a symbolic link that points to `target`."),
+            help("readlink(path)", "Get the path that a symbolic link points to."),
+            help("is_symlink(path)", "Check if a path is a symbolic link."),
+            help("realpath(path)", "Get the absolute path of a file, with its symbolic links followed."),
+            help("chmod(path, mode)", "Change the permissions of a file, with a mode like `755` or `u+x`."),
+            help("chown(path, owner)", "Change the owner of a file, given as `user` or `user:group`."),
+            help("du(path?)", "Add up the sizes of the files under a path, in bytes."),
+            help("df(path?)", "Describe the space on each filesystem, or on the one that a path is on."),
+            help("sha256(file_or_text)", "Hash a file or string with SHA-256."),
+            help("md5(file_or_text)", "Hash a file or string with MD5."),
+            help("hash_file(path, algorithm?)", "Hash a file with `sha256` or `md5`, which is `sha256` if it's left out."),
+            help("zip(src, dest)", "Compress a file or directory into a zip archive."),
+            help("unzip(archive, dest?)", "Extract a zip archive, into the current directory if `dest` is left out."),
+            help("tar(src, dest)", "Put a file or directory into a tar archive, which is compressed if it ends in `.gz`."),
+            help("untar(archive, dest?)", "Extract a tar archive, into the current directory if `dest` is left out."),
+        ],
+    ),
+    (
+        "Directories",
+        &[
+            help("cd(dir?)", "Change the current directory, to the home directory if `dir` is left out, or to the last directory if it's `-`."),
+            help("try_cd(dir?)", "Like `cd`, but give whether the directory was changed instead of failing."),
+            aliased("cwd", &["pwd"], "Get the current directory."),
+            help("pushd(dir?)", "Save the current directory on the directory stack and change to another directory."),
+            help("popd", "Change to the directory on top of the directory stack."),
+            help("dirs", "List the directories on the directory stack."),
+            help("bookmark(name)", "Save the current directory under a name."),
+            help("goto(name)", "Change to a directory saved with `bookmark`."),
+            help("bookmarks", "Get a dictionary of the saved bookmarks."),
+            help("z(words...)", "Change to the visited directory that best matches the words, favoring the ones visited often and recently."),
+        ],
+    ),
+    (
+        "Programs",
+        &[
+            aliased("sh(command)", &["cmd"], "Run a program, given as a string to split into words or as several words."),
+            help("spawn(command)", "Start a program in the background, and get a dictionary with its `pid` and the functions `wait`, `kill`, and `stdout`."),
+            help("timeout(seconds, command_or_function)", "Run a program or call a function, stopping it if it takes longer than `seconds`."),
+            help("ps", "List the running processes as dictionaries of their `pid`, `name`, `cpu`, and `mem`."),
+            help("kill(pid, signal?)", "Send a signal to a process, which is `TERM` if it's left out."),
+            help("env()", "Get a dictionary of the environment variables."),
+            help("getenv(name)", "Get the value of an environment variable."),
+            help("setenv(name, value)", "Set an environment variable."),
+            help("unsetenv(name)", "Remove an environment variable."),
+        ],
+    ),
+    (
+        "Input and output",
+        &[
+            help("print(value)", "Write a value without a newline."),
+            help("println(value)", "Write a value with a newline."),
+            help("input(prompt?)", "Read a line that the user types."),
+            help("select(choices)", "Let the user pick from a list of choices, and get the choice they picked."),
+            help("confirm(question)", "Ask the user a yes or no question."),
+            help("password(prompt?)", "Read a line that the user types without showing it."),
+            help("table(rows)", "Write a list of dictionaries as a table."),
+            help("table_string(rows)", "Lay out a list of dictionaries as a table in a string."),
+            help("black(text)", "Color text black."),
+            help("red(text)", "Color text red."),
+            help("green(text)", "Color text green."),
+            help("yellow(text)", "Color text yellow."),
+            help("blue(text)", "Color text blue."),
+            help("magenta(text)", "Color text magenta."),
+            help("cyan(text)", "Color text cyan."),
+            help("white(text)", "Color text white."),
+            help("bold(text)", "Make text bold."),
+            help("dim(text)", "Make text dim."),
+            help("italic(text)", "Make text italic."),
+            help("underline(text)", "Make text underlined."),
+            help("blink(text)", "Make text blink."),
+            help("reverse(text)", "Make text have its colors reversed."),
+            help("strikethrough(text)", "Make text struck through."),
+            help("style(text, options)", "Color and style text with a dictionary like `fg`, `bg`, and `bold`."),
+            aliased("clear", &["cls"], "Clear the screen."),
+            help("help(topic?)", "List the builtins, or describe a builtin or the builtins in a group or namespace."),
+            help("info()", "Show information about the shell and its state."),
+            help("debug()", "Show the state of the machine that runs the shell."),
+            help("logo()", "Show the logo of the shell."),
+        ],
+    ),
+    (
+        "Values",
+        &[
+            help("dict()", "Make an empty dictionary."),
+            help("keys(dict)", "List the keys of a dictionary."),
+            help("values(dict)", "List the values of a dictionary."),
+            help("has_key(dict, key)", "Check if a dictionary has a key."),
+            help("remove_key(dict, key)", "Make a copy of a dictionary without a key."),
+            help("merge(dict, other)", "Make a dictionary with the keys of both, favoring `other`."),
+            help("is_none(value)", "Check if a value is none."),
+            help("map(list, function)", "Call a function on each item of a list."),
+            help("not(value)", "Check if a value is false."),
+            help("eq(a, b)", "Check if two values are equal."),
+            help("neq(a, b)", "Check if two values are different."),
+            help("gt(a, b)", "Check if a number is greater than another."),
+            help("lt(a, b)", "Check if a number is less than another."),
+            help("ge(a, b)", "Check if a number is greater than or equal to another."),
+            help("le(a, b)", "Check if a number is less than or equal to another."),
+            help("add(a, b)", "Add numbers, or join strings or lists."),
+            help("sub(a, b)", "Subtract a number from another."),
+            help("mul(a, b)", "Multiply two numbers."),
+            help("div(a, b)", "Divide a number by another."),
+            help("rem(a, b)", "Get the remainder of dividing a number by another."),
+            help("exists(name)", "Check if a variable is defined, including keys like `config.path`."),
+            help("eval(source)", "Run dune code in a string."),
+            help("json_parse(text)", "Read a value from JSON."),
+            help("json_string(value)", "Write a value as JSON."),
+            help("toml_parse(text)", "Read a value from TOML."),
+            help("toml_string(value)", "Write a value as TOML."),
+            help("yaml_parse(text)", "Read a value from YAML."),
+            help("yaml_string(value)", "Write a value as YAML."),
+        ],
+    ),
+    (
+        "Text",
+        &[
+            help("re_match(pattern, text)", "Check if a regular expression matches text."),
+            help("re_find_all(pattern, text)", "List the matches of a regular expression, or their groups if it has any."),
+            help("re_replace(pattern, text, replacement)", "Replace the matches of a regular expression."),
+            help("str.split(text, separator?)", "Split a string, on whitespace if `separator` is left out."),
+            help("str.join(list, separator)", "Join a list of strings with a separator."),
+            help("str.trim(text)", "Remove the whitespace around a string."),
+            help("str.upper(text)", "Make a string uppercase."),
+            help("str.lower(text)", "Make a string lowercase."),
+            help("str.replace(text, from, to)", "Replace every `from` in a string with `to`."),
+            help("str.contains(text, pattern)", "Check if a string contains another."),
+            help("str.starts_with(text, prefix)", "Check if a string starts with another."),
+            help("str.ends_with(text, suffix)", "Check if a string ends with another."),
+            help("str.len(text)", "Count the characters in a string."),
+            help("str.substr(text, start, end?)", "Get the characters of a string from `start` up to `end`."),
+            help("str.capitalize(text)", "Make the first letter of a string uppercase."),
+            help("str.repeat(text, n)", "Repeat a string a number of times."),
+            help("str.pad_left(text, width)", "Pad the start of a string with spaces to a width."),
+            help("str.pad_right(text, width)", "Pad the end of a string with spaces to a width."),
+            help("str.lines(text)", "Split a string into its lines."),
+            help("str.words(text)", "Split a string into its words."),
+        ],
+    ),
+    (
+        "Lists",
+        &[
+            help("list.len(list)", "Count the items in a list."),
+            help("list.push(list, item)", "Make a copy of a list with an item added to the end."),
+            help("list.pop(list)", "Make a copy of a list without its last item."),
+            help("list.get(list, index)", "Get an item of a list. Negative indices count from the end."),
+            help("list.set(list, index, item)", "Make a copy of a list with an item replaced."),
+            help("list.sort(list)", "Sort a list."),
+            help("list.reverse(list)", "Reverse a list."),
+            help("list.filter(list, predicate)", "List the items that the predicate function is true for."),
+            help("list.reduce(list, function, initial)", "Combine the items of a list with a function."),
+            help("list.contains(list, item)", "Check if a list contains an item."),
+            help("list.slice(list, start, end?)", "Get the items of a list from `start` up to `end`."),
+            help("list.range(start, end)", "List the numbers from `start` up to `end`."),
+            help("list.map(list, function)", "Make a list of the results of calling a function on each item."),
+            help("list.each(list, function)", "Call a function on each item of a list."),
+            help("list.first(list)", "Get the first item of a list."),
+            help("list.last(list)", "Get the last item of a list."),
+            help("list.sum(list)", "Add up the numbers in a list."),
+            help("shuffle(list)", "Put a list in a random order."),
+            help("choice(list)", "Pick a random item from a list."),
+        ],
+    ),
+    (
+        "Math",
+        &[
+            help("math.sqrt(x)", "Get the square root of a number."),
+            help("math.pow(x, y)", "Raise a number to a power."),
+            help("math.abs(x)", "Get the absolute value of a number."),
+            help("math.floor(x)", "Round a number down."),
+            help("math.ceil(x)", "Round a number up."),
+            help("math.round(x)", "Round a number to the nearest whole number."),
+            help("math.min(a, b)", "Get the smaller of two numbers."),
+            help("math.max(a, b)", "Get the larger of two numbers."),
+            help("math.log(x, base?)", "Get the logarithm of a number, which is the natural logarithm if `base` is left out."),
+            help("math.sin(x)", "Get the sine of an angle in radians."),
+            help("math.cos(x)", "Get the cosine of an angle in radians."),
+            help("math.tan(x)", "Get the tangent of an angle in radians."),
+            help("math.asin(x)", "Get the angle in radians with a sine."),
+            help("math.acos(x)", "Get the angle in radians with a cosine."),
+            help("math.atan(x)", "Get the angle in radians with a tangent."),
+            help("pi", "The ratio of a circle's circumference to its diameter."),
+            help("e", "The base of the natural logarithm."),
+            help("random()", "Get a random number from 0 up to 1."),
+            help("random_int(lo, hi)", "Get a random whole number from `lo` to `hi`."),
+            help("uuid()", "Make a random UUID."),
+        ],
+    ),
+    (
+        "Paths",
+        &[
+            help("path.join(path, parts...)", "Join paths, starting over at any absolute path."),
+            help("path.parent(path)", "Get the directory that a path is in."),
+            help("path.filename(path)", "Get the last part of a path."),
+            help("path.extension(path)", "Get the extension of a path, without the dot."),
+            help("path.stem(path)", "Get the last part of a path without its extension."),
+            help("path.is_absolute(path)", "Check if a path is absolute."),
+            help("path.exists(path)", "Check if a file exists."),
+            help("basename(path)", "Get the last part of a path."),
+            help("dirname(path)", "Get the directory that a path is in."),
+            help("join_path(a, b)", "Join two paths."),
+        ],
+    ),
+    (
+        "Shell",
+        &[
+            help(
+                "plugin(path)",
+                "Load a WebAssembly plugin, and get a dictionary of the builtins it exports.",
+            ),
+            help(
+                "profile(function)",
+                "Call a function, and show how long the functions it called took.",
+            ),
+            help(
+                "set_strict(on)",
+                "Stop scripts when a program fails, instead of going on. The status that the last program exited with is always kept in `status`.",
+            ),
+            aliased(
+                "exit(status)",
+                &["quit", "bye"],
+                "Leave the shell, with a status of 0 unless one is given.",
+            ),
+        ],
+    ),
+];
+
+/// Find the documentation for a builtin by its name or one of its aliases
+pub fn find_help(name: &str) -> Option<&'static Help> {
+    HELP.iter()
+        .flat_map(|(_, entries)| entries.iter())
+        .find(|entry| entry.is_named(name))
+}
+
+/// Show the documentation about a topic, which is a builtin, a
+/// group of builtins like `files`, or a namespace like `str`
+pub fn help_topic(topic: &str) -> Result<String, Error> {
+    if let Some(entry) = find_help(topic) {
+        return Ok(entry.show());
+    }
+    if let Some((group, entries)) = HELP
+        .iter()
+        .find(|(group, _)| group.eq_ignore_ascii_case(topic))
+    {
+        return Ok(list_help(&[(group, entries.iter().collect())]));
+    }
+    let prefix = format!("{}.", topic);
+    let members = HELP
+        .iter()
+        .flat_map(|(_, entries)| entries.iter())
+        .filter(|entry| entry.usage.starts_with(&prefix))
+        .collect::<Vec<&Help>>();
+    if !members.is_empty() {
+        return Ok(list_help(&[(topic, members)]));
+    }
+
+    let names = HELP
+        .iter()
+        .flat_map(|(group, entries)| {
+            let names = entries.iter().flat_map(|entry| {
+                Some(entry.name())
+                    .into_iter()
+                    .chain(entry.aliases.iter().copied())
+            });
+            Some(*group).into_iter().chain(names)
+        })
+        .map(|name| name.to_lowercase())
+        .collect();
+    Err(Error::UndefinedVariable(
+        topic.to_string(),
+        most_similar(&topic.to_lowercase(), names),
+    ))
+}
+
+/// Show the builtins in groups, with the usage of
+/// each builtin and the start of its description
+pub fn list_help(groups: &[(&str, Vec<&Help>)]) -> String {
+    let width = groups
+        .iter()
+        .flat_map(|(_, entries)| entries.iter())
+        .map(|entry| entry.usage.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut result = String::new();
+    for (group, entries) in groups {
+        result += &format!("{}:\n", group);
+        for entry in entries {
+            // Only the first sentence fits on the line
+            let summary = entry.description.split(". ").next().unwrap_or_default();
+            result += &format!(
+                "    {:width$}  {}\n",
+                entry.usage,
+                summary.trim_end_matches('.'),
+                width = width
+            );
+        }
+    }
+    result
+}
+
+/// Show every builtin, grouped by what they're for
+pub fn list_all_help() -> String {
+    let groups = HELP
+        .iter()
+        .map(|(group, entries)| (*group, entries.iter().collect()))
+        .collect::<Vec<_>>();
+    list_help(&groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_help, help_topic};
+    use crate::shell::Shell;
+    use crate::tokens::{Error, BUILTINS};
+    use xmachine::Value;
+
+    /// The help is written separately from the builtins, so
+    /// this makes sure that every one of them has an entry
+    #[test]
+    fn every_builtin_has_help() {
+        let shell = Shell::new();
+        let mut names = BUILTINS
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect::<Vec<String>>();
+        for name in shell.names() {
+            match shell.get_var(&name).as_deref() {
+                Some(Value::Function(_)) => names.push(name),
+                Some(Value::Tree(namespace)) => names.extend(
+                    namespace
+                        .iter()
+                        .filter(|(_, value)| matches!(***value, Value::Function(_)))
+                        .map(|(member, _)| format!("{}.{}", name, member)),
+                ),
+                _ => {}
+            }
+        }
+        let missing = names
+            .into_iter()
+            .filter(|name| find_help(name).is_none())
+            .collect::<Vec<String>>();
+        assert!(missing.is_empty(), "no help for {}", missing.join(", "));
+    }
+
+    #[test]
+    fn topics_are_builtins_groups_or_namespaces() {
+        assert_eq!(help_topic("dir").unwrap(), find_help("ls").unwrap().show());
+        assert!(help_topic("FILES")
+            .unwrap()
+            .starts_with("Files:\n    ls(dir?)"));
+        let namespace = help_topic("str").unwrap();
+        assert!(namespace.starts_with("str:\n"));
+        assert!(namespace.contains("str.split(text, separator?)"));
+        match help_topic("basenme") {
+            Err(Error::UndefinedVariable(_, Some(similar))) => assert_eq!(similar, "basename"),
+            result => panic!("basenme should suggest basename, not {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::color::{color_code, paint, style_code};
+use crate::lexer::{tokenize, TokenKind};
+use crate::tokens::Span;
+
+/// The colors and styles that code typed into the shell is highlighted
+/// with, as ANSI codes. The defaults are changed by setting keys of a
+/// `theme` dictionary in `~/.dunerc`, like `theme.keyword = "bold magenta"`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub keyword: Vec<u8>,
+    pub builtin: Vec<u8>,
+    pub identifier: Vec<u8>,
+    pub string: Vec<u8>,
+    pub number: Vec<u8>,
+    pub comment: Vec<u8>,
+    pub operator: Vec<u8>,
+    pub punctuation: Vec<u8>,
+    pub word: Vec<u8>,
+    /// A closing bracket that doesn't match the bracket it closes
+    pub unmatched: Vec<u8>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            keyword: vec![35],
+            builtin: vec![36],
+            identifier: vec![],
+            string: vec![32],
+            number: vec![33],
+            comment: vec![2],
+            operator: vec![],
+            punctuation: vec![],
+            word: vec![],
+            unmatched: vec![1, 31],
+        }
+    }
+}
+
+impl Theme {
+    /// Change how a kind of code is highlighted, given as the names of
+    /// colors and styles separated by spaces, like `"bold red"`. An empty
+    /// string turns it off. Names that aren't colors or styles are ignored.
+    /// Returns whether the kind of code is one that a theme has.
+    pub fn set(&mut self, kind: &str, style: &str) -> bool {
+        let codes = match kind {
+            "keyword" => &mut self.keyword,
+            "builtin" => &mut self.builtin,
+            "identifier" => &mut self.identifier,
+            "string" => &mut self.string,
+            "number" => &mut self.number,
+            "comment" => &mut self.comment,
+            "operator" => &mut self.operator,
+            "punctuation" => &mut self.punctuation,
+            "word" => &mut self.word,
+            "unmatched" => &mut self.unmatched,
+            _ => return false,
+        };
+        *codes = style
+            .split_whitespace()
+            .filter_map(|name| color_code(name, false).or_else(|| style_code(name)))
+            .collect();
+        true
+    }
+
+    fn codes(&self, kind: TokenKind) -> &[u8] {
+        match kind {
+            TokenKind::Keyword => &self.keyword,
+            TokenKind::Builtin => &self.builtin,
+            TokenKind::Identifier => &self.identifier,
+            TokenKind::String => &self.string,
+            TokenKind::Number => &self.number,
+            TokenKind::Comment => &self.comment,
+            TokenKind::Operator => &self.operator,
+            TokenKind::Punctuation => &self.punctuation,
+            TokenKind::Word => &self.word,
+        }
+    }
+}
+
+/// Find the closing brackets that don't close the last bracket that
+/// was opened. Brackets that are still open aren't a mistake, since
+/// the code may continue on the next line.
+fn unmatched_brackets(source: &str, tokens: &[(TokenKind, Span)]) -> Vec<usize> {
+    let mut open = vec![];
+    let mut unmatched = vec![];
+    for (kind, span) in tokens {
+        if *kind != TokenKind::Punctuation {
+            continue;
+        }
+        let closes = match &source[span.start..span.end] {
+            "(" | "[" | "{" => {
+                open.push(&source[span.start..span.end]);
+                continue;
+            }
+            ")" => "(",
+            "]" => "[",
+            "}" => "{",
+            _ => continue,
+        };
+        if open.last() == Some(&closes) {
+            open.pop();
+        } else {
+            unmatched.push(span.start);
+        }
+    }
+    unmatched
+}
+
+/// Color a line of code that is being typed. The lines before it in the
+/// same command are given too, so that a string or a block that they
+/// start is highlighted correctly, but only the line itself is returned.
+pub fn highlight(line: &str, before: &str, theme: &Theme) -> String {
+    let (source, offset) = if before.is_empty() {
+        (line.to_string(), 0)
+    } else {
+        (format!("{}\n{}", before, line), before.len() + 1)
+    };
+    let tokens = tokenize(&source);
+    let unmatched = unmatched_brackets(&source, &tokens);
+
+    let mut result = String::new();
+    let mut last = offset;
+    for (kind, span) in tokens.iter().filter(|(_, span)| span.end > offset) {
+        let start = span.start.max(offset);
+        result += &source[last..start];
+        let codes = if unmatched.contains(&span.start) {
+            &theme.unmatched
+        } else {
+            theme.codes(*kind)
+        };
+        result += &paint(&source[start..span.end], codes);
+        last = span.end;
+    }
+    result += &source[last..];
+    result
+}
@@ -0,0 +1,187 @@
+use crate::highlight::{highlight, Theme};
+use dirs::home_dir;
+use rustyline::completion::Completer;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{error::ReadlineError, Editor, Helper};
+use std::borrow::Cow;
+use std::io::{stdout, Write};
+use std::path::PathBuf;
+
+/// The result of reading a line of input
+#[derive(Clone, Debug, PartialEq)]
+pub enum Line {
+    /// A line of text entered by the user
+    Text(String),
+    /// The user pressed Ctrl-C, and the command being entered should be dropped
+    Interrupted,
+    /// There is no more input
+    End,
+}
+
+/// Where the shell reads its input from and writes its output to
+pub trait ShellIo {
+    /// Display a prompt and read a line of input
+    fn read_line(&mut self, prompt: &str) -> Line;
+
+    /// Write output for the user
+    fn write(&mut self, text: &str);
+
+    /// Write an error message for the user
+    fn write_err(&mut self, text: &str);
+
+    /// Remember a command that was entered
+    fn add_history(&mut self, _command: &str) {}
+
+    /// Color the code read by the next lines with a theme, or stop coloring
+    /// input if there's no theme. The lines already entered for the same
+    /// command are given too, since the next line may continue them.
+    fn set_highlighting(&mut self, _theme: Option<Theme>, _before: &str) {}
+
+    /// Whether output is written to the shell's own stdout. If it is,
+    /// programs are given the same stdout, so that programs like editors
+    /// and pagers can use the terminal. Otherwise their output is read
+    /// and passed to `write`.
+    fn writes_to_stdout(&self) -> bool {
+        false
+    }
+}
+
+/// Any iterator over lines can drive the shell, which
+/// allows the prompt loop to be run programmatically
+impl<I: Iterator<Item = String>> ShellIo for I {
+    fn read_line(&mut self, _prompt: &str) -> Line {
+        match self.next() {
+            Some(line) => Line::Text(line),
+            None => Line::End,
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        print!("{}", text);
+        let _ = stdout().flush();
+    }
+
+    fn write_err(&mut self, text: &str) {
+        eprint!("{}", text);
+    }
+
+    fn writes_to_stdout(&self) -> bool {
+        true
+    }
+}
+
+/// Colors the line being edited as it's typed
+#[derive(Default)]
+struct Highlighting {
+    theme: Option<Theme>,
+    before: String,
+}
+
+impl Highlighter for Highlighting {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        match &self.theme {
+            Some(theme) => Cow::Owned(highlight(line, &self.before, theme)),
+            None => Cow::Borrowed(line),
+        }
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        self.theme.is_some()
+    }
+}
+
+impl Completer for Highlighting {
+    type Candidate = String;
+}
+
+impl Hinter for Highlighting {
+    type Hint = String;
+}
+
+impl Validator for Highlighting {}
+
+impl Helper for Highlighting {}
+
+/// The interactive line editor, which supports cursor
+/// movement and recalling previous commands
+pub struct LineEditor {
+    editor: Option<Editor<Highlighting, DefaultHistory>>,
+    history: Option<PathBuf>,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self {
+            editor: None,
+            history: home_dir().map(|home| home.join(".dune_history")),
+        }
+    }
+
+    /// The editor is only set up once it is used, because
+    /// many shells are created that never read any input
+    fn editor(&mut self) -> Option<&mut Editor<Highlighting, DefaultHistory>> {
+        if self.editor.is_none() {
+            let mut editor = Editor::new().ok();
+            if let Some(editor) = &mut editor {
+                editor.set_helper(Some(Highlighting::default()));
+                if let Some(path) = &self.history {
+                    let _ = editor.load_history(path);
+                }
+            }
+            self.editor = editor;
+        }
+        self.editor.as_mut()
+    }
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShellIo for LineEditor {
+    fn read_line(&mut self, prompt: &str) -> Line {
+        match self.editor() {
+            Some(editor) => match editor.readline(prompt) {
+                Ok(line) => Line::Text(line),
+                Err(ReadlineError::Interrupted) => Line::Interrupted,
+                Err(_) => Line::End,
+            },
+            None => Line::End,
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        print!("{}", text);
+        let _ = stdout().flush();
+    }
+
+    fn write_err(&mut self, text: &str) {
+        eprint!("{}", text);
+    }
+
+    fn writes_to_stdout(&self) -> bool {
+        true
+    }
+
+    fn add_history(&mut self, command: &str) {
+        let history = self.history.clone();
+        if let Some(editor) = self.editor() {
+            let _ = editor.add_history_entry(command);
+            if let Some(path) = &history {
+                let _ = editor.save_history(path);
+            }
+        }
+    }
+
+    fn set_highlighting(&mut self, theme: Option<Theme>, before: &str) {
+        if let Some(helper) = self.editor().and_then(|editor| editor.helper_mut()) {
+            helper.theme = theme;
+            helper.before = before.to_string();
+        }
+    }
+}
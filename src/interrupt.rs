@@ -0,0 +1,60 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::Instant;
+
+/// Whether or not the user has pressed Ctrl-C since the last reset
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+
+thread_local! {
+    /// When the code that is running must stop, if it has a time limit
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Catch SIGINT instead of letting it kill the shell. Child processes
+/// still receive the signal from the terminal, so Ctrl-C only stops
+/// the command that is currently running.
+pub fn catch_interrupts() {
+    INSTALL.call_once(|| {
+        let _ = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst));
+    });
+}
+
+/// Has Ctrl-C been pressed since the last reset, or
+/// has the time limit for the running code passed?
+pub fn is_interrupted() -> bool {
+    is_ctrl_c_pressed()
+        || DEADLINE
+            .with(|deadline| deadline.get())
+            .is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+/// Has Ctrl-C been pressed since the last reset?
+pub fn is_ctrl_c_pressed() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Stop the running code at a time, as if Ctrl-C was pressed. If an
+/// earlier time was already set, that time is kept. The time that was
+/// set before is returned, so that it can be restored afterwards.
+pub fn set_deadline(deadline: Instant) -> Option<Instant> {
+    DEADLINE.with(|current| {
+        let outer = current.get();
+        current.set(Some(match outer {
+            Some(outer) => outer.min(deadline),
+            None => deadline,
+        }));
+        outer
+    })
+}
+
+/// Put back the time limit that was replaced by `set_deadline`
+pub fn restore_deadline(deadline: Option<Instant>) {
+    DEADLINE.with(|current| current.set(deadline));
+}
+
+/// Forget about any Ctrl-C presses before now
+pub fn reset_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
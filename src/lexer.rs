@@ -0,0 +1,149 @@
+use crate::parser::RESERVED;
+use crate::tokens::{Span, BUILTINS};
+
+/// The kinds of pieces that source code is split into by `tokenize`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenKind {
+    /// A word of the language, like `if`, `fn`, or `true`
+    Keyword,
+    /// A name, such as of a variable or a program
+    Identifier,
+    /// The name of a builtin, like `ls` or `cd`
+    Builtin,
+    /// A string literal, with its quotes
+    String,
+    Number,
+    /// A comment, from its `#` to the end of the line
+    Comment,
+    /// A comparison, a logical operator, `=`, or `=>`
+    Operator,
+    /// A bracket, a comma, a semicolon, or a dot
+    Punctuation,
+    /// Anything else given to a program, like `-la` or `/tmp`
+    Word,
+}
+
+/// The operators, with the longer ones first so they are matched first
+const OPERATORS: &[&str] = &[
+    "==", "!=", "<=", ">=", "=>", "&&", "||", "=", "<", ">", "!", "&", "|",
+];
+
+/// The characters that end a word
+fn ends_word(ch: char) -> bool {
+    ch.is_whitespace() || "(){}[],;\"".contains(ch)
+}
+
+/// The characters that can't be part of an identifier or a number
+/// without making them a word, like the `-` in `my-file`
+fn continues_word(ch: char) -> bool {
+    !ends_word(ch) && !".=<>!&|".contains(ch)
+}
+
+/// Split source code into the pieces that an editor would color, along
+/// with where each of them is. This doesn't parse the code, so it works
+/// on code that isn't finished, and the whitespace between the pieces is
+/// left out. A `#` in the middle of a word, like `a#b`, isn't a comment.
+pub fn tokenize(source: &str) -> Vec<(TokenKind, Span)> {
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut previous = '\n';
+    let mut i = 0;
+    while let Some(ch) = source[i..].chars().next() {
+        let rest = &source[i..];
+        let after = |n: usize| rest[n..].chars().next();
+        let is_number =
+            ch.is_ascii_digit() || (ch == '-' && after(1).is_some_and(|c| c.is_ascii_digit()));
+        let (kind, len) = if ch.is_whitespace() {
+            if ch == '\n' {
+                line += 1;
+            }
+            previous = ch;
+            i += ch.len_utf8();
+            continue;
+        } else if ch == '#' && ends_word(previous) {
+            (TokenKind::Comment, rest.find('\n').unwrap_or(rest.len()))
+        } else if ch == '"' {
+            (TokenKind::String, string_len(rest))
+        } else if is_number {
+            let mut len = 1 + digits_len(&rest[1..]);
+            if rest[len..].starts_with('.') && after(len + 1).is_some_and(|c| c.is_ascii_digit()) {
+                len += 1 + digits_len(&rest[len + 1..]);
+            }
+            (TokenKind::Number, len)
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let name = &rest[..len];
+            // The names after a dot are the keys of a dictionary
+            let kind = if previous == '.' {
+                TokenKind::Identifier
+            } else if RESERVED.contains(&name) {
+                TokenKind::Keyword
+            } else if BUILTINS.iter().any(|(builtin, _)| *builtin == name) {
+                TokenKind::Builtin
+            } else {
+                TokenKind::Identifier
+            };
+            (kind, len)
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            (TokenKind::Operator, op.len())
+        } else if "(){}[],;".contains(ch)
+            || (ch == '.' && after(1).is_some_and(|c| c.is_ascii_alphabetic()))
+        {
+            // A dot that isn't before a name is part of a path, like `./run`
+            (TokenKind::Punctuation, 1)
+        } else {
+            (TokenKind::Word, 0)
+        };
+
+        // Identifiers and numbers with other characters stuck to them,
+        // like `my-file` or `2nd`, are words given to a program
+        let (kind, len) = match kind {
+            TokenKind::Comment
+            | TokenKind::String
+            | TokenKind::Operator
+            | TokenKind::Punctuation => (kind, len),
+            _ if len > 0 && !rest[len..].starts_with(continues_word) => (kind, len),
+            _ => (
+                TokenKind::Word,
+                rest.find(|c: char| ends_word(c)).unwrap_or(rest.len()),
+            ),
+        };
+        tokens.push((
+            kind,
+            Span {
+                start: i,
+                end: i + len,
+                line,
+                source: 0,
+            },
+        ));
+        line += rest[..len].matches('\n').count();
+        previous = rest[..len].chars().last().unwrap_or(previous);
+        i += len;
+    }
+    tokens
+}
+
+/// The length of the digits at the start of some code
+fn digits_len(code: &str) -> usize {
+    code.find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(code.len())
+}
+
+/// The length of a string literal at the start of some code, including
+/// its quotes. A string that isn't closed goes to the end of the code.
+fn string_len(code: &str) -> usize {
+    let mut chars = code.char_indices().skip(1);
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '"' => return i + 1,
+            _ => {}
+        }
+    }
+    code.len()
+}
@@ -8,9 +8,84 @@ pub use shell::*;
 pub mod tokens;
 pub use tokens::*;
 
+pub mod scope;
+pub use scope::*;
+
+pub mod symbol;
+pub use symbol::*;
+
 pub mod parser;
 pub use parser::*;
 
+pub mod formatter;
+pub use formatter::*;
+
+pub mod frecency;
+pub use frecency::*;
+
+pub mod hash;
+pub use hash::*;
+
+pub mod help;
+pub use help::*;
+
+pub mod highlight;
+pub use highlight::*;
+
+pub mod input;
+pub use input::*;
+
+pub mod interrupt;
+pub use interrupt::*;
+
+pub mod lexer;
+pub use lexer::*;
+
+pub mod menu;
+pub use menu::*;
+
+pub mod archive;
+pub use archive::*;
+
+pub mod checker;
+pub use checker::*;
+
+pub mod compiler;
+pub use compiler::*;
+
+pub mod debugger;
+pub use debugger::*;
+
+pub mod color;
+pub use color::*;
+
+pub mod convert;
+pub use convert::*;
+
+pub mod disk;
+pub use disk::*;
+
+pub mod display;
+pub use display::*;
+
+pub mod permissions;
+pub use permissions::*;
+
+pub mod plugin;
+pub use plugin::*;
+
+pub mod process;
+pub use process::*;
+
+pub mod profiler;
+pub use profiler::*;
+
+pub mod stdlib;
+pub use stdlib::*;
+
+pub mod visit;
+pub use visit::*;
+
 
 pub const INFO: &str = concat!("Dune Shell ", env!("CARGO_PKG_VERSION"), "\n", env!("CARGO_PKG_AUTHORS"), "\n", env!("CARGO_PKG_DESCRIPTION"));
 
@@ -0,0 +1,248 @@
+use crate::tokens::Error;
+
+/// The most choices that are shown at once
+const MAX_SHOWN: usize = 10;
+
+/// Does a choice match what the user has typed? The typed characters
+/// must appear in the choice in order, but not necessarily together,
+/// so `fb` matches `foo bar`.
+pub fn fuzzy_match(choice: &str, query: &str) -> bool {
+    let mut choice = choice.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|ch| choice.any(|other| other == ch))
+}
+
+/// A key pressed while the menu is open
+#[cfg(unix)]
+enum Key {
+    Up,
+    Down,
+    Enter,
+    Cancel,
+    Backspace,
+    Char(char),
+    Other,
+}
+
+/// Let the user pick one of several choices with the arrow keys, typing
+/// to narrow down the choices. The index of the choice is returned, or
+/// nothing if the user cancelled. If there is no terminal to show the
+/// menu in, `Err` is returned so that the caller can ask another way.
+#[cfg(unix)]
+pub fn select(choices: &[String]) -> Result<Option<usize>, Error> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|e| Error::IoError(format!("select: {}", e)))?;
+    let _raw = RawMode::enable(tty.as_raw_fd())
+        .ok_or_else(|| Error::IoError(String::from("select: not a terminal")))?;
+
+    let mut query = String::new();
+    let mut selected = 0;
+    let choice = loop {
+        let matches = (0..choices.len())
+            .filter(|i| fuzzy_match(&choices[*i], &query))
+            .collect::<Vec<usize>>();
+        selected = selected.min(matches.len().saturating_sub(1));
+        // Scroll so that the selected choice is always shown
+        let first = (selected + 1).saturating_sub(MAX_SHOWN);
+
+        let mut screen = String::from("\r\x1b[J");
+        screen += &format!("> {}", query);
+        for (i, index) in matches.iter().enumerate().skip(first).take(MAX_SHOWN) {
+            if i == selected {
+                screen += &format!("\r\n\x1b[7m{}\x1b[0m", choices[*index]);
+            } else {
+                screen += &format!("\r\n{}", choices[*index]);
+            }
+        }
+        // Go back to the end of the line being typed on
+        let shown = (matches.len() - first).min(MAX_SHOWN);
+        if shown > 0 {
+            screen += &format!("\x1b[{}A", shown);
+        }
+        screen += &format!("\r\x1b[{}C", query.chars().count() + 2);
+        let _ = tty.write_all(screen.as_bytes());
+        let _ = tty.flush();
+
+        match read_key(&mut tty) {
+            Key::Up => selected = selected.saturating_sub(1),
+            Key::Down => selected += 1,
+            Key::Enter => break matches.get(selected).copied(),
+            Key::Cancel => break None,
+            Key::Backspace => {
+                query.pop();
+            }
+            Key::Char(ch) => query.push(ch),
+            Key::Other => {}
+        }
+    };
+    let _ = tty.write_all(b"\r\x1b[J");
+    Ok(choice)
+}
+
+/// Let the user pick one of several choices. Menus can't be
+/// shown on this platform, so the caller must ask another way.
+#[cfg(not(unix))]
+pub fn select(_choices: &[String]) -> Result<Option<usize>, Error> {
+    Err(Error::IoError(String::from(
+        "select: menus are not supported on this platform",
+    )))
+}
+
+/// Read a line from the terminal without showing what is typed,
+/// for secrets like passwords. If there is no terminal, `Err` is
+/// returned so that the caller can ask another way.
+#[cfg(unix)]
+pub fn read_password(prompt: &str) -> Result<String, Error> {
+    use std::fs::OpenOptions;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::io::AsRawFd;
+
+    let error = |e: std::io::Error| Error::IoError(format!("password: {}", e));
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(error)?;
+    let hidden = RawMode::without(tty.as_raw_fd(), libc::ECHO)
+        .ok_or_else(|| Error::IoError(String::from("password: not a terminal")))?;
+    tty.write_all(prompt.as_bytes()).map_err(error)?;
+    tty.flush().map_err(error)?;
+    let mut line = String::new();
+    BufReader::new(&tty).read_line(&mut line).map_err(error)?;
+    drop(hidden);
+    // The newline that was typed wasn't shown either
+    tty.write_all(b"\n").map_err(error)?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// Read a line without showing what is typed. This is not
+/// supported on this platform, so the caller must ask another way.
+#[cfg(not(unix))]
+pub fn read_password(_prompt: &str) -> Result<String, Error> {
+    Err(Error::IoError(String::from(
+        "password: hidden input is not supported on this platform",
+    )))
+}
+
+/// Read a key from the terminal, including the arrow keys,
+/// which are sent as several bytes
+#[cfg(unix)]
+fn read_key(tty: &mut std::fs::File) -> Key {
+    match read_byte(tty) {
+        None | Some(3) | Some(4) => Key::Cancel,
+        Some(b'\r') | Some(b'\n') => Key::Enter,
+        Some(127) | Some(8) => Key::Backspace,
+        Some(16) => Key::Up,
+        Some(14) => Key::Down,
+        Some(27) => {
+            // A lone escape cancels, but escape is also the start of the arrow keys
+            if !more_input(tty) {
+                return Key::Cancel;
+            }
+            match (read_byte(tty), read_byte(tty)) {
+                (Some(b'['), Some(b'A')) | (Some(b'O'), Some(b'A')) => Key::Up,
+                (Some(b'['), Some(b'B')) | (Some(b'O'), Some(b'B')) => Key::Down,
+                _ => Key::Other,
+            }
+        }
+        Some(byte) if byte.is_ascii_graphic() || byte == b' ' => Key::Char(byte as char),
+        // The rest of a character that takes more than one byte
+        Some(byte) if byte >= 0xc0 => {
+            let len = if byte >= 0xf0 {
+                4
+            } else if byte >= 0xe0 {
+                3
+            } else {
+                2
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.extend(read_byte(tty));
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(ch) => Key::Char(ch),
+                None => Key::Other,
+            }
+        }
+        Some(_) => Key::Other,
+    }
+}
+
+/// Read a single byte from the terminal
+#[cfg(unix)]
+fn read_byte(tty: &mut std::fs::File) -> Option<u8> {
+    use std::io::Read;
+
+    let mut byte = [0];
+    match tty.read(&mut byte) {
+        Ok(1) => Some(byte[0]),
+        _ => None,
+    }
+}
+
+/// Is there more input waiting to be read right away?
+#[cfg(unix)]
+fn more_input(tty: &std::fs::File) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    let mut poll = libc::pollfd {
+        fd: tty.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut poll, 1, 50) > 0 }
+}
+
+/// Turns off some of the features of a terminal while it exists,
+/// such as line buffering or showing what is typed
+#[cfg(unix)]
+struct RawMode {
+    fd: i32,
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    /// Turn off line buffering and echoing, so that
+    /// each key can be read as it's pressed
+    fn enable(fd: i32) -> Option<Self> {
+        Self::without(fd, libc::ICANON | libc::ECHO | libc::ISIG)
+    }
+
+    /// Turn off the features given by the terminal's local flags
+    fn without(fd: i32, flags: libc::tcflag_t) -> Option<Self> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return None;
+        }
+        let mut raw = original;
+        raw.c_lflag &= !flags;
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return None;
+        }
+        Some(Self { fd, original })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+        }
+    }
+}
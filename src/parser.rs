@@ -1,21 +1,200 @@
 extern crate honeycomb;
 use honeycomb::{
-    atoms::{any, eof, opt, rec, seq_no_ws, space, sym},
-    language::{array, identifier, number, string},
+    atoms::{eof, one_of, opt, space, sym},
+    language::{alphanumeric, array, identifier, number},
     transform::to_number,
     Parser,
 };
 
 use alloc::boxed::Box;
 use alloc::vec::Vec;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
 
+use crate::compiler::LoopCode;
+use crate::symbol::Symbol;
 use crate::tokens::{
-    Builtin, Expr, FnCall, Function, FunctionDef, Identifier, Literal, Name, Suite, Value,
+    Builtin, Error, Expr, FnCall, Function, FunctionDef, Identifier, Literal, Name, Pattern, Span,
+    Suite, Value, BUILTINS,
 };
 
+/// This consumes an exact sequence of characters. The honeycomb
+/// version slices its input by byte when the sequence is not found,
+/// which panics when that lands inside of a multibyte character.
+pub fn seq(sequence: &'static str) -> Parser<String> {
+    Parser::new(
+        move |s: &str| {
+            if let Some(remaining) = s.strip_prefix(sequence) {
+                Ok((sequence.to_string(), remaining.to_string()))
+            } else {
+                let found = s.chars().take(sequence.chars().count()).collect::<String>();
+                honeycomb::Error::new(found, sequence, s)
+            }
+        },
+        sequence.to_string(),
+    )
+}
+
+/// This consumes a sequence of characters and the whitespace around it
+pub fn seq_no_ws(sequence: &'static str) -> Parser<String> {
+    space() >> seq(sequence) << space()
+}
+
+/// This matches a word that is not the beginning of a longer identifier,
+/// without consuming any of the whitespace that follows it
+pub fn word(word: &'static str) -> Parser<String> {
+    space() >> seq(word) << (alphanumeric() | sym('_')).isnt()
+}
+
+/// This matches a keyword that is not the beginning of a longer identifier
+pub fn keyword(keyword: &'static str) -> Parser<String> {
+    word(keyword) << space()
+}
+
+thread_local! {
+    /// The length of the source being parsed. Parsers are only given
+    /// the source that is left to parse, so this is how they find
+    /// how far into the source they are.
+    static SOURCE_LENGTH: Cell<usize> = const { Cell::new(0) };
+    /// How many sources have been parsed, which tells apart the
+    /// spans of each source from the spans of the others
+    static SOURCE_COUNT: Cell<usize> = const { Cell::new(0) };
+    /// The offsets that each line of the source being parsed starts at
+    static LINE_STARTS: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    /// The parsers that have been built, by the function that builds them
+    static PARSERS: RefCell<HashMap<usize, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Build a parser the first time it's needed, and reuse it after that.
+/// Building the combinators for the whole language takes much longer
+/// than parsing a line with them.
+fn built<T: 'static + Clone>(parser: fn() -> Parser<T>) -> Parser<T> {
+    let key = parser as usize;
+    let cached = PARSERS.with(|parsers| {
+        parsers
+            .borrow()
+            .get(&key)
+            .and_then(|built| built.downcast_ref::<Parser<T>>())
+            .cloned()
+    });
+    cached.unwrap_or_else(|| {
+        let built = parser();
+        PARSERS.with(|parsers| parsers.borrow_mut().insert(key, Box::new(built.clone())));
+        built
+    })
+}
+
+/// This refers to a parser that contains itself. The honeycomb
+/// version builds the parser again every time it is used, so
+/// recursive parsers were rebuilt at every level of nesting.
+pub fn rec<T: 'static + Clone>(parser: fn() -> Parser<T>) -> Parser<T> {
+    Parser::new(
+        move |s| built(parser).parse_internal(s),
+        "result from recursive Parser",
+    )
+}
+
+/// This runs a parser, and gives the span of the source that
+/// it matched, without the whitespace around it
+pub fn spanned<T: 'static + Clone>(parser: Parser<T>) -> Parser<(Span, T)> {
+    let expectation = parser.expectation.clone();
+    Parser::new(
+        move |s: &str| {
+            let (result, remaining) = parser.parse_internal(s)?;
+            let offset = SOURCE_LENGTH.with(Cell::get).saturating_sub(s.len());
+            let consumed = s
+                .get(..s.len().saturating_sub(remaining.len()))
+                .unwrap_or("");
+            let start = offset + consumed.len() - consumed.trim_start().len();
+            let line = LINE_STARTS.with(|starts| starts.borrow().partition_point(|&s| s <= start));
+            let span = Span {
+                start,
+                end: offset + consumed.trim_end().len(),
+                line,
+                source: SOURCE_COUNT.with(Cell::get),
+            };
+            Ok(((span, result), remaining))
+        },
+        expectation,
+    )
+}
+
+/// This runs a parser over the rest of the current line only,
+/// so that it cannot consume anything on the following lines
+pub fn on_line<T: 'static + Clone>(parser: Parser<T>) -> Parser<T> {
+    let expectation = parser.expectation.clone();
+    Parser::new(
+        move |s: &str| {
+            let end = s.find('\n').unwrap_or(s.len());
+            let (consumed, remaining) = parser.parse_internal(&s[..end])?;
+            Ok((consumed, remaining + &s[end..]))
+        },
+        expectation,
+    )
+}
+
+/// This matches a unicode escape sequence such as `u{1F600}`,
+/// and gives the character with that code point
+pub fn unicode_escape() -> Parser<char> {
+    ((seq("u{") >> (one_of(b"0123456789abcdefABCDEF") * (1..=6)) << sym('}'))
+        ^ |digits: Vec<char>| -> Result<char, ()> {
+            let code =
+                u32::from_str_radix(&digits.iter().collect::<String>(), 16).map_err(|_| ())?;
+            core::char::from_u32(code).ok_or(())
+        })
+        % "a unicode escape sequence"
+}
+
+/// This matches a backslash escape sequence in a string,
+/// and gives the character it stands for
+pub fn escape_sequence() -> Parser<char> {
+    (sym('\\')
+        >> (sym('\\')
+            | sym('/')
+            | sym('"')
+            | sym('\'')
+            | (sym('0') - |_| '\0')
+            | (sym('b') - |_| '\x08')
+            | (sym('f') - |_| '\x0C')
+            | (sym('n') - |_| '\n')
+            | (sym('r') - |_| '\r')
+            | (sym('t') - |_| '\t')
+            | unicode_escape()))
+        % "an escape sequence"
+}
+
+/// This consumes a character if a function is true for it.
+/// The honeycomb atoms index strings by byte, so this one is
+/// written by hand to keep multibyte characters intact.
+pub fn char_where(predicate: fn(char) -> bool) -> Parser<char> {
+    Parser::new(
+        move |s: &str| match s.chars().next() {
+            Some(ch) if predicate(ch) => Ok((ch, s[ch.len_utf8()..].to_string())),
+            Some(ch) => honeycomb::Error::new(ch, "a matching character", s),
+            None => honeycomb::Error::new('\0', "a matching character", s),
+        },
+        "a matching character",
+    )
+}
+
+/// This matches any character in a string besides a quote or a backslash
+pub fn string_char() -> Parser<char> {
+    char_where(|ch| ch != '\\' && ch != '"') % "a string character"
+}
+
+/// This matches a double quoted string, and decodes
+/// the escape sequences within it
+pub fn string_body() -> Parser<String> {
+    ((sym('"') >> ((string_char() | escape_sequence()) * (..)) << sym('"'))
+        - |chars: Vec<char>| chars.iter().collect::<String>())
+        % "a string"
+}
+
 /// This parses a string literal
 pub fn string_literal() -> Parser<Literal> {
-    ((space() >> string() << space()) - Literal::String) % "a string literal"
+    ((space() >> string_body() << space()) - Literal::String) % "a string literal"
 }
 
 /// This parses a number literal
@@ -23,6 +202,18 @@ pub fn number_literal() -> Parser<Literal> {
     ((space() >> (number() - to_number) << space()) - Literal::Number) % "a number literal"
 }
 
+/// This parses `true` or `false`
+pub fn bool_literal() -> Parser<Literal> {
+    (((word("true") - |_| Literal::Bool(true)) | (word("false") - |_| Literal::Bool(false)))
+        << space())
+        % "a boolean literal"
+}
+
+/// This parses `none`
+pub fn none_literal() -> Parser<Literal> {
+    ((word("none") << space()) - |_| Literal::None) % "none"
+}
+
 /// This matches either a number or string literal
 pub fn literal() -> Parser<Value> {
     (string_literal() | number_literal()) - Value::Literal
@@ -30,27 +221,37 @@ pub fn literal() -> Parser<Value> {
 
 /// This matches a simple identifier
 pub fn builtin() -> Parser<Value> {
-    (((seq_no_ws("ls") | seq_no_ws("dir")) - |_| Builtin::List)
-        | ((seq_no_ws("clear") | seq_no_ws("cls")) - |_| Builtin::Clear)
-        | ((seq_no_ws("sh") | seq_no_ws("cmd")) - |_| Builtin::ShellOut)
-        | (seq_no_ws("mv") - |_| Builtin::Move)
-        | (seq_no_ws("cd") - |_| Builtin::ChangeDir)
-        | (seq_no_ws("rm") - |_| Builtin::Remove)
-        | (seq_no_ws("mkdir") - |_| Builtin::MakeDir)
-        | ((seq_no_ws("mkf") | seq_no_ws("touch")) - |_| Builtin::MakeFile)
-        | ((seq_no_ws("pwd") | seq_no_ws("cwd")) - |_| Builtin::WorkingDir)
-        | ((seq_no_ws("exit") | seq_no_ws("quit") | seq_no_ws("bye")) - |_| Builtin::Exit))
-        - Value::Builtin
+    BUILTINS
+        .iter()
+        .map(|(name, _)| word(name))
+        .reduce(|a, b| a | b)
+        .unwrap()
+        ^ |word: String| -> Result<Value, ()> {
+            match BUILTINS.iter().find(|(name, _)| *name == word) {
+                Some((_, builtin)) => Ok(Value::Builtin(builtin.clone())),
+                None => Err(()),
+            }
+        }
+}
+
+/// This matches `_`, or `_` followed by a number, which are
+/// the names of the results of the last commands entered
+pub fn result_name() -> Parser<String> {
+    ((sym('_') >> (char_where(|ch| ch.is_ascii_digit()) * (..)))
+        << (alphanumeric() | sym('_')).isnt())
+        - |digits| format!("_{}", digits.into_iter().collect::<String>())
 }
 
 /// This matches a simple identifier
 pub fn ident() -> Parser<Identifier> {
-    ((space() >> identifier() << space()) - Identifier) % "an identifier"
+    ((space() >> (identifier() | result_name()) << space())
+        - |name| Identifier(Symbol::new(&name)))
+        % "an identifier"
 }
 
 /// This matches a value, succeeded by [] enclosed values
 pub fn index_name(values: Parser<Value>) -> Parser<(Box<Value>, Vec<Value>)> {
-    ((values & ((seq_no_ws("[") >> rec(value) << seq_no_ws("]")) * (1..)))
+    ((values & ((seq_no_ws("[") >> rec(disjunction) << seq_no_ws("]")) * (1..)))
         - |(head, indices)| (Box::new(head), indices))
         % "a value followed by one or more indices"
 }
@@ -80,6 +281,31 @@ pub fn name() -> Parser<Name> {
         % "a dotted name, an indexed value, or an identifier"
 }
 
+/// This matches `cd -`, which goes back to the previous directory.
+/// A lone `-` is not a value, so it is handled on its own.
+pub fn cd_back() -> Parser<Value> {
+    (word("cd") >> on_line(space() >> seq("-") << (alphanumeric() | one_of(b"-_")).isnt()))
+        - |_| {
+            Value::FnCall(FnCall(
+                Box::new(Value::Builtin(Builtin::ChangeDir)),
+                vec![Value::Literal(Literal::String(String::from("-")))],
+            ))
+        }
+}
+
+/// This matches `bookmark name` or `goto name`, which
+/// take a name for a directory instead of a value
+pub fn bookmark_cmd() -> Parser<Value> {
+    (((word("bookmark") - |_| Builtin::Bookmark) | (word("goto") - |_| Builtin::Goto))
+        & on_line(space() >> identifier() << (alphanumeric() | sym('_')).isnt()))
+        - |(builtin, name)| {
+            Value::FnCall(FnCall(
+                Box::new(Value::Builtin(builtin)),
+                vec![Value::Literal(Literal::String(name))],
+            ))
+        }
+}
+
 /// This matches a function call, a value called with arguments
 pub fn fncall() -> Parser<Value> {
     // The value being called can either be
@@ -87,17 +313,28 @@ pub fn fncall() -> Parser<Value> {
     // 2) group
     // The arguments can be () enclosed and comma separated values
     // there can be 0 or more values.
-    (((builtin() & (rec(value) * (1..)))
-        - |call_data: (Value, Vec<Value>)| {
-            Value::FnCall(FnCall(Box::new(call_data.0), call_data.1))
-        })
-        | (((builtin() | (name() - Value::Name) | rec(group)) & array("(", rec(value), ")"))
+    // Builtins can also take space separated arguments, which
+    // must all be on the same line as the builtin.
+    (cd_back()
+        | bookmark_cmd()
+        | ((builtin() & on_line(rec(value) * (1..)))
+            - |call_data: (Value, Vec<Value>)| {
+                Value::FnCall(FnCall(Box::new(call_data.0), call_data.1))
+            })
+        | (((builtin() | callee()) & array("(", rec(disjunction), ")"))
             - |call_data: (Value, Vec<Value>)| {
                 Value::FnCall(FnCall(Box::new(call_data.0), call_data.1))
             }))
         % "a value followed by comma arguments"
 }
 
+/// This matches a name or a group being called, and records where
+/// it is so that errors inside of the function can show the call
+pub fn callee() -> Parser<Value> {
+    spanned((name() - Value::Name) | rec(group))
+        - |(span, value)| Value::Spanned(span, Box::new(value))
+}
+
 /// This represents an anonymous function literal.
 /// A function literal looks like the following:
 ///
@@ -107,7 +344,7 @@ pub fn fncall() -> Parser<Value> {
 /// is basically a lambda expression.
 pub fn function() -> Parser<Function> {
     (seq_no_ws("fn") >> (array("(", ident(), ")") & suite()))
-        - |(params, suite)| Function(params, suite)
+        - |(params, suite)| Function(params, Rc::new(suite))
 }
 
 /// This represents a function definition.
@@ -120,13 +357,13 @@ pub fn function() -> Parser<Function> {
 pub fn function_def() -> Parser<FunctionDef> {
     let body = array("(", ident(), ")") & rec(suite);
     ((seq_no_ws("fn") >> name() & body)
-        - |(n, (params, suite))| FunctionDef(n, Function(params, suite)))
+        - |(n, (params, suite))| FunctionDef(n, Function(params, Rc::new(suite))))
         % "a valid function definition"
 }
 
 /// This matches a grouped value, any () enclosed value
 pub fn group() -> Parser<Value> {
-    seq_no_ws("(") >> rec(value) << seq_no_ws(")")
+    seq_no_ws("(") >> rec(disjunction) << seq_no_ws(")")
 }
 
 /// This matches values that do not have the possibility of
@@ -146,26 +383,103 @@ pub fn recursive_value() -> Parser<Value> {
 
 /// This represents an atomic value
 pub fn value() -> Parser<Value> {
-    rec(recursive_value) | rec(flat_value)
+    // Booleans and none are matched first, so that they aren't taken for names
+    spanned(
+        ((bool_literal() | none_literal()) - Value::Literal)
+            | rec(recursive_value)
+            | rec(flat_value),
+    ) - |(span, value)| Value::Spanned(span, Box::new(value))
+}
+
+/// This matches a comparison operator, and gives the
+/// name of the builtin function that performs it
+pub fn comparison_op() -> Parser<Identifier> {
+    ((seq_no_ws("==") - |_| Identifier(Symbol::new("eq")))
+        | (seq_no_ws("!=") - |_| Identifier(Symbol::new("neq")))
+        | (seq_no_ws("<=") - |_| Identifier(Symbol::new("le")))
+        | (seq_no_ws(">=") - |_| Identifier(Symbol::new("ge")))
+        | (seq_no_ws("<") - |_| Identifier(Symbol::new("lt")))
+        | (seq_no_ws(">") - |_| Identifier(Symbol::new("gt"))))
+        % "a comparison operator"
+}
+
+/// This matches a value, optionally compared to another value.
+/// A comparison is a call to one of the comparison builtins,
+/// so `i < 10` is the same as `lt(i, 10)`
+pub fn comparison() -> Parser<Value> {
+    ((rec(value) & opt(comparison_op() & rec(value)))
+        - |(lhs, rest): (Value, Option<(Identifier, Value)>)| match rest {
+            Some((op, rhs)) => Value::FnCall(FnCall(
+                Box::new(Value::Name(Name::Name(op))),
+                vec![lhs, rhs],
+            )),
+            None => lhs,
+        })
+        % "a value or a comparison of two values"
+}
+
+/// This matches one or more comparisons joined with `&&` or `and`
+pub fn conjunction() -> Parser<Value> {
+    ((rec(comparison) & (((seq_no_ws("&&") | keyword("and")) >> rec(comparison)) * (..)))
+        - |(head, rest): (Value, Vec<Value>)| {
+            rest.into_iter()
+                .fold(head, |lhs, rhs| Value::And(Box::new(lhs), Box::new(rhs)))
+        })
+        % "a value or a conjunction of values"
+}
+
+/// This matches one or more conjunctions joined with `||` or `or`.
+/// Logical operators only evaluate their right hand side when
+/// the left hand side does not determine the result.
+pub fn disjunction() -> Parser<Value> {
+    ((rec(conjunction) & (((seq_no_ws("||") | keyword("or")) >> rec(conjunction)) * (..)))
+        - |(head, rest): (Value, Vec<Value>)| {
+            rest.into_iter()
+                .fold(head, |lhs, rhs| Value::Or(Box::new(lhs), Box::new(rhs)))
+        })
+        % "a value or a disjunction of values"
 }
 
 /// This stores to an identifier,
 /// or assigns to an indexed value
 pub fn assignment() -> Parser<Expr> {
-    ((name() & (seq_no_ws("=") >> value())) - |(n, v)| Expr::Assignment(n, v))
+    ((name() & (seq_no_ws("=") >> disjunction())) - |(n, v)| Expr::Assignment(n, v))
         % "a valid assignment"
 }
 
 /// While a condition is true, execute a suite
 pub fn while_loop() -> Parser<Expr> {
-    (((seq_no_ws("while") >> value()) & rec(suite)) - |(n, v)| Expr::WhileLoop(n, v))
+    (((seq_no_ws("while") >> disjunction()) & rec(suite))
+        - |(n, v)| Expr::WhileLoop(n, v, LoopCode::default()))
         % "a valid while loop"
 }
 
+/// For each item in a list, bind it to a name and execute a suite
+pub fn for_loop() -> Parser<Expr> {
+    ((((keyword("for") >> ident()) & (keyword("in") >> value())) & rec(suite))
+        - |((name, list), body)| Expr::ForLoop(name, list, body, LoopCode::default()))
+        % "a valid for loop"
+}
+
+/// Stop executing the current function, and return a value
+pub fn return_stmt() -> Parser<Expr> {
+    ((keyword("return") >> disjunction()) - Expr::Return) % "a valid return statement"
+}
+
+/// Stop executing the innermost loop
+pub fn break_stmt() -> Parser<Expr> {
+    (keyword("break") - |_| Expr::Break) % "a valid break statement"
+}
+
+/// Skip to the next iteration of the innermost loop
+pub fn continue_stmt() -> Parser<Expr> {
+    (keyword("continue") - |_| Expr::Continue) % "a valid continue statement"
+}
+
 /// If a condition is true, execute a suite
 /// else, execute a suite
 pub fn if_then_else() -> Parser<Expr> {
-    ((((seq_no_ws("if") >> value()) & rec(suite)) & opt(seq_no_ws("else") >> rec(suite)))
+    ((((seq_no_ws("if") >> disjunction()) & rec(suite)) & opt(seq_no_ws("else") >> rec(suite)))
         - |((condition, then_body), else_body_opt)| {
             let else_body = match else_body_opt {
                 Some(body) => body,
@@ -177,17 +491,135 @@ pub fn if_then_else() -> Parser<Expr> {
         % "a valid if else statement"
 }
 
+/// This matches a pattern that a value can be matched against:
+/// a literal, a list of patterns, or `_`
+pub fn pattern() -> Parser<Pattern> {
+    (((word("_") << space()) - |_| Pattern::Wildcard)
+        | ((string_literal() | number_literal() | bool_literal() | none_literal())
+            - Pattern::Literal)
+        | (array("[", rec(pattern), "]") - Pattern::List))
+        % "a pattern"
+}
+
+/// Execute the suite of the first pattern that matches a value
+///
+/// `match ext { ".rs" => { println("rust") } _ => {} }`
+pub fn match_expr() -> Parser<Expr> {
+    let arm = (pattern() & (seq_no_ws("=>") >> rec(suite))) << opt(seq_no_ws(","));
+    (((keyword("match") >> disjunction()) & (seq_no_ws("{") >> (arm * (..)) << seq_no_ws("}")))
+        - |(value, arms)| Expr::Match(value, arms))
+        % "a valid match expression"
+}
+
+/// Run another file, optionally putting its definitions in a dictionary
+///
+/// `import "lib/paths.dune" as paths`
+pub fn import() -> Parser<Expr> {
+    (((keyword("import") >> string_body() << space()) & opt(keyword("as") >> ident()))
+        - |(path, name)| Expr::Import(path, name))
+        % "a valid import statement"
+}
+
+/// This matches the rest of the current line, without
+/// the whitespace around it
+pub fn rest_of_line() -> Parser<String> {
+    Parser::new(
+        |s: &str| {
+            let end = s.find('\n').unwrap_or(s.len());
+            Ok((s[..end].trim().to_string(), s[end..].to_string()))
+        },
+        String::from("the rest of the line"),
+    )
+}
+
+/// Define an abbreviation for a command. The command
+/// is the rest of the line, and is not parsed until it is used.
+///
+/// `alias gs = sh "git status"`
+pub fn alias() -> Parser<Expr> {
+    (((keyword("alias") >> ident()) & (seq_no_ws("=") >> rest_of_line()))
+        - |(name, command)| Expr::Alias(name, command))
+        % "a valid alias"
+}
+
+/// Remove an abbreviation for a command
+pub fn unalias() -> Parser<Expr> {
+    ((keyword("unalias") >> ident()) - Expr::Unalias) % "a valid unalias"
+}
+
+/// The words of the language that can't be the name of a program
+pub const RESERVED: &[&str] = &[
+    "if", "else", "while", "for", "in", "fn", "return", "break", "continue", "match", "import",
+    "as", "alias", "unalias", "and", "or", "true", "false", "none",
+];
+
+/// This matches a word that is given to a program: a string literal,
+/// or any other characters up to the next space, `;`, or `}`
+pub fn command_arg() -> Parser<String> {
+    let bare_word = Parser::new(
+        |s: &str| {
+            let end = s
+                .find(|c: char| c.is_whitespace() || c == ';' || c == '}')
+                .unwrap_or(s.len());
+            if end == 0 || s.starts_with('#') {
+                return honeycomb::Error::new(s.chars().take(1).collect::<String>(), "a word", s);
+            }
+            Ok((s[..end].to_string(), s[end..].to_string()))
+        },
+        String::from("a word"),
+    );
+    space() >> (string_body() | bare_word)
+}
+
+/// This runs a program with the words that follow it on the same line,
+/// such as `git status`. Lines that could be code are not commands.
+pub fn command_line() -> Parser<Expr> {
+    ((space() >> on_line(identifier() & (command_arg() * (1..))))
+        ^ |(name, args): (String, Vec<String>)| -> Result<Expr, ()> {
+            let is_code = RESERVED.contains(&name.as_str())
+                || BUILTINS.iter().any(|(builtin, _)| *builtin == name)
+                || args[0].starts_with(|c| "=([{.,)<>!&|".contains(c))
+                || args[0] == "and"
+                || args[0] == "or";
+            if is_code {
+                Err(())
+            } else {
+                Ok(Expr::Command(Identifier(Symbol::new(&name)), args))
+            }
+        })
+        % "a command"
+}
+
 /// A fundamental language expression
 pub fn expr() -> Parser<Expr> {
     opt(comment() * (..))
-        >> (((assignment() << opt(seq_no_ws(";"))) % "a valid assignment")
-            | while_loop()
-            | if_then_else()
-            | (function_def() - Expr::FunctionDef)
-            | (((value() - Expr::Value) << opt(seq_no_ws(";"))) % "a value"))
+        >> (spanned(
+            ((assignment() << opt(seq_no_ws(";"))) % "a valid assignment")
+                | while_loop()
+                | for_loop()
+                | if_then_else()
+                | match_expr()
+                | (import() << opt(seq_no_ws(";")))
+                | alias()
+                | (unalias() << opt(seq_no_ws(";")))
+                | (function_def() - Expr::FunctionDef)
+                | (return_stmt() << opt(seq_no_ws(";")))
+                | (break_stmt() << opt(seq_no_ws(";")))
+                | (continue_stmt() << opt(seq_no_ws(";")))
+                | (command_line() << opt(seq_no_ws(";")))
+                | (((disjunction() & opt(seq_no_ws(";"))) - value_statement) % "a value"),
+        ) - |(span, expr)| Expr::Spanned(span, Box::new(expr)))
         << opt(comment() * (..))
 }
 
+/// A value by itself is shown, unless it's followed by `;`
+fn value_statement((value, semicolon): (Value, Option<String>)) -> Expr {
+    match semicolon {
+        Some(_) => Expr::Silent(value),
+        None => Expr::Value(value),
+    }
+}
+
 /// A series of instructions enclosed with {}
 pub fn suite() -> Parser<Suite> {
     ((seq_no_ws("{") >> (expr() * (..)) << seq_no_ws("}")) - Suite)
@@ -196,10 +628,102 @@ pub fn suite() -> Parser<Suite> {
 
 /// Matches a comment in source code
 pub fn comment() -> Parser<()> {
-    (seq_no_ws("#") >> ((sym('\n').isnt() >> any()) * (..))) - |_| ()
+    (seq_no_ws("#") >> (char_where(|ch| ch != '\n') * (..))) - |_| ()
 }
 
-/// A series of expressions
+/// A series of expressions. This is only built once.
 pub fn program() -> Parser<Suite> {
-    ((expr() * (..)) - Suite) << eof()
+    built(|| ((expr() * (..)) - Suite) << (comment() * (..)) << eof())
+}
+
+/// Parse the source code of a program
+pub fn parse(source: &str) -> Result<Suite, Error> {
+    SOURCE_LENGTH.with(|length| length.set(source.len()));
+    SOURCE_COUNT.with(|count| count.set(count.get() + 1));
+    LINE_STARTS.with(|starts| {
+        *starts.borrow_mut() = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect()
+    });
+    program()
+        .parse(source)
+        .map_err(|e| Error::ParseError(format!("{:?}", e)))
+}
+
+/// Parse the source code of a script without running it, so that tools
+/// like linters and editors can work with its syntax tree. A shebang line
+/// is skipped, but its newline is kept so the spans stay the same.
+pub fn parse_ast(source: &str) -> Result<Suite, Error> {
+    if source.starts_with("#!") {
+        let newline = source.find('\n').unwrap_or(source.len());
+        parse(&format!("{}{}", " ".repeat(newline), &source[newline..]))
+    } else {
+        parse(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::Shell;
+
+    /// Run a program, and get the values left by its top level statements
+    fn run(source: &str) -> Vec<String> {
+        match Shell::new().eval(source) {
+            Ok(values) => values.iter().map(ToString::to_string).collect(),
+            Err(e) => panic!("{} failed: {}", source, e.report(source)),
+        }
+    }
+
+    #[test]
+    fn comparisons_call_the_comparison_builtins() {
+        let source = r#"
+            1 < 2
+            2 <= 1
+            "a" == "a"
+            1 != 1
+            3 > 2
+            2 >= 3
+            lt(1, 2) == (2 > 1)
+        "#;
+        assert_eq!(run(source), ["1", "0", "1", "0", "1", "0", "1"]);
+    }
+
+    #[test]
+    fn logical_operators_only_run_what_decides_the_result() {
+        // The undefined names would be errors if they were run
+        let source = r#"
+            0 && undefined_thing
+            1 || undefined_thing
+            1 && 2
+            0 || "right"
+            false and undefined_thing
+            true or undefined_thing
+            1 || 0 && 0
+            (1 || 0) && 0
+            1 < 2 && 2 < 3
+        "#;
+        assert_eq!(run(source), ["0", "1", "2", "right", "0", "1", "1", "0", "1"]);
+    }
+
+    #[test]
+    fn escape_sequences_in_strings_are_decoded() {
+        let source = r#"
+            "tab\tnew\nline\r"
+            "\"quoted\" \'single\' back\\slash\/"
+            "\0\b\f"
+            "\u{48}\u{1F600}"
+            "naïve café"
+        "#;
+        let expected = [
+            "tab\tnew\nline\r",
+            "\"quoted\" 'single' back\\slash/",
+            "\0\x08\x0C",
+            "H\u{1F600}",
+            "naïve café",
+        ];
+        assert_eq!(run(source), expected);
+        for source in [r#""\q""#, r#""\u{110000}""#, r#""\u{}""#] {
+            assert!(super::parse(source).is_err(), "{}", source);
+        }
+    }
 }
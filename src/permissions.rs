@@ -0,0 +1,189 @@
+use crate::tokens::Error;
+use std::fs::{set_permissions, Metadata};
+use std::path::Path;
+
+/// The permission bits of a file, like `0o755`. Windows only knows
+/// whether a file is read-only, so every file there is either
+/// `0o444` or `0o666`.
+#[cfg(unix)]
+pub fn mode_bits(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+/// The permission bits of a file, like `0o755`. Windows only knows
+/// whether a file is read-only, so every file there is either
+/// `0o444` or `0o666`.
+#[cfg(not(unix))]
+pub fn mode_bits(metadata: &Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o666
+    }
+}
+
+/// Work out the permission bits a file should have from a mode, which is
+/// either octal like `755` or symbolic like `u+x,go-w`, as `chmod` takes.
+/// Symbolic modes change the file's `current` bits.
+pub fn parse_mode(mode: &str, current: u32) -> Option<u32> {
+    if !mode.is_empty() && mode.chars().all(|ch| ch.is_digit(8)) {
+        return u32::from_str_radix(mode, 8)
+            .ok()
+            .filter(|bits| *bits <= 0o7777);
+    }
+
+    let mut bits = current;
+    for clause in mode.split(',') {
+        let mut chars = clause.chars().peekable();
+        // The users that the clause is about, which is everyone if none are given
+        let mut who = 0;
+        while let Some(ch) = chars.peek() {
+            who |= match ch {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => break,
+            };
+            chars.next();
+        }
+        if who == 0 {
+            who = 0o7777;
+        }
+
+        // There can be several operations, like `u+x-w`
+        let mut operations = 0;
+        while let Some(op) = chars.next() {
+            if !"+-=".contains(op) {
+                return None;
+            }
+            let mut changed = 0;
+            while let Some(ch) = chars.peek() {
+                changed |= match ch {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    _ => break,
+                };
+                chars.next();
+            }
+            changed &= who;
+            match op {
+                '+' => bits |= changed,
+                '-' => bits &= !changed,
+                _ => bits = bits & !who | changed,
+            }
+            operations += 1;
+        }
+        if operations == 0 {
+            return None;
+        }
+    }
+    Some(bits)
+}
+
+/// Give a file new permission bits. On Windows, a file is made
+/// read-only when its owner isn't allowed to write to it.
+pub fn set_mode(path: &Path, bits: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    let permissions = {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::Permissions::from_mode(bits)
+    };
+    #[cfg(not(unix))]
+    let permissions = {
+        let mut permissions = path.metadata()?.permissions();
+        permissions.set_readonly(bits & 0o200 == 0);
+        permissions
+    };
+    set_permissions(path, permissions)
+}
+
+/// Change the user that owns a file, and optionally its group, given
+/// as `user` or `user:group`. Either can be a name or an ID, and the
+/// user can be left out to only change the group, as in `:group`.
+#[cfg(unix)]
+pub fn change_owner(path: &Path, owner: &str) -> Result<(), Error> {
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+    let user = match user {
+        "" => None,
+        user => Some(
+            user.parse()
+                .ok()
+                .or_else(|| user_id(user))
+                .ok_or_else(|| Error::IoError(format!("chown: no such user {}", user)))?,
+        ),
+    };
+    let group = match group {
+        None | Some("") => None,
+        Some(group) => Some(
+            group
+                .parse()
+                .ok()
+                .or_else(|| group_id(group))
+                .ok_or_else(|| Error::IoError(format!("chown: no such group {}", group)))?,
+        ),
+    };
+    std::os::unix::fs::chown(path, user, group)
+        .map_err(|e| Error::IoError(format!("chown: {}: {}", path.to_string_lossy(), e)))
+}
+
+/// Files have no owners to change on this platform
+#[cfg(not(unix))]
+pub fn change_owner(_path: &Path, _owner: &str) -> Result<(), Error> {
+    Err(Error::IoError(String::from(
+        "chown: files have no owners on this platform",
+    )))
+}
+
+/// Look up the ID of a user by their name
+#[cfg(unix)]
+fn user_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut user: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut found = std::ptr::null_mut();
+    unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut user,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        );
+    }
+    if found.is_null() {
+        None
+    } else {
+        Some(user.pw_uid)
+    }
+}
+
+/// Look up the ID of a group by its name
+#[cfg(unix)]
+fn group_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut found = std::ptr::null_mut();
+    unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        );
+    }
+    if found.is_null() {
+        None
+    } else {
+        Some(group.gr_gid)
+    }
+}
@@ -0,0 +1,282 @@
+use crate::convert::DuneValue;
+use crate::interrupt::is_interrupted;
+use crate::shell::Shell;
+use crate::tokens::Error;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{read, write};
+use std::rc::Rc;
+use wasmi::errors::HostError;
+use wasmi::{
+    AsContext, AsContextMut, Caller, Config, Engine, Extern, ExternType, Instance, Linker, Memory,
+    Module, Store, TypedFunc, TypedResumableCall,
+};
+use xmachine::{Machine, Ref, Value};
+
+/// How much work a plugin does between checks for whether it should
+/// stop, roughly in instructions. A plugin's start function has to
+/// finish within this much work, since it can't be stopped and resumed.
+const FUEL_SLICE: u64 = 1_000_000;
+
+/// An error from a plugin can be a shell error, like
+/// a file that's outside of the sandbox
+impl HostError for Error {}
+
+/// The memory that a plugin shares with the shell, and
+/// the function that the shell calls to get space in it
+#[derive(Clone, Copy)]
+struct Exports {
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+}
+
+impl Exports {
+    fn of_instance(instance: &Instance, store: impl AsContext) -> Result<Self, wasmi::Error> {
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| wasmi::Error::new("the plugin doesn't export its memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+        Ok(Self { memory, alloc })
+    }
+
+    /// Get the exports of the plugin that is calling a function of the shell
+    fn of_caller(caller: &Caller<()>) -> Result<Self, wasmi::Error> {
+        let memory = caller
+            .get_export("memory")
+            .and_then(Extern::into_memory)
+            .ok_or_else(|| wasmi::Error::new("the plugin doesn't export its memory"))?;
+        let alloc = caller
+            .get_export("alloc")
+            .and_then(Extern::into_func)
+            .ok_or_else(|| wasmi::Error::new("the plugin doesn't export `alloc`"))?
+            .typed::<i32, i32>(caller)?;
+        Ok(Self { memory, alloc })
+    }
+
+    /// Read the bytes that the plugin gave the shell
+    fn read(&self, store: impl AsContext, ptr: i32, len: i32) -> Result<Vec<u8>, wasmi::Error> {
+        let mut bytes = vec![0; len as u32 as usize];
+        self.memory
+            .read(store, ptr as u32 as usize, &mut bytes)
+            .map_err(|e| wasmi::Error::new(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    /// Copy bytes into space that the plugin allocates for them,
+    /// and get where they are in the form that the plugin takes
+    fn give(&self, mut store: impl AsContextMut, bytes: &[u8]) -> Result<i64, wasmi::Error> {
+        let len = bytes.len() as i32;
+        // The plugin's allocator gets its own fuel, even
+        // if it's called at the end of a call's slice
+        store.as_context_mut().set_fuel(FUEL_SLICE)?;
+        let ptr = self.alloc.call(&mut store, len)?;
+        self.memory
+            .write(&mut store, ptr as u32 as usize, bytes)
+            .map_err(|e| wasmi::Error::new(e.to_string()))?;
+        Ok(pack(ptr, len))
+    }
+}
+
+/// A plugin that has been loaded, which its builtins share
+struct Plugin {
+    store: Store<()>,
+    exports: Exports,
+}
+
+/// Load a WebAssembly module as a plugin, and make a dictionary of the
+/// builtins that it exports. The module runs in its own memory, and
+/// can only reach the system through the functions the shell gives it.
+/// Its builtins can be stopped with Ctrl-C or `timeout` like other code.
+///
+/// The module exports its `memory`, and `alloc(len) -> ptr` for the shell
+/// to get space in it. Each other exported function that takes two `i32`s
+/// and returns an `i64` is a builtin. It's given where the JSON of a list
+/// of its arguments is, and returns where the JSON of its result is. A
+/// place in memory is given as a pointer and a length, and returned as
+/// the pointer in the high 32 bits and the length in the low 32 bits.
+///
+/// The module can import these functions from `dune`:
+/// - `print(ptr, len)` writes text to the shell's output.
+/// - `error(ptr, len)` stops the call with an error message.
+/// - `read_file(path_ptr, path_len) -> i64` reads a file into space that
+///   the shell allocates, and returns where it is.
+/// - `write_file(path_ptr, path_len, data_ptr, data_len)` writes a file.
+///
+/// Files are found like the shell's own builtins find them, relative to
+/// the current directory and only inside of the sandbox if there is one.
+pub fn load_plugin(name: &str, wasm: &[u8], m: &Machine) -> Result<Ref<Value>, Error> {
+    let error = |e: wasmi::Error| Error::ImportError(format!("{}: {}", name, e));
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, wasm).map_err(error)?;
+    let mut store = Store::new(&engine, ());
+    store.set_fuel(FUEL_SLICE).map_err(error)?;
+    let mut linker = Linker::new(&engine);
+    link(&mut linker).map_err(error)?;
+    let instance = linker
+        .instantiate_and_start(&mut store, &module)
+        .map_err(error)?;
+    let exports = Exports::of_instance(&instance, &store).map_err(error)?;
+
+    let mut builtins = vec![];
+    for export in module.exports() {
+        if !matches!(export.ty(), ExternType::Func(_)) || export.name() == "alloc" {
+            continue;
+        }
+        // Functions that aren't builtins can be exported for other reasons
+        if let Ok(function) = instance.get_typed_func::<(i32, i32), i64>(&store, export.name()) {
+            builtins.push((export.name().to_string(), function));
+        }
+    }
+
+    let plugin = Rc::new(RefCell::new(Plugin { store, exports }));
+    let mut dict = BTreeMap::new();
+    for (name, function) in builtins {
+        let calling = Rc::clone(&plugin);
+        let builtin_name = name.clone();
+        dict.insert(
+            name,
+            Value::function(
+                move |m| {
+                    // The first argument is on top of the stack
+                    let mut args = vec![];
+                    while let Some(arg) = m.pop() {
+                        args.push(DuneValue::from_value(&arg));
+                    }
+                    let result = args
+                        .into_iter()
+                        .collect::<Result<_, _>>()
+                        .and_then(|args| call(&calling, &builtin_name, function, args));
+                    match result {
+                        Ok(value) => m.push(value),
+                        Err(e) => e.raise(),
+                    }
+                },
+                m,
+            ),
+        );
+    }
+    Ok(Ref::new(Value::Tree(dict)))
+}
+
+/// Call a builtin of a plugin with its arguments, and get its result
+fn call(
+    plugin: &RefCell<Plugin>,
+    name: &str,
+    function: TypedFunc<(i32, i32), i64>,
+    args: Vec<DuneValue>,
+) -> Result<Ref<Value>, Error> {
+    let json = serde_json::to_string(&DuneValue::List(args))
+        .map_err(|e| Error::TypeError(e.to_string()))?;
+    let mut plugin = plugin.borrow_mut();
+    let Plugin { store, exports } = &mut *plugin;
+    let error = |e: &wasmi::Error| match e.downcast_ref::<Error>() {
+        Some(e) => e.clone(),
+        None => Error::ProcessError(format!("{}: {}", name, e)),
+    };
+    let args = exports
+        .give(&mut *store, json.as_bytes())
+        .map_err(|e| error(&e))?;
+    store.set_fuel(FUEL_SLICE).map_err(|e| error(&e))?;
+    let mut call = function
+        .call_resumable(&mut *store, unpack(args))
+        .map_err(|e| error(&e))?;
+    // Each time the plugin runs out of fuel, it's given more
+    // unless Ctrl-C was pressed or its time limit has passed
+    let result = loop {
+        call = match call {
+            TypedResumableCall::Finished(result) => break result,
+            TypedResumableCall::OutOfFuel(_) if is_interrupted() => return Err(Error::Interrupted),
+            TypedResumableCall::OutOfFuel(paused) => {
+                store.set_fuel(FUEL_SLICE).map_err(|e| error(&e))?;
+                paused.resume(&mut *store).map_err(|e| error(&e))?
+            }
+            TypedResumableCall::HostTrap(trap) => return Err(error(trap.host_error())),
+        };
+    };
+    let (ptr, len) = unpack(result);
+    let result = exports.read(&*store, ptr, len).map_err(|e| error(&e))?;
+
+    // A builtin that returns nothing returns none
+    if result.is_empty() {
+        return Ok(Value::none());
+    }
+    match serde_json::from_slice::<DuneValue>(&result) {
+        Ok(data) => Ok(data.into_value()),
+        Err(e) => Err(Error::ParseError(format!(
+            "{} returned invalid json: {}",
+            name, e
+        ))),
+    }
+}
+
+/// Give a plugin the functions that it can import from the shell
+fn link(linker: &mut Linker<()>) -> Result<(), wasmi::Error> {
+    linker.func_wrap(
+        "dune",
+        "print",
+        |caller: Caller<()>, ptr: i32, len: i32| -> Result<(), wasmi::Error> {
+            let text = read_text(&caller, ptr, len)?;
+            Shell::for_call().write(&text);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "dune",
+        "error",
+        |caller: Caller<()>, ptr: i32, len: i32| -> Result<(), wasmi::Error> {
+            let message = read_text(&caller, ptr, len)?;
+            Err(wasmi::Error::host(Error::ProcessError(message)))
+        },
+    )?;
+    linker.func_wrap(
+        "dune",
+        "read_file",
+        |mut caller: Caller<()>, ptr: i32, len: i32| -> Result<i64, wasmi::Error> {
+            let path = read_text(&caller, ptr, len)?;
+            let file = Shell::for_call()
+                .resolve(&path)
+                .map_err(wasmi::Error::host)?;
+            let contents = read(&file).map_err(|e| {
+                wasmi::Error::host(Error::IoError(format!("read_file: {}: {}", path, e)))
+            })?;
+            Exports::of_caller(&caller)?.give(&mut caller, &contents)
+        },
+    )?;
+    linker.func_wrap(
+        "dune",
+        "write_file",
+        |caller: Caller<()>,
+         path_ptr: i32,
+         path_len: i32,
+         data_ptr: i32,
+         data_len: i32|
+         -> Result<(), wasmi::Error> {
+            let path = read_text(&caller, path_ptr, path_len)?;
+            let contents = Exports::of_caller(&caller)?.read(&caller, data_ptr, data_len)?;
+            let file = Shell::for_call()
+                .resolve(&path)
+                .map_err(wasmi::Error::host)?;
+            write(&file, contents).map_err(|e| {
+                wasmi::Error::host(Error::IoError(format!("write_file: {}: {}", path, e)))
+            })
+        },
+    )?;
+    Ok(())
+}
+
+/// Read text that a plugin gave the shell
+fn read_text(caller: &Caller<()>, ptr: i32, len: i32) -> Result<String, wasmi::Error> {
+    let bytes = Exports::of_caller(caller)?.read(caller, ptr, len)?;
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// Put a pointer and a length together into the `i64` that a plugin returns
+fn pack(ptr: i32, len: i32) -> i64 {
+    ((ptr as u32 as i64) << 32) | len as u32 as i64
+}
+
+fn unpack(place: i64) -> (i32, i32) {
+    ((place >> 32) as i32, place as i32)
+}
@@ -0,0 +1,242 @@
+use crate::tokens::Error;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::process::Child;
+use std::rc::Rc;
+use std::thread::{spawn, JoinHandle};
+use xmachine::{Machine, Ref, Value};
+
+/// A process running on the system, as listed by `ps`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub name: String,
+    /// The percentage of one CPU the process has used since it started
+    pub cpu: f64,
+    /// The percentage of the system's memory the process is using
+    pub mem: f64,
+}
+
+/// List the processes running on the system
+#[cfg(target_os = "linux")]
+pub fn processes() -> Result<Vec<ProcessInfo>, Error> {
+    use std::fs::{read_dir, read_to_string};
+
+    let error = |e: std::io::Error| Error::IoError(format!("ps: {}", e));
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as f64;
+    let uptime = read_to_string("/proc/uptime")
+        .map_err(error)?
+        .split_whitespace()
+        .next()
+        .and_then(|seconds| seconds.parse::<f64>().ok())
+        .unwrap_or(0.0);
+    // The total memory is given in kilobytes
+    let total_memory = read_to_string("/proc/meminfo")
+        .map_err(error)?
+        .lines()
+        .find(|line| line.starts_with("MemTotal:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|kb| kb.parse::<f64>().ok())
+        .map(|kb| kb * 1024.0)
+        .unwrap_or(0.0);
+
+    let mut processes = vec![];
+    for entry in read_dir("/proc").map_err(error)?.flatten() {
+        let pid = match entry.file_name().to_str().and_then(|pid| pid.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // A process can exit while the list is being made
+        let stat = match read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        // The name is in parentheses, and can contain spaces or parentheses itself
+        let (open, close) = match (stat.find('('), stat.rfind(')')) {
+            (Some(open), Some(close)) if open < close => (open, close),
+            _ => continue,
+        };
+        let name = stat[open + 1..close].to_string();
+        // The fields after the name, starting from the process state
+        let fields = stat[close + 1..].split_whitespace().collect::<Vec<&str>>();
+        let field = |n: usize| -> f64 { fields.get(n).and_then(|f| f.parse().ok()).unwrap_or(0.0) };
+
+        let cpu_time = (field(11) + field(12)) / ticks;
+        let running_time = uptime - field(19) / ticks;
+        let cpu = if running_time > 0.0 {
+            100.0 * cpu_time / running_time
+        } else {
+            0.0
+        };
+        let mem = if total_memory > 0.0 {
+            100.0 * field(21) * page_size / total_memory
+        } else {
+            0.0
+        };
+        processes.push(ProcessInfo {
+            pid,
+            name,
+            cpu,
+            mem,
+        });
+    }
+    processes.sort_by_key(|process| process.pid);
+    Ok(processes)
+}
+
+/// List the processes running on the system
+#[cfg(not(target_os = "linux"))]
+pub fn processes() -> Result<Vec<ProcessInfo>, Error> {
+    Err(Error::ProcessError(String::from(
+        "ps is not supported on this platform",
+    )))
+}
+
+/// Get the number of a signal from its name, such as `TERM` or `SIGKILL`
+#[cfg(unix)]
+pub fn signal_number(name: &str) -> Option<i32> {
+    let name = name.to_uppercase();
+    Some(match name.strip_prefix("SIG").unwrap_or(&name) {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "ALRM" => libc::SIGALRM,
+        "TERM" => libc::SIGTERM,
+        "CONT" => libc::SIGCONT,
+        "STOP" => libc::SIGSTOP,
+        "TSTP" => libc::SIGTSTP,
+        _ => return None,
+    })
+}
+
+/// Get the number of a signal from its name, such as `TERM` or `SIGKILL`
+#[cfg(not(unix))]
+pub fn signal_number(_name: &str) -> Option<i32> {
+    None
+}
+
+/// The signal that `kill` sends when none is given
+#[cfg(unix)]
+pub const DEFAULT_SIGNAL: i32 = libc::SIGTERM;
+#[cfg(not(unix))]
+pub const DEFAULT_SIGNAL: i32 = 15;
+
+/// Send a signal to a process
+#[cfg(unix)]
+pub fn kill(pid: u32, signal: i32) -> Result<(), Error> {
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(Error::ProcessError(format!(
+            "kill: {}: {}",
+            pid,
+            std::io::Error::last_os_error()
+        )))
+    }
+}
+
+/// Send a signal to a process
+#[cfg(not(unix))]
+pub fn kill(_pid: u32, _signal: i32) -> Result<(), Error> {
+    Err(Error::ProcessError(String::from(
+        "kill is not supported on this platform",
+    )))
+}
+
+/// A program started by `spawn` that is running alongside the shell
+struct Job {
+    name: String,
+    child: Child,
+    /// Reads the output of the program as it runs, so that
+    /// the program doesn't block on a full pipe
+    reader: Option<JoinHandle<Vec<u8>>>,
+    output: String,
+}
+
+impl Job {
+    /// Wait for the program to exit, and get its exit code.
+    /// A program stopped by a signal has no exit code.
+    fn wait(&mut self) -> Result<Option<i32>, Error> {
+        let status = self
+            .child
+            .wait()
+            .map_err(|e| Error::ProcessError(format!("{}: {}", self.name, e)))?;
+        if let Some(reader) = self.reader.take() {
+            let output = reader.join().unwrap_or_default();
+            self.output = String::from_utf8_lossy(&output).to_string();
+        }
+        Ok(status.code())
+    }
+}
+
+/// Make a handle for a running program, which is a dictionary with its
+/// `pid` and the functions `wait`, `kill`, and `stdout`. The program's
+/// output is collected in the background, and `stdout` waits for it
+/// to exit before returning everything it printed.
+pub fn job_handle(name: &str, mut child: Child, m: &Machine) -> Ref<Value> {
+    let reader = child.stdout.take().map(|mut stdout| {
+        spawn(move || {
+            let mut output = vec![];
+            let _ = stdout.read_to_end(&mut output);
+            output
+        })
+    });
+    let pid = child.id();
+    let job = Rc::new(RefCell::new(Job {
+        name: name.to_string(),
+        child,
+        reader,
+        output: String::new(),
+    }));
+
+    let mut handle = BTreeMap::new();
+    handle.insert(String::from("pid"), Value::number(pid as f64));
+
+    let waiting = Rc::clone(&job);
+    handle.insert(
+        String::from("wait"),
+        Value::function(
+            move |m| match waiting.borrow_mut().wait() {
+                Ok(Some(code)) => m.push(Value::number(code as f64)),
+                Ok(None) => m.push(Value::none()),
+                Err(e) => e.raise(),
+            },
+            m,
+        ),
+    );
+
+    let killing = Rc::clone(&job);
+    handle.insert(
+        String::from("kill"),
+        Value::function(
+            move |_| {
+                let mut job = killing.borrow_mut();
+                if let Err(e) = job.child.kill() {
+                    Error::ProcessError(format!("{}: {}", job.name, e)).raise();
+                }
+            },
+            m,
+        ),
+    );
+
+    let reading = job;
+    handle.insert(
+        String::from("stdout"),
+        Value::function(
+            move |m| {
+                let mut job = reading.borrow_mut();
+                match job.wait() {
+                    Ok(_) => m.push(Value::string(job.output.clone())),
+                    Err(e) => e.raise(),
+                }
+            },
+            m,
+        ),
+    );
+    Ref::new(Value::Tree(handle))
+}
@@ -0,0 +1,206 @@
+use crate::parser::parse;
+use crate::shell::{Frame, Shell};
+use crate::tokens::{Error, Span};
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+use xmachine::{Ref, Value};
+
+thread_local! {
+    /// What the code being profiled has spent its time on, if any code is
+    static PROFILER: RefCell<Option<Profiler>> = const { RefCell::new(None) };
+}
+
+/// The most lines and functions that a report shows
+const REPORT_LENGTH: usize = 20;
+
+/// How many times something ran, and how long it took altogether
+#[derive(Clone, Copy, Default)]
+struct Timing {
+    count: usize,
+    time: Duration,
+}
+
+/// Records the time spent on each line of a script and in each function
+struct Profiler {
+    /// The script being profiled, and where its statements are. Time spent
+    /// in code from other files, like the standard library, counts toward
+    /// the line of the script that ran it. Profiling a function without
+    /// its script only times the functions it calls.
+    script: Option<(String, HashSet<(usize, usize)>)>,
+    lines: BTreeMap<usize, Timing>,
+    functions: BTreeMap<String, Timing>,
+    /// The line that is running, and when it started
+    current: Option<(usize, Instant)>,
+    /// The lines that called the functions that are running, innermost
+    /// last. Once a function returns, the time goes to its caller again.
+    callers: Vec<Option<usize>>,
+    started: Instant,
+}
+
+/// Run a script, and then report the lines and functions
+/// that it spent the most time on to the user
+pub fn profile(shell: &mut Shell, source: &str) -> Result<(), Error> {
+    let statements = parse(source)?
+        .statements()
+        .iter()
+        .map(|span| (span.start, span.end))
+        .collect();
+    profile_with(shell, Some((source.to_string(), statements)), |shell| {
+        shell.execute_source(source)
+    })
+}
+
+/// Call the function on top of the stack, and then report
+/// the functions that it spent the most time in
+pub fn profile_call(shell: &mut Shell, function: Ref<Value>) -> Result<(), Error> {
+    profile_with(shell, None, |shell| {
+        shell.machine.push(function);
+        shell.call()
+    })
+}
+
+/// Profile some code, and report what it spent its time on even if it fails.
+/// Code that is already being profiled stops being timed until this is done.
+fn profile_with(
+    shell: &mut Shell,
+    script: Option<(String, HashSet<(usize, usize)>)>,
+    run: impl FnOnce(&mut Shell) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let profiler = Profiler {
+        script,
+        lines: BTreeMap::new(),
+        functions: BTreeMap::new(),
+        current: None,
+        callers: vec![],
+        started: Instant::now(),
+    };
+    let outer = PROFILER.with(|slot| slot.borrow_mut().replace(profiler));
+    let result = run(shell);
+    let profiler = PROFILER.with(|slot| std::mem::replace(&mut *slot.borrow_mut(), outer));
+    if let Some(mut profiler) = profiler {
+        profiler.finish_line();
+        shell.write_err(&profiler.report());
+    }
+    result
+}
+
+/// Called before each statement is run, so that the time until
+/// the next statement counts toward the line of this one
+pub fn record_statement(span: Span) {
+    PROFILER.with(|slot| {
+        if let Some(profiler) = slot.borrow_mut().as_mut() {
+            profiler.record_statement(span);
+        }
+    });
+}
+
+/// Get the time that a function is called at, if the code is being profiled
+pub fn start_call() -> Option<Instant> {
+    PROFILER.with(|slot| {
+        let mut slot = slot.borrow_mut();
+        let profiler = slot.as_mut()?;
+        let line = profiler.current.map(|(line, _)| line);
+        profiler.callers.push(line);
+        Some(Instant::now())
+    })
+}
+
+/// Count the time spent in the innermost function of the call stack. A
+/// recursive call is counted, but its time already counts toward the
+/// outer call of the same function, so it isn't added twice.
+pub fn end_call(call_stack: &[Frame], started: Option<Instant>) {
+    let (started, (frame, callers)) = match (started, call_stack.split_last()) {
+        (Some(started), Some(frames)) => (started, frames),
+        _ => return,
+    };
+    let is_recursive = callers
+        .iter()
+        .any(|caller| caller.function == frame.function);
+    PROFILER.with(|slot| {
+        if let Some(profiler) = slot.borrow_mut().as_mut() {
+            let timing = profiler
+                .functions
+                .entry(frame.function.clone())
+                .or_default();
+            timing.count += 1;
+            if !is_recursive {
+                timing.time += started.elapsed();
+            }
+            if let Some(line) = profiler.callers.pop() {
+                profiler.finish_line();
+                profiler.current = line.map(|line| (line, Instant::now()));
+            }
+        }
+    });
+}
+
+impl Profiler {
+    fn record_statement(&mut self, span: Span) {
+        match &self.script {
+            Some((_, statements)) if statements.contains(&(span.start, span.end)) => {}
+            _ => return,
+        }
+        self.finish_line();
+        self.lines.entry(span.line).or_default().count += 1;
+        self.current = Some((span.line, Instant::now()));
+    }
+
+    /// Count the time since the line that is running started toward it
+    fn finish_line(&mut self) {
+        if let Some((line, started)) = self.current.take() {
+            self.lines.entry(line).or_default().time += started.elapsed();
+        }
+    }
+
+    /// Describe the lines and functions that took the most time, slowest first
+    fn report(&self) -> String {
+        let mut report = String::new();
+        if let Some((source, _)) = &self.script {
+            let lines = source.lines().collect::<Vec<&str>>();
+            report += &format!("{:>12} {:>8}  line\n", "time", "runs");
+            for (line, timing) in slowest(&self.lines) {
+                let text = lines.get(line - 1).map_or("", |text| text.trim());
+                report += &format!(
+                    "{:>12} {:>8}  {:>4} | {}\n",
+                    format_time(timing.time),
+                    timing.count,
+                    line,
+                    text
+                );
+            }
+            report += "\n";
+        }
+        if !self.functions.is_empty() {
+            report += &format!("{:>12} {:>8}  function\n", "time", "calls");
+            for (name, timing) in slowest(&self.functions) {
+                report += &format!(
+                    "{:>12} {:>8}  {}\n",
+                    format_time(timing.time),
+                    timing.count,
+                    name
+                );
+            }
+            report += "\n";
+        }
+        report += &format!("total {}\n", format_time(self.started.elapsed()));
+        report
+    }
+}
+
+/// The things that took the most time, slowest first
+fn slowest<K: Clone>(timings: &BTreeMap<K, Timing>) -> Vec<(K, Timing)> {
+    let mut timings = timings
+        .iter()
+        .map(|(key, timing)| (key.clone(), *timing))
+        .collect::<Vec<_>>();
+    timings.sort_by_key(|(_, timing)| Reverse(timing.time));
+    timings.truncate(REPORT_LENGTH);
+    timings
+}
+
+/// Describe a short amount of time in milliseconds, like `12.345ms`
+fn format_time(time: Duration) -> String {
+    format!("{:.3}ms", time.as_secs_f64() * 1000.0)
+}
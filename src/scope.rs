@@ -0,0 +1,62 @@
+use crate::symbol::Symbol;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use xmachine::{Ref, Value};
+
+/// The variables defined by a block of code, such as the body of a
+/// function, along with the scope that the block was written in.
+/// Functions keep the scope that they were defined in, so they can
+/// use the variables around them, even ones defined after them.
+#[derive(Default)]
+pub struct Scope {
+    variables: RefCell<BTreeMap<Symbol, Ref<Value>>>,
+    parent: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    /// Create a scope inside of another scope
+    pub fn new(parent: Option<Rc<Scope>>) -> Rc<Self> {
+        Rc::new(Self {
+            variables: RefCell::new(BTreeMap::new()),
+            parent,
+        })
+    }
+
+    /// Find a variable in this scope or any of the scopes around it
+    pub fn get(&self, name: &str) -> Option<Ref<Value>> {
+        match self.variables.borrow().get(name) {
+            Some(value) => Some(Ref::clone(value)),
+            None => self.parent.as_ref().and_then(|parent| parent.get(name)),
+        }
+    }
+
+    /// Define a variable in this scope, hiding any
+    /// variable with the same name in the scopes around it
+    pub fn define(&self, name: impl Into<Symbol>, value: Ref<Value>) {
+        self.variables.borrow_mut().insert(name.into(), value);
+    }
+
+    /// The variables defined in this scope, but not the scopes around it
+    pub fn variables(&self) -> BTreeMap<String, Ref<Value>> {
+        self.variables
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.to_string(), Ref::clone(value)))
+            .collect()
+    }
+
+    /// The names of all the variables that can be used in this scope
+    pub fn names(&self) -> Vec<String> {
+        let mut names = match &self.parent {
+            Some(parent) => parent.names(),
+            None => vec![],
+        };
+        for name in self.variables.borrow().keys() {
+            if !names.iter().any(|other| other == name.as_str()) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+}
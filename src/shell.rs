@@ -1,241 +1,2847 @@
-use crate::parser::program;
-use crate::tokens::Execute;
+use crate::color::{color_code, colors_enabled, paint, style_code, COLORS, STYLES};
+use crate::compiler::{compile, Code};
+use crate::convert::DuneValue;
+use crate::disk::{disk_containing, disk_usage, disks, DiskSpace};
+use crate::frecency::{ranked_matches, record_visit, Visits};
+use crate::display::{columns, pretty, table, terminal_width};
+use crate::hash::{hash_bytes, hash_reader};
+use crate::help::{help_topic, list_all_help};
+use crate::highlight::Theme;
+use crate::input::{Line, LineEditor, ShellIo};
+use crate::interrupt::{
+    catch_interrupts, is_ctrl_c_pressed, is_interrupted, reset_interrupt, restore_deadline,
+    set_deadline,
+};
+use crate::menu::{read_password, select};
+use crate::parser::{parse, program};
+use crate::permissions::{change_owner, mode_bits, parse_mode, set_mode};
+use crate::plugin::load_plugin;
+use crate::process::{job_handle, kill, processes, signal_number, DEFAULT_SIGNAL};
+use crate::profiler::{end_call, start_call};
+use crate::scope::Scope;
+use crate::stdlib::STDLIB;
+use crate::tokens::{is_true, most_similar, Error, Execute, Signal, Span, Suite, BUILTINS};
 use crate::{LOGO, INFO};
-use read_input::prelude::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use regex::{Captures, Regex};
 use xmachine::{Machine, Ref, Value};
 
 use dirs::home_dir;
-use std::process::{Command, Stdio};
-use std::fs::{create_dir_all, read_dir, remove_dir_all, remove_file, rename, write};
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::env::{remove_var, set_var, split_paths, var, var_os, vars_os};
+use std::ffi::OsString;
+use std::io::{stdout, BufRead, BufReader, ErrorKind, IsTerminal, Read};
+use std::process::{Command, ExitStatus, Stdio};
+use std::str::from_utf8;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::fs::{
+    copy, create_dir_all, read, read_dir, read_to_string, remove_dir_all, remove_file, rename,
+    write, File, Metadata,
+};
+use std::path::{is_separator, Component, Path, PathBuf};
+use std::mem::{replace, take};
+use std::rc::Rc;
 
-fn to_string(path: &PathBuf) -> String {
-    path.to_str().unwrap().to_string()
+/// How many results before the last one are kept, as `_1` to `_9`
+const RESULT_HISTORY: usize = 9;
+
+/// Is this the name of a result kept by `record_result`, like `_` or `_2`?
+pub fn is_result_name(name: &str) -> bool {
+    name.strip_prefix('_')
+        .is_some_and(|digits| digits.chars().all(|ch| ch.is_ascii_digit()))
+}
+
+/// A builtin function that operates on the machine directly
+pub type MachineFn = fn(&mut Machine);
+
+/// The input and output of a shell, which is shared with
+/// the builtin functions that read input or print output
+type SharedIo = Rc<RefCell<Box<dyn ShellIo>>>;
+
+/// Get a path as text. Parts of it that aren't valid unicode are
+/// replaced, rather than stopping the shell.
+fn to_string(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+/// A call to a function that has not returned yet
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// The name that the function was called by
+    pub function: String,
+    /// Where the function was called
+    pub span: Span,
 }
 
 #[derive(Clone)]
 pub struct Shell {
     pub directory: PathBuf,
+    /// The directory that the shell was in before the last `cd`
+    pub previous_directory: Option<PathBuf>,
+    /// The directories saved by `pushd`, with the most recent last
+    pub directory_stack: Vec<PathBuf>,
     pub machine: Machine,
-    pub is_done: bool
+    /// The variables defined by the user
+    pub scope: Rc<Scope>,
+    /// The functions that are currently being called, innermost last
+    pub call_stack: Vec<Frame>,
+    pub signal: Option<Signal>,
+    pub is_done: bool,
+    /// The status that the shell exits with, set by `exit(status)`
+    pub exit_status: i32,
+    /// Whether a program that fails is an error that stops the script
+    pub is_strict: bool,
+    /// Whether the prompt loop is running, so the user can be asked questions
+    is_interactive: bool,
+    io: SharedIo,
+    /// The only directory that a sandboxed shell can access
+    sandbox: Option<PathBuf>,
+    /// The script being run and the files it is importing, innermost last
+    files: Vec<PathBuf>,
+    /// Abbreviations for commands, which are expanded
+    /// when they begin a command entered in the REPL
+    aliases: BTreeMap<String, String>,
+    /// The `.dune-env` files that are in effect, outermost first
+    dir_envs: Vec<DirEnv>,
+    /// The directory that was last checked for a `.dune-env` file
+    env_checked: Option<PathBuf>,
+    /// The last list of names made by `ls`, and the directory it listed.
+    /// When that list is printed, it is shown in columns.
+    listing: Option<(Ref<Value>, PathBuf)>,
+    /// The directory that the REPL last counted a visit to, for `z`
+    visited: Option<PathBuf>,
+    /// Whether visits are still counted, which stops
+    /// the first time that they can't be saved
+    counts_visits: bool,
+}
+
+/// The changes made by a directory's `.dune-env` file, which
+/// are undone when the shell leaves that directory
+#[derive(Clone)]
+struct DirEnv {
+    directory: PathBuf,
+    /// The environment variables that were changed, with their old values
+    vars: Vec<(OsString, Option<OsString>)>,
+    /// The aliases that were changed, with their old commands
+    aliases: Vec<(String, Option<String>)>,
+}
+
+thread_local! {
+    /// The shells that are calling functions, with the innermost
+    /// caller last. Functions are only given the machine that calls
+    /// them, so this is how they find the rest of the caller's state.
+    static CALLERS: RefCell<Vec<Shell>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl Shell {
-    pub fn new() -> Self {
-        Self {
-            directory: home_dir().unwrap(),
-            machine: machine(),
-            is_done: false
+impl Shell {
+    pub fn new() -> Self {
+        let io: SharedIo = Rc::new(RefCell::new(Box::new(LineEditor::new())));
+        Self {
+            directory: home_dir().unwrap_or_default(),
+            previous_directory: None,
+            directory_stack: vec![],
+            machine: machine(&io),
+            scope: Scope::new(None),
+            call_stack: vec![],
+            signal: None,
+            is_done: false,
+            exit_status: 0,
+            is_strict: false,
+            is_interactive: false,
+            io,
+            sandbox: None,
+            files: vec![],
+            aliases: BTreeMap::new(),
+            dir_envs: vec![],
+            env_checked: None,
+            listing: None,
+            visited: None,
+            counts_visits: true,
+        }
+    }
+
+    /// Create a shell that can only access the files inside of a
+    /// directory, and that cannot run other programs. This allows
+    /// scripts that aren't trusted to be run safely.
+    pub fn sandboxed(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let root = root.canonicalize().unwrap_or(root);
+        Self {
+            directory: root.clone(),
+            sandbox: Some(root),
+            ..Self::new()
+        }
+    }
+
+    /// Create a shell for running a function called by the innermost
+    /// calling shell, which shares its directory and settings
+    pub fn for_call() -> Self {
+        CALLERS.with(|callers| match callers.borrow().last() {
+            Some(caller) => caller.clone(),
+            None => Self::new(),
+        })
+    }
+
+    /// Give the state of a shell made by `for_call` back to the caller
+    pub fn return_to_caller(&self) {
+        CALLERS.with(|callers| {
+            if let Some(caller) = callers.borrow_mut().last_mut() {
+                caller.directory = self.directory.clone();
+                caller.previous_directory = self.previous_directory.clone();
+                caller.directory_stack = self.directory_stack.clone();
+                caller.aliases = self.aliases.clone();
+                caller.dir_envs = self.dir_envs.clone();
+                caller.env_checked = self.env_checked.clone();
+                caller.is_done = self.is_done;
+                caller.exit_status = self.exit_status;
+                caller.is_strict = self.is_strict;
+            }
+        });
+    }
+
+    /// Call the function on top of the stack with the values below it
+    pub fn call(&mut self) -> Result<(), Error> {
+        let caller = Self {
+            directory: self.directory.clone(),
+            previous_directory: self.previous_directory.clone(),
+            directory_stack: self.directory_stack.clone(),
+            machine: Machine::new(),
+            scope: Rc::clone(&self.scope),
+            call_stack: self.call_stack.clone(),
+            signal: None,
+            is_done: false,
+            exit_status: 0,
+            is_strict: self.is_strict,
+            is_interactive: self.is_interactive,
+            io: Rc::clone(&self.io),
+            sandbox: self.sandbox.clone(),
+            files: self.files.clone(),
+            aliases: self.aliases.clone(),
+            dir_envs: self.dir_envs.clone(),
+            env_checked: self.env_checked.clone(),
+            listing: None,
+            visited: None,
+            counts_visits: self.counts_visits,
+        };
+        CALLERS.with(|callers| callers.borrow_mut().push(caller));
+        self.machine.call();
+        // The function can change directories, which can run their
+        // `.dune-env` files, or exit the shell
+        if let Some(caller) = CALLERS.with(|callers| callers.borrow_mut().pop()) {
+            self.directory = caller.directory;
+            self.previous_directory = caller.previous_directory;
+            self.directory_stack = caller.directory_stack;
+            self.aliases = caller.aliases;
+            self.dir_envs = caller.dir_envs;
+            self.env_checked = caller.env_checked;
+            self.is_strict = caller.is_strict;
+            if caller.is_done {
+                self.is_done = true;
+                self.exit_status = caller.exit_status;
+            }
+        }
+
+        match Error::take_raised() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Call the function on top of the stack with the values below it,
+    /// keeping track of it in the call stack until it returns
+    pub fn call_in_frame(&mut self, frame: Frame) -> Result<(), Error> {
+        self.call_stack.push(frame);
+        let started = start_call();
+        let result = self.call();
+        end_call(&self.call_stack, started);
+        self.call_stack.pop();
+        result
+    }
+
+    /// Get the value of a variable defined by the user,
+    /// or of a builtin if there is no such variable
+    pub fn get_var(&self, name: &str) -> Option<Ref<Value>> {
+        self.scope
+            .get(name)
+            .or_else(|| self.machine.registers.get(name).map(Ref::clone))
+    }
+
+    /// The names of all of the variables and builtins
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.scope.names();
+        names.extend(self.machine.registers.keys().cloned());
+        names.extend(BUILTINS.iter().map(|(name, _)| name.to_string()));
+        names
+    }
+
+    /// Find the variable, builtin, or program on `PATH` whose name
+    /// is closest to a name that isn't any of them
+    pub fn similar_command(&self, name: &str) -> Option<String> {
+        let mut names = self.names();
+        names.extend(program_names());
+        most_similar(name, names)
+    }
+
+    /// Find the path that a path given to a command refers to. Relative
+    /// paths are relative to the current directory, and `~` is the home
+    /// directory, or the root of a sandbox. The `.` and `..` in a path are
+    /// removed even if some of its directories don't exist yet, but its
+    /// symbolic links are kept so that commands can work on the links
+    /// themselves. A sandboxed shell does not allow paths outside of its
+    /// directory.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, Error> {
+        let path = match path.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with(is_separator) => {
+                match self.sandbox.clone().or_else(home_dir) {
+                    Some(home) => home.join(rest.trim_start_matches(is_separator)),
+                    None => PathBuf::from(path),
+                }
+            }
+            _ => PathBuf::from(path),
+        };
+        let result = normalize(&self.directory.join(path));
+        match &self.sandbox {
+            Some(root) if !real_path(&result).starts_with(root) => Err(Error::PermissionDenied(
+                format!("{} is outside of the sandbox", to_string(&result)),
+            )),
+            _ => Ok(result),
+        }
+    }
+
+    /// Define the parts of the standard library that are written in dune
+    pub fn load_stdlib(&mut self) -> Result<(), Error> {
+        for (name, source) in STDLIB {
+            let result = parse(source).and_then(|suite| suite.execute(self));
+            self.clear_stack();
+            result.map_err(|e| e.in_function(name))?;
+        }
+        Ok(())
+    }
+
+    /// Set the script that is being run, so that the
+    /// files it imports are found relative to it
+    pub fn set_script(&mut self, path: &str) {
+        self.files = vec![real_path(&self.directory.join(path))];
+    }
+
+    /// Run another file. Its definitions are put in the current scope,
+    /// or in a dictionary with the given name. Paths are relative to the
+    /// file doing the importing, or to the current directory in the REPL.
+    pub fn import(&mut self, path: &str, name: Option<&str>) -> Result<(), Error> {
+        let file = match self.files.last().and_then(|file| file.parent()) {
+            Some(dir) => self.resolve(&to_string(&dir.join(path)))?,
+            None => self.resolve(path)?,
+        };
+        let file = real_path(&file);
+        if self.files.contains(&file) {
+            return Err(Error::ImportError(format!(
+                "{} is already being imported",
+                to_string(&file)
+            )));
+        }
+        let source = read_to_string(&file)
+            .map_err(|e| Error::IoError(format!("import: {}: {}", to_string(&file), e)))?;
+        let suite = parse(&source).map_err(|e| e.in_function(&to_string(&file)))?;
+
+        let scope = match name {
+            Some(_) => Scope::new(None),
+            None => Rc::clone(&self.scope),
+        };
+        let outer_scope = replace(&mut self.scope, Rc::clone(&scope));
+        let height = self.machine.stack.len();
+        self.files.push(file.clone());
+        let result = suite.execute(self);
+        self.files.pop();
+        self.machine.stack.truncate(height);
+        self.signal = None;
+        self.scope = outer_scope;
+        result.map_err(|e| e.in_function(&to_string(&file)))?;
+
+        if let Some(name) = name {
+            self.scope
+                .define(name, Ref::new(Value::Tree(scope.variables())));
+        }
+        Ok(())
+    }
+
+    /// Read input from and write output to somewhere other than the terminal
+    pub fn set_io(&mut self, io: impl ShellIo + 'static) {
+        *self.io.borrow_mut() = Box::new(io);
+    }
+
+    pub fn run(&mut self) {
+        catch_interrupts();
+        self.load_rc();
+        self.repl();
+    }
+
+    /// Run the user's `~/.dunerc`, if they have one. A sandboxed
+    /// shell can't read it, since it is outside of the sandbox.
+    fn load_rc(&mut self) {
+        let rc = match home_dir() {
+            Some(home) if self.sandbox.is_none() => home.join(".dunerc"),
+            _ => return,
+        };
+        if rc.is_file() {
+            if let Err(e) = self.import(&to_string(&rc), None) {
+                self.write_err(&format!("Error: {}\n", e.report("")));
+            }
+        }
+    }
+
+    /// Run the `.dune-env` file of the directory the shell has entered,
+    /// and undo the changes made by the files of directories it has left.
+    /// A file is only run once the user has allowed it.
+    fn update_dir_env(&mut self) {
+        if self.sandbox.is_some() || self.env_checked.as_ref() == Some(&self.directory) {
+            return;
+        }
+        self.env_checked = Some(self.directory.clone());
+
+        while let Some(env) = self.dir_envs.last() {
+            if self.directory.starts_with(&env.directory) {
+                break;
+            }
+            if let Some(env) = self.dir_envs.pop() {
+                for (name, value) in env.vars.into_iter().rev() {
+                    match value {
+                        Some(value) => set_var(name, value),
+                        None => remove_var(name),
+                    }
+                }
+                for (name, command) in env.aliases.into_iter().rev() {
+                    match command {
+                        Some(command) => self.alias(&name, &command),
+                        None => self.unalias(&name),
+                    }
+                }
+            }
+        }
+
+        let file = self.directory.join(".dune-env");
+        let loaded = self
+            .dir_envs
+            .iter()
+            .any(|env| env.directory == self.directory);
+        if loaded || !file.is_file() || !self.allow_dir_env(&file) {
+            return;
+        }
+        let old_vars = vars_os().collect::<BTreeMap<OsString, OsString>>();
+        let old_aliases = self.aliases.clone();
+        if let Err(e) = self.import(&to_string(&file), None) {
+            self.write_err(&format!("Error: {}\n", e.report("")));
+        }
+        // Whatever the file changed is recorded, even if it failed partway
+        let new_vars = vars_os().collect::<BTreeMap<OsString, OsString>>();
+        let new_aliases = self.aliases.clone();
+        self.dir_envs.push(DirEnv {
+            directory: self.directory.clone(),
+            vars: changes(&old_vars, &new_vars),
+            aliases: changes(&old_aliases, &new_aliases),
+        });
+    }
+
+    /// Check whether the user has allowed a `.dune-env` file to be run,
+    /// and ask them if they haven't. Allowed files are listed in
+    /// `~/.dune_allowed` with the hash of their contents, so a file
+    /// has to be allowed again after it changes. Scripts only run
+    /// files that have already been allowed.
+    fn allow_dir_env(&mut self, file: &Path) -> bool {
+        let allowed = match home_dir() {
+            Some(home) => home.join(".dune_allowed"),
+            None => return false,
+        };
+        let hash = match File::open(file).map_err(|e| Error::IoError(e.to_string())) {
+            Ok(contents) => hash_reader("sha256", contents),
+            Err(e) => Err(e),
+        };
+        let hash = match hash {
+            Ok(hash) => hash,
+            Err(e) => {
+                self.write_err(&format!("dune: {}: {}\n", to_string(file), e));
+                return false;
+            }
+        };
+        let file = to_string(file);
+        let entry = format!("{} {}", hash, file);
+        let list = read_to_string(&allowed).unwrap_or_default();
+        if list.lines().any(|line| line == entry) {
+            return true;
+        }
+        if !self.is_interactive || !self.ask(&format!("dune: run {}?", file)) {
+            return false;
+        }
+        // The file's entry for its old contents is replaced
+        let mut lines = list
+            .lines()
+            .filter(|line| line.split_once(' ').map(|(_, path)| path) != Some(&file))
+            .map(|line| format!("{}\n", line))
+            .collect::<String>();
+        lines.push_str(&entry);
+        lines.push('\n');
+        if let Err(e) = write(&allowed, lines) {
+            self.write_err(&format!("dune: {}: {}\n", to_string(&allowed), e));
+        }
+        true
+    }
+
+    /// Ask the user a yes or no question. Anything but yes is taken as no.
+    fn ask(&mut self, question: &str) -> bool {
+        match self.read_line(&format!("{} [y/N] ", question)) {
+            Line::Text(answer) => {
+                let answer = answer.trim().to_lowercase();
+                answer == "y" || answer == "yes"
+            }
+            _ => false,
+        }
+    }
+
+    /// Define an abbreviation for a command
+    pub fn alias(&mut self, name: &str, command: &str) {
+        self.aliases.insert(name.to_string(), command.to_string());
+    }
+
+    /// Remove an abbreviation for a command
+    pub fn unalias(&mut self, name: &str) {
+        self.aliases.remove(name);
+    }
+
+    /// Replace the alias that a command begins with by what it stands
+    /// for. Aliases can begin with other aliases, but not with themselves.
+    pub fn expand_aliases(&self, command: &str) -> String {
+        let mut command = command.to_string();
+        let mut expanded = vec![];
+        loop {
+            let start = command.len() - command.trim_start().len();
+            let end = command[start..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map_or(command.len(), |i| start + i);
+            let word = &command[start..end];
+            match self.aliases.get(word) {
+                Some(alias) if !expanded.contains(&word.to_string()) => {
+                    expanded.push(word.to_string());
+                    command = format!("{}{}{}", &command[..start], alias, &command[end..]);
+                }
+                _ => return command,
+            }
+        }
+    }
+
+    /// Run the prompt loop with input and output other than the terminal
+    pub fn run_with(&mut self, io: impl ShellIo + 'static) {
+        self.set_io(io);
+        self.repl();
+    }
+
+    /// Display a prompt and read a line of input from the user
+    pub fn read_line(&mut self, prompt: &str) -> Line {
+        self.io.borrow_mut().read_line(prompt)
+    }
+
+    /// Read a line of a command, highlighted as it's typed. The lines
+    /// before it in the same command are given to highlight it correctly.
+    fn read_code(&mut self, prompt: &str, before: &str) -> Line {
+        let theme = self.theme();
+        let mut io = self.io.borrow_mut();
+        io.set_highlighting(theme, before);
+        let line = io.read_line(prompt);
+        // Other input, like from `input`, isn't code
+        io.set_highlighting(None, "");
+        line
+    }
+
+    /// Get the theme that commands are highlighted with. The default
+    /// theme is changed by the keys of a `theme` dictionary, like
+    /// `theme.string = "green"`, and `theme = false` turns it off.
+    pub fn theme(&mut self) -> Option<Theme> {
+        let mut theme = Theme::default();
+        let value = match self.get_var("theme") {
+            Some(value) => value,
+            None => return Some(theme),
+        };
+        match &*value {
+            Value::Tree(styles) => {
+                for (kind, style) in styles {
+                    if !theme.set(kind, &style.to_string()) {
+                        self.write_err(&format!("dune: theme has no `{}`\n", kind));
+                    }
+                }
+                Some(theme)
+            }
+            _ if is_true(&value) => Some(theme),
+            _ => None,
+        }
+    }
+
+    fn repl(&mut self) {
+        self.is_interactive = true;
+        'repl: while !self.is_done {
+            self.update_dir_env();
+            self.record_visit();
+            let prompt = self.prompt();
+            let mut command = match self.read_code(&prompt, "") {
+                Line::Text(line) => line,
+                Line::Interrupted => continue,
+                Line::End => break,
+            };
+            let mut user_input = command.clone();
+            while program().parse(&command).is_err() && !user_input.trim().is_empty() {
+                let prompt = " ".repeat(prompt.chars().count()) + "> ";
+                user_input = match self.read_code(&prompt, &command) {
+                    Line::Text(line) => line,
+                    Line::Interrupted => continue 'repl,
+                    Line::End => String::new(),
+                };
+                command += "\n";
+                command += &user_input;
+            }
+
+            if !command.trim().is_empty() {
+                self.io.borrow_mut().add_history(&command);
+            }
+            reset_interrupt();
+            let command = self.expand_aliases(&command);
+            if command.trim().is_empty() {
+                continue;
+            }
+            self.run_hook("on_before_command", vec![Value::string(&command)]);
+            // The hook after the command is given the error message, if any
+            let start = Instant::now();
+            let outcome = match self.execute_source(&command) {
+                Ok(()) => Value::none(),
+                Err(e) => {
+                    self.write_err(&format!("Error: {}\n", e.report(&command)));
+                    Value::string(e.to_string())
+                }
+            };
+            self.report_time(start.elapsed());
+            self.run_hook("on_after_command", vec![Value::string(&command), outcome]);
+        }
+    }
+
+    /// Write output for the user
+    pub fn write(&self, text: &str) {
+        self.io.borrow_mut().write(text);
+    }
+
+    /// Write an error message for the user
+    pub fn write_err(&self, text: &str) {
+        self.io.borrow_mut().write_err(text);
+    }
+
+    /// Get the prompt to display before reading a command.
+    /// If the user has defined a `prompt` function, it is called
+    /// with the current directory. A `prompt` string is used as is.
+    pub fn prompt(&mut self) -> String {
+        let default = format!("{}$ ", to_string(&self.directory));
+        let prompt = match self.get_var("prompt") {
+            Some(prompt) => prompt,
+            None => return default,
+        };
+
+        match &*prompt {
+            Value::String(s) => s.clone(),
+            Value::Function(_) => {
+                self.machine.push(Value::string(to_string(&self.directory)));
+                self.machine.push(prompt);
+                let call = self.call();
+                let result = self.machine.pop();
+                self.clear_stack();
+
+                if let Err(e) = call {
+                    self.write_err(&format!("Error: {}\n", e));
+                    return default;
+                }
+                match result {
+                    Some(value) => value.to_string(),
+                    None => default,
+                }
+            }
+            _ => default,
+        }
+    }
+
+    /// Tell the user how long a command took, if it took at least as
+    /// many seconds as they set `REPORT_TIME` to, like zsh's `REPORTTIME`
+    fn report_time(&self, elapsed: Duration) {
+        let threshold = match self.get_var("REPORT_TIME").as_deref() {
+            Some(Value::Number(seconds)) => *seconds,
+            _ => return,
+        };
+        if elapsed.as_secs_f64() >= threshold {
+            self.write(&format!("took {}\n", format_duration(elapsed)));
+        }
+    }
+
+    /// Call a function that the user defined to run when something
+    /// happens, such as `on_before_command`, if they defined one
+    fn run_hook(&mut self, name: &str, args: Vec<Ref<Value>>) {
+        let hook = match self.get_var(name) {
+            Some(hook) if matches!(*hook, Value::Function(_)) => hook,
+            _ => return,
+        };
+        for arg in args.into_iter().rev() {
+            self.machine.push(arg);
+        }
+        self.machine.push(hook);
+        let call = self.call_in_frame(Frame {
+            function: name.to_string(),
+            span: Span::default(),
+        });
+        self.clear_stack();
+        if let Err(e) = call {
+            self.write_err(&format!("Error: {}\n", e.report("")));
+        }
+    }
+
+    /// Compile code into a flat list of instructions, which is how the
+    /// bodies of functions and loops are run. This is for running other
+    /// code the same way, such as a whole program that is run many times.
+    pub fn compile(&self, suite: &Suite) -> Code {
+        compile(suite)
+    }
+
+    /// Parse and execute a program. The values left on the stack
+    /// by each top level expression are printed after it runs.
+    pub fn execute_source(&mut self, source: &str) -> Result<(), Error> {
+        let Suite(exprs) = parse(source)?;
+
+        for expr in exprs {
+            let mut result = expr.execute(self);
+            if result.is_ok() {
+                result = self.auto_cd();
+            }
+            if result.is_ok() {
+                match self.machine.stack.last() {
+                    Some(value) if **value != Value::None => self.record_result(Ref::clone(value)),
+                    _ => {}
+                }
+                self.print_stack();
+            }
+            self.clear_stack();
+            result?;
+
+            if self.signal.take().is_some() || self.is_done {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Is `AUTO_CD` on, so that typing the name of a directory goes into it?
+    pub fn auto_cd_enabled(&self) -> bool {
+        self.get_var("AUTO_CD").is_some_and(|value| is_true(&value))
+    }
+
+    /// Go into the directory named by a top level expression when
+    /// `AUTO_CD` is on, instead of printing the directory's name
+    fn auto_cd(&mut self) -> Result<(), Error> {
+        if self.machine.stack.len() != 1 || !self.auto_cd_enabled() {
+            return Ok(());
+        }
+        let dir = match &*self.machine.stack[0] {
+            Value::String(name) => name.clone(),
+            _ => return Ok(()),
+        };
+        if self.resolve(&dir).is_ok_and(|path| path.is_dir()) {
+            self.clear_stack();
+            self.cd(Some(&dir))?;
+        }
+        Ok(())
+    }
+
+    /// Parse and execute a program, and give back the values left on
+    /// the stack by its top level expressions instead of printing them.
+    /// The values are in the order that they were pushed.
+    pub fn eval(&mut self, source: &str) -> Result<Vec<Value>, Error> {
+        let Suite(exprs) = parse(source)?;
+
+        let mut values = vec![];
+        for expr in exprs {
+            let result = expr.execute(self);
+            values.extend(self.machine.stack.drain(..).map(|v| (*v).clone()));
+            result?;
+
+            if self.signal.take().is_some() || self.is_done {
+                break;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Bind the path of the running script to `arg0`,
+    /// and the arguments passed to it to the `args` list
+    pub fn set_args(&mut self, arg0: &str, args: &[String]) {
+        self.register_const("arg0", arg0);
+        self.register_const(
+            "args",
+            Value::List(args.iter().map(Value::string).collect()),
+        );
+    }
+
+    /// Define a builtin function written in Rust. Like the builtins that
+    /// come with the shell, it takes its arguments from the stack with
+    /// the first argument on top, and pushes its return value.
+    pub fn register_fn(&mut self, name: &str, function: MachineFn) {
+        add_fn(&mut self.machine, function, name);
+    }
+
+    /// Define a variable with a value given from Rust
+    pub fn register_const(&mut self, name: &str, value: impl Into<Value>) {
+        add_const(&mut self.machine, value, name);
+    }
+
+    /// Define a variable holding data given from Rust,
+    /// which scripts can use and change like any other
+    pub fn set_data(&mut self, name: &str, data: DuneValue) {
+        self.scope.define(name, data.into_value());
+    }
+
+    /// Get the data that a variable holds, such as the results of a script
+    pub fn get_data(&self, name: &str) -> Result<DuneValue, Error> {
+        match self.get_var(name) {
+            Some(value) => DuneValue::from_value(&value),
+            None => Err(Error::UndefinedVariable(name.to_string(), None)),
+        }
+    }
+
+    /// Show the results left on the stack by a command. Statements
+    /// that are run for what they do, like loops and values followed
+    /// by `;`, don't leave results.
+    pub fn print_stack(&mut self) {
+        let listing = self.listing.take();
+        while let Some(value) = self.machine.pop() {
+            match (&*value, &listing) {
+                // Like a command that has no result, `none` isn't shown
+                (Value::None, _) => {}
+                // Files listed by `ls` are laid out in columns in a terminal,
+                // but a script still sees them as a plain list of names
+                (Value::List(names), Some((list, dir)))
+                    if Rc::ptr_eq(list, &value) && stdout().is_terminal() =>
+                {
+                    self.write(&show_listing(names, dir));
+                }
+                _ => {
+                    let text = pretty(&value, terminal_width(), colors_enabled());
+                    self.write(&format!("{}\n", text));
+                }
+            }
+        }
+    }
+
+    /// Keep the result of a command in `_`, so that the next command
+    /// can use it. The results before it are kept in `_1`, `_2`, and so
+    /// on, with `_1` being the one just before `_`.
+    pub fn record_result(&mut self, value: Ref<Value>) {
+        for i in (1..RESULT_HISTORY).rev() {
+            if let Some(older) = self.scope.get(&format!("_{}", i)) {
+                self.scope.define(format!("_{}", i + 1).as_str(), older);
+            }
+        }
+        if let Some(last) = self.scope.get("_") {
+            self.scope.define("_1", last);
+        }
+        self.scope.define("_", value);
+    }
+
+    pub fn clear_stack(&mut self) {
+        while self.machine.pop().is_some() {}
+    }
+
+    pub fn wd(&mut self) {
+        self.machine.push(Value::string(to_string(&self.directory)));
+    }
+
+    /// Check whether a variable is defined. A key of a
+    /// dictionary can be checked with dots, as in `config.editor`.
+    pub fn exists(&mut self, name: &str) {
+        let mut names = name.split('.');
+        let mut value = names.next().and_then(|name| self.get_var(name));
+        for key in names {
+            value = match value.as_deref() {
+                Some(Value::Tree(dict)) => dict.get(key).map(Ref::clone),
+                _ => None,
+            };
+        }
+        self.machine.push(Value::number(value.is_some() as i32));
+    }
+
+    pub fn mv(&self, old: &str, new: &str) -> Result<(), Error> {
+        let old_dir = self.resolve(old)?;
+        let new_dir = self.resolve(new)?;
+        move_path(&old_dir, &new_dir).map_err(|e| file_error("mv", &old_dir, e))
+    }
+
+    pub fn cp(&self, src: &str, dst: &str) -> Result<(), Error> {
+        let src_path = self.resolve(src)?;
+        let mut dst_path = self.resolve(dst)?;
+
+        // Copying into an existing directory puts the copy inside of it
+        if dst_path.is_dir() {
+            if let Some(name) = src_path.file_name() {
+                dst_path.push(name);
+            }
+        }
+
+        let result = if src_path.is_dir() {
+            if dst_path.starts_with(&src_path) {
+                return Err(Error::IoError(format!(
+                    "cp: can't copy {} into itself",
+                    to_string(&src_path)
+                )));
+            }
+            copy_dir(&src_path, &dst_path)
+        } else {
+            copy(&src_path, &dst_path).map(|_| ())
+        };
+        result.map_err(|e| Error::IoError(format!("cp: {}: {}", to_string(&src_path), e)))
+    }
+
+    /// Remove a file or directory. The user is asked before a directory
+    /// that isn't empty is removed, unless `force` is set, and scripts
+    /// can only remove one with `force`. When `RM_TRASH` is true, the
+    /// file is moved to the trash so it can be restored.
+    pub fn rm(&mut self, path: &str, force: bool) -> Result<(), Error> {
+        if path.is_empty() {
+            return Ok(());
+        }
+        let directory = self.resolve(path)?;
+
+        let is_dir = directory.symlink_metadata().is_ok_and(|m| m.is_dir());
+        let is_full_dir =
+            is_dir && read_dir(&directory).is_ok_and(|mut entries| entries.next().is_some());
+        if is_full_dir && !force && !self.is_interactive {
+            return Err(Error::IoError(format!(
+                "rm: {} is not empty, and is only removed with force",
+                to_string(&directory)
+            )));
+        }
+        if is_full_dir
+            && !force
+            && !self.ask(&format!(
+                "rm: {} is not empty. Remove it?",
+                to_string(&directory)
+            ))
+        {
+            return Err(Error::IoError(format!(
+                "rm: {} was not removed",
+                to_string(&directory)
+            )));
+        }
+
+        // The trash can't be used in the sandbox, so nothing is removed there
+        let use_trash = self
+            .get_var("RM_TRASH")
+            .is_some_and(|value| is_true(&value));
+        if use_trash {
+            return self.trash(&directory);
+        }
+        // A link to a directory is removed rather than the directory
+        let result = if is_dir {
+            remove_dir_all(&directory)
+        } else {
+            remove_file(&directory)
+        };
+        result.map_err(|e| file_error("rm", &directory, e))
+    }
+
+    /// The directory that removed files are moved to when `RM_TRASH` is on
+    fn trash_dir(&self) -> Result<PathBuf, Error> {
+        if self.sandbox.is_some() {
+            return Err(Error::PermissionDenied(String::from(
+                "the trash is disabled in the sandbox",
+            )));
+        }
+        match home_dir() {
+            Some(home) => Ok(home.join(".dune_trash")),
+            None => Err(Error::IoError(String::from("trash: no home directory"))),
+        }
+    }
+
+    /// Read the list of files in the trash, which maps the name of
+    /// each file in the trash to the path it was removed from
+    fn read_trash(&self) -> Result<BTreeMap<String, String>, Error> {
+        let index = self.trash_dir()?.join("index.json");
+        if !index.exists() {
+            return Ok(BTreeMap::new());
+        }
+        read_to_string(&index)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+            .map_err(|e| Error::IoError(format!("trash: {}: {}", to_string(&index), e)))
+    }
+
+    /// Save the list of files in the trash
+    fn write_trash(&self, trash: &BTreeMap<String, String>) -> Result<(), Error> {
+        let index = self.trash_dir()?.join("index.json");
+        let text = serde_json::to_string_pretty(trash).unwrap_or_default();
+        write(&index, text)
+            .map_err(|e| Error::IoError(format!("trash: {}: {}", to_string(&index), e)))
+    }
+
+    /// Move a file or directory to the trash
+    fn trash(&self, path: &Path) -> Result<(), Error> {
+        if path.symlink_metadata().is_err() {
+            return Ok(());
+        }
+        let dir = self.trash_dir()?;
+        let error = |e: std::io::Error| Error::IoError(format!("rm: {}: {}", to_string(path), e));
+        create_dir_all(&dir).map_err(error)?;
+
+        // The names start with the time they were removed,
+        // so that they are listed from oldest to newest
+        let time = std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis())
+            .unwrap_or(0);
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut name = format!("{:015}-{}", time, file_name);
+        while dir.join(&name).symlink_metadata().is_ok() {
+            name = format!("{:015}-{}", time, uuid::Uuid::new_v4().simple());
+        }
+
+        move_path(path, &dir.join(&name)).map_err(error)?;
+        let mut trash = self.read_trash()?;
+        trash.insert(name, to_string(path));
+        self.write_trash(&trash)
+    }
+
+    /// Put a file that was moved to the trash back where it was. The
+    /// file can be named by the path it was removed from, or by its
+    /// name. If several match, the one removed most recently is restored.
+    pub fn restore(&mut self, path: &str) -> Result<(), Error> {
+        let mut trash = self.read_trash()?;
+        let original = to_string(&self.resolve(path)?);
+        let found = trash
+            .iter()
+            .rev()
+            .find(|(_, removed)| **removed == original)
+            .or_else(|| {
+                trash.iter().rev().find(|(_, removed)| {
+                    Path::new(removed)
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        == Some(path)
+                })
+            })
+            .map(|(name, removed)| (name.clone(), removed.clone()));
+        let (name, removed) = match found {
+            Some(found) => found,
+            None => {
+                return Err(Error::IoError(format!(
+                    "restore: {} is not in the trash",
+                    path
+                )))
+            }
+        };
+
+        let removed_path = PathBuf::from(&removed);
+        if removed_path.symlink_metadata().is_ok() {
+            return Err(Error::IoError(format!(
+                "restore: {} already exists",
+                removed
+            )));
+        }
+        move_path(&self.trash_dir()?.join(&name), &removed_path)
+            .map_err(|e| Error::IoError(format!("restore: {}: {}", removed, e)))?;
+        trash.remove(&name);
+        self.write_trash(&trash)
+    }
+
+    pub fn mkdir(&self, path: &str) -> Result<(), Error> {
+        if path.is_empty() {
+            return Ok(());
+        }
+        let directory = self.resolve(path)?;
+
+        create_dir_all(&directory).map_err(|e| file_error("mkdir", &directory, e))
+    }
+
+    pub fn mkf(&self, path: &str) -> Result<(), Error> {
+        if path.is_empty() {
+            return Ok(());
+        }
+        let directory = self.resolve(path)?;
+
+        write(&directory, "").map_err(|e| file_error("mkf", &directory, e))
+    }
+
+    pub fn cat(&mut self, path: &str) -> Result<(), Error> {
+        let file = self.resolve(path)?;
+
+        match read_to_string(&file) {
+            Ok(contents) => {
+                self.machine.push(Value::string(contents));
+                Ok(())
+            }
+            Err(e) => Err(Error::IoError(format!("cat: {}: {}", to_string(&file), e))),
+        }
+    }
+
+    /// Read a CSV file with a header row into a list of dictionaries
+    /// that map the name of each column to its value in that row
+    pub fn csv_read(&mut self, path: &str) -> Result<(), Error> {
+        let file = self.resolve(path)?;
+        let error =
+            |e: csv::Error| Error::IoError(format!("csv_read: {}: {}", to_string(&file), e));
+
+        let mut reader = csv::Reader::from_path(&file).map_err(error)?;
+        let headers = reader.headers().map_err(error)?.clone();
+        let mut rows = vec![];
+        for record in reader.records() {
+            let record = record.map_err(error)?;
+            let row = headers
+                .iter()
+                .zip(record.iter())
+                .map(|(header, field)| (header.to_string(), Value::string(field)))
+                .collect::<BTreeMap<_, _>>();
+            rows.push(Ref::new(Value::Tree(row)));
+        }
+
+        self.machine.push(Ref::new(Value::List(rows)));
+        Ok(())
+    }
+
+    /// Write a list of dictionaries to a CSV file. The header row has
+    /// every key used by the rows, and missing values are left empty.
+    pub fn csv_write(&self, path: &str, rows: &[Ref<Value>]) -> Result<(), Error> {
+        let file = self.resolve(path)?;
+        let error =
+            |e: csv::Error| Error::IoError(format!("csv_write: {}: {}", to_string(&file), e));
+
+        let mut headers: Vec<String> = vec![];
+        for row in rows {
+            match &**row {
+                Value::Tree(row) => {
+                    for key in row.keys() {
+                        if !headers.contains(key) {
+                            headers.push(key.clone());
+                        }
+                    }
+                }
+                other => {
+                    return Err(Error::TypeError(format!(
+                        "csv_write: expected a dictionary, but found {}",
+                        other
+                    )))
+                }
+            }
+        }
+
+        let mut writer = csv::Writer::from_path(&file).map_err(error)?;
+        writer.write_record(&headers).map_err(error)?;
+        for row in rows {
+            if let Value::Tree(row) = &**row {
+                let fields = headers.iter().map(|key| match row.get(key).map(|v| &**v) {
+                    Some(Value::None) | None => String::new(),
+                    Some(value) => value.to_string(),
+                });
+                writer.write_record(fields).map_err(error)?;
+            }
+        }
+        writer
+            .flush()
+            .map_err(|e| Error::IoError(format!("csv_write: {}: {}", to_string(&file), e)))
+    }
+
+    pub fn ls(&mut self, dir: Option<String>) -> Result<(), Error> {
+        let directory = match dir {
+            Some(d) => self.resolve(&d)?,
+            None => self.directory.clone(),
+        };
+
+        let mut result = vec![];
+        let entries = read_dir(&directory).map_err(|e| file_error("ls", &directory, e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| file_error("ls", &directory, e))?;
+            result.push(Value::string(entry.file_name().to_string_lossy()));
+        }
+
+        let list = Ref::new(Value::List(result));
+        self.listing = Some((Ref::clone(&list), directory));
+        self.machine.push(list);
+        Ok(())
+    }
+
+    /// Call a function with the name of each file in a directory as soon
+    /// as it's read, instead of listing the whole directory first. The
+    /// function can return `false` to stop before the rest is read.
+    pub fn ls_each(&mut self, dir: &str, function: Ref<Value>) -> Result<(), Error> {
+        let directory = self.resolve(dir)?;
+        let entries = read_dir(&directory).map_err(|e| file_error("ls_each", &directory, e))?;
+        for entry in entries {
+            if is_interrupted() {
+                return Err(Error::Interrupted);
+            }
+            let entry = entry.map_err(|e| file_error("ls_each", &directory, e))?;
+            self.machine
+                .push(Value::string(entry.file_name().to_string_lossy()));
+            self.machine.push(Ref::clone(&function));
+            self.call()?;
+            // Only `false` stops, so a function that returns nothing goes on
+            if let Some(Value::Number(n)) = self.machine.pop().as_deref() {
+                if *n == 0.0 {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Create or extract an archive with the path given to the builtin.
+    /// Extracting an archive without a destination puts its files in the
+    /// current directory.
+    pub fn archive(
+        &mut self,
+        builtin: &str,
+        action: fn(&Path, &Path) -> std::io::Result<()>,
+        from: &str,
+        to: Option<&str>,
+    ) -> Result<(), Error> {
+        let from = self.resolve(from)?;
+        let to = match to {
+            Some(to) => self.resolve(to)?,
+            None => self.directory.clone(),
+        };
+        action(&from, &to)
+            .map_err(|e| Error::IoError(format!("{}: {}: {}", builtin, to_string(&from), e)))
+    }
+
+    /// Get the absolute path of a file with its symbolic links followed.
+    /// The parts of the path that don't exist yet are kept as they are.
+    pub fn realpath(&mut self, path: &str) -> Result<(), Error> {
+        let path = real_path(&self.resolve(path)?);
+        self.machine.push(Value::string(to_string(&path)));
+        Ok(())
+    }
+
+    /// Describe a file in detail
+    pub fn stat(&mut self, path: &str) -> Result<(), Error> {
+        let path = self.resolve(path)?;
+        let info = file_stat(&path)
+            .map_err(|e| Error::IoError(format!("stat: {}: {}", to_string(&path), e)))?;
+        self.machine.push(info);
+        Ok(())
+    }
+
+    /// Make a symbolic link at `link` that points to `target`. Like `ln -s`,
+    /// a relative target is relative to the directory the link is in.
+    pub fn symlink(&self, target: &str, link: &str) -> Result<(), Error> {
+        let link = self.resolve(link)?;
+        if let Some(root) = &self.sandbox {
+            let pointee = link.parent().unwrap_or(&link).join(target);
+            if !real_path(&pointee).starts_with(root) {
+                return Err(Error::PermissionDenied(format!(
+                    "{} is outside of the sandbox",
+                    to_string(&pointee)
+                )));
+            }
+        }
+        make_symlink(Path::new(target), &link)
+            .map_err(|e| Error::IoError(format!("symlink: {}: {}", to_string(&link), e)))
+    }
+
+    /// Get the path that a symbolic link points to
+    pub fn readlink(&mut self, path: &str) -> Result<(), Error> {
+        let path = self.resolve(path)?;
+        let target = path.read_link().map_err(|e| {
+            let reason = match e.kind() {
+                ErrorKind::InvalidInput => String::from("not a symbolic link"),
+                _ => e.to_string(),
+            };
+            Error::IoError(format!("readlink: {}: {}", to_string(&path), reason))
+        })?;
+        self.machine.push(Value::string(to_string(&target)));
+        Ok(())
+    }
+
+    /// Is a path a symbolic link? Links that point to
+    /// files that don't exist are still links.
+    pub fn is_symlink(&mut self, path: &str) -> Result<(), Error> {
+        let path = self.resolve(path)?;
+        self.machine.push(Value::number(path.is_symlink() as i32));
+        Ok(())
+    }
+
+    /// Change the permissions of a file, with an octal mode like `755`
+    /// or a symbolic one like `u+x`
+    pub fn chmod(&self, path: &str, mode: &str) -> Result<(), Error> {
+        let path = self.resolve(path)?;
+        let error = |e: String| Error::IoError(format!("chmod: {}: {}", to_string(&path), e));
+        let current = path.metadata().map_err(|e| error(e.to_string()))?;
+        let bits = parse_mode(mode, mode_bits(&current))
+            .ok_or_else(|| error(format!("invalid mode {}", mode)))?;
+        set_mode(&path, bits).map_err(|e| error(e.to_string()))
+    }
+
+    /// Change the user and group that own a file
+    pub fn chown(&self, path: &str, owner: &str) -> Result<(), Error> {
+        let path = self.resolve(path)?;
+        change_owner(&path, owner)
+    }
+
+    /// Get the total size in bytes of the files under a path
+    pub fn du(&mut self, path: Option<String>) -> Result<(), Error> {
+        let path = match path {
+            Some(path) => self.resolve(&path)?,
+            None => self.directory.clone(),
+        };
+        let mut skip =
+            |path: &Path, e| self.write_err(&format!("du: {}: {}\n", to_string(path), e));
+        let size = disk_usage(&path, &mut skip)
+            .map_err(|e| Error::IoError(format!("du: {}: {}", to_string(&path), e)))?;
+        self.machine.push(Value::number(size as f64));
+        Ok(())
+    }
+
+    /// Describe the space on every mounted filesystem, or on
+    /// the one that a path is on if a path is given
+    pub fn df(&mut self, path: Option<String>) -> Result<(), Error> {
+        if self.sandbox.is_some() {
+            return Err(Error::PermissionDenied(String::from(
+                "df is disabled in the sandbox",
+            )));
+        }
+        let describe = |disk: DiskSpace| {
+            let mut info = BTreeMap::new();
+            info.insert(String::from("filesystem"), Value::string(disk.filesystem));
+            info.insert(String::from("mount"), Value::string(to_string(&disk.mount)));
+            info.insert(String::from("total"), Value::number(disk.total as f64));
+            info.insert(String::from("free"), Value::number(disk.free as f64));
+            info.insert(
+                String::from("available"),
+                Value::number(disk.available as f64),
+            );
+            info.insert(
+                String::from("used"),
+                Value::number(disk.total.saturating_sub(disk.free) as f64),
+            );
+            Ref::new(Value::Tree(info))
+        };
+        let result = match path {
+            Some(path) => describe(disk_containing(&self.resolve(&path)?)?),
+            None => Ref::new(Value::List(disks()?.into_iter().map(describe).collect())),
+        };
+        self.machine.push(result);
+        Ok(())
+    }
+
+    /// Hash a file with an algorithm, or hash a string if it isn't the
+    /// path of a file. The hash is pushed as a hexadecimal string.
+    pub fn hash(&mut self, algorithm: &str, file_or_text: &str) -> Result<(), Error> {
+        match self.resolve(file_or_text) {
+            Ok(path) if path.is_file() => self.hash_file(algorithm, file_or_text),
+            _ => {
+                let hash = hash_bytes(algorithm, file_or_text.as_bytes())?;
+                self.machine.push(Value::string(hash));
+                Ok(())
+            }
+        }
+    }
+
+    /// Hash the contents of a file with an algorithm, such as `sha256`
+    pub fn hash_file(&mut self, algorithm: &str, path: &str) -> Result<(), Error> {
+        let path = self.resolve(path)?;
+        let error = |e: Error| match e {
+            Error::IoError(e) => Error::IoError(format!("hash: {}: {}", to_string(&path), e)),
+            e => e,
+        };
+        let file = File::open(&path).map_err(|e| error(Error::IoError(e.to_string())))?;
+        let hash = hash_reader(algorithm, file).map_err(error)?;
+        self.machine.push(Value::string(hash));
+        Ok(())
+    }
+
+    /// List a directory as dictionaries describing each entry
+    pub fn lsl(&mut self, dir: Option<String>) -> Result<(), Error> {
+        let directory = match dir {
+            Some(d) => self.resolve(&d)?,
+            None => self.directory.clone(),
+        };
+
+        let mut result = vec![];
+        if let Ok(dir) = read_dir(directory) {
+            for entry in dir.flatten() {
+                if let Ok(info) = file_info(&entry.path()) {
+                    result.push(info);
+                }
+            }
+        }
+
+        self.machine.push(Ref::new(Value::List(result)));
+        Ok(())
+    }
+
+    /// Recursively list the paths under a directory relative to it,
+    /// descending at most `depth` levels if a depth is given
+    pub fn walk(&mut self, dir: Option<String>, depth: Option<usize>) -> Result<(), Error> {
+        let directory = match dir {
+            Some(d) => self.resolve(&d)?,
+            None => self.directory.clone(),
+        };
+
+        let mut paths = vec![];
+        walk_dir(&directory, depth, &mut paths)
+            .map_err(|e| Error::IoError(format!("walk: {}: {}", to_string(&directory), e)))?;
+
+        let result = paths
+            .iter()
+            .filter_map(|path| path.strip_prefix(&directory).ok())
+            .map(|path| Value::string(path.to_string_lossy()))
+            .collect();
+        self.machine.push(Ref::new(Value::List(result)));
+        Ok(())
+    }
+
+    /// Recursively search a directory for the entries that a predicate
+    /// function returns true for. The predicate is called with the same
+    /// dictionary that `lsl` describes an entry with, which also holds
+    /// the path of the entry relative to the directory being searched.
+    pub fn find(
+        &mut self,
+        dir: Option<String>,
+        predicate: Option<Ref<Value>>,
+    ) -> Result<(), Error> {
+        let directory = match dir {
+            Some(d) => self.resolve(&d)?,
+            None => self.directory.clone(),
+        };
+
+        let mut paths = vec![];
+        walk_dir(&directory, None, &mut paths)
+            .map_err(|e| Error::IoError(format!("find: {}: {}", to_string(&directory), e)))?;
+
+        let mut result = vec![];
+        for path in paths {
+            let mut info = match file_info(&path) {
+                Ok(info) => (*info).clone(),
+                Err(_) => continue,
+            };
+            if let (Value::Tree(tree), Ok(relative)) = (&mut info, path.strip_prefix(&directory)) {
+                tree.insert(
+                    String::from("path"),
+                    Value::string(relative.to_string_lossy()),
+                );
+            }
+            let info = Ref::new(info);
+
+            if let Some(predicate) = &predicate {
+                self.machine.push(Ref::clone(&info));
+                self.machine.push(Ref::clone(predicate));
+                self.call()?;
+                match self.machine.pop() {
+                    Some(v) if is_true(&v) => {}
+                    _ => continue,
+                }
+            }
+            result.push(info);
+        }
+
+        self.machine.push(Ref::new(Value::List(result)));
+        Ok(())
+    }
+
+    /// Find the lines containing a pattern. If the target names a file,
+    /// the contents of the file are searched instead of the target itself.
+    pub fn grep(&mut self, pattern: &str, target: &str) -> Result<(), Error> {
+        // Text that can't be a path in the sandbox is searched as is
+        let file = self.resolve(target).unwrap_or_default();
+
+        let text = if !target.contains('\n') && file.is_file() {
+            read_to_string(&file)
+                .map_err(|e| Error::IoError(format!("grep: {}: {}", to_string(&file), e)))?
+        } else {
+            target.to_string()
+        };
+
+        let result = text
+            .lines()
+            .filter(|line| line.contains(pattern))
+            .map(Value::string)
+            .collect();
+        self.machine.push(Ref::new(Value::List(result)));
+        Ok(())
+    }
+
+    /// Read the lines of a file, or of a string if it isn't
+    /// the path of a file, the way that `grep` does
+    fn text_lines(
+        &self,
+        builtin: &str,
+        target: &str,
+    ) -> Result<Box<dyn Iterator<Item = Result<String, Error>>>, Error> {
+        let file = self.resolve(target).unwrap_or_default();
+        if target.contains('\n') || !file.is_file() {
+            let lines = target.lines().map(|line| Ok(line.to_string()));
+            return Ok(Box::new(lines.collect::<Vec<_>>().into_iter()));
+        }
+
+        let context = format!("{}: {}", builtin, to_string(&file));
+        let error = move |e: std::io::Error| Error::IoError(format!("{}: {}", context, e));
+        let reader = File::open(&file).map_err(&error)?;
+        Ok(Box::new(
+            BufReader::new(reader)
+                .lines()
+                .map(move |line| line.map_err(&error)),
+        ))
+    }
+
+    /// Get the first `n` lines of a file or string
+    pub fn head(&mut self, target: &str, n: usize) -> Result<(), Error> {
+        let result = self
+            .text_lines("head", target)?
+            .take(n)
+            .map(|line| line.map(Value::string))
+            .collect::<Result<_, _>>()?;
+        self.machine.push(Ref::new(Value::List(result)));
+        Ok(())
+    }
+
+    /// Get the last `n` lines of a file or string
+    pub fn tail(&mut self, target: &str, n: usize) -> Result<(), Error> {
+        let mut last = VecDeque::new();
+        for line in self.text_lines("tail", target)? {
+            let line = line?;
+            if n == 0 {
+                continue;
+            }
+            if last.len() == n {
+                last.pop_front();
+            }
+            last.push_back(Value::string(line));
+        }
+        self.machine.push(Ref::new(Value::List(last.into())));
+        Ok(())
+    }
+
+    /// Count the lines in a file or string
+    pub fn count_lines(&mut self, target: &str) -> Result<(), Error> {
+        let mut count = 0;
+        for line in self.text_lines("count_lines", target)? {
+            line?;
+            count += 1;
+        }
+        self.machine.push(Value::number(count as f64));
+        Ok(())
+    }
+
+    /// Change the current directory. Without a directory, this goes to
+    /// the home directory, or to the root of a sandbox. The directory
+    /// `-` is the directory that the shell was in before.
+    pub fn cd(&mut self, dir: Option<&str>) -> Result<(), Error> {
+        let result = match dir {
+            Some("-") => match &self.previous_directory {
+                Some(previous) => self.resolve(&to_string(previous))?,
+                None => return Err(Error::IoError(String::from("cd: no previous directory"))),
+            },
+            Some(dir) => self.resolve(dir)?,
+            None => match self.sandbox.clone().or_else(home_dir) {
+                Some(home) => home,
+                None => return Err(Error::IoError(String::from("cd: no home directory"))),
+            },
+        };
+        match result.metadata() {
+            Ok(metadata) if !metadata.is_dir() => {
+                return Err(file_error("cd", &result, ErrorKind::NotADirectory.into()))
+            }
+            Err(e) => return Err(file_error("cd", &result, e)),
+            Ok(_) => {}
+        }
+        // Looking inside of the directory fails if it can't be entered
+        let dir = result
+            .join(".")
+            .metadata()
+            .and_then(|_| result.canonicalize())
+            .map_err(|e| file_error("cd", &result, e))?;
+        if dir != self.directory {
+            self.previous_directory = Some(replace(&mut self.directory, dir));
+        }
+        self.update_dir_env();
+        Ok(())
+    }
+
+    /// Save the current directory on the directory stack, and change to
+    /// another directory. Without a directory, this swaps the current
+    /// directory with the one on top of the stack.
+    pub fn pushd(&mut self, dir: Option<&str>) -> Result<(), Error> {
+        // The stack is only changed once the directory has been changed
+        let target = match dir {
+            Some(dir) => self.resolve(dir)?,
+            None => match self.directory_stack.last() {
+                Some(top) => top.clone(),
+                None => return Err(Error::IoError(String::from("pushd: no other directory"))),
+            },
+        };
+        if !target.is_dir() {
+            return Err(Error::IoError(format!(
+                "pushd: {}: not a directory",
+                to_string(&target)
+            )));
+        }
+        let current = self.directory.clone();
+        self.cd(Some(&to_string(&target)))?;
+        if dir.is_none() {
+            self.directory_stack.pop();
+        }
+        self.directory_stack.push(current);
+        Ok(())
+    }
+
+    /// Change to the directory on top of the directory stack, and remove
+    /// it. It stays on the stack if it can't be changed to.
+    pub fn popd(&mut self) -> Result<(), Error> {
+        match self.directory_stack.last() {
+            Some(dir) => {
+                self.cd(Some(&to_string(&dir.clone())))?;
+                self.directory_stack.pop();
+                Ok(())
+            }
+            None => Err(Error::IoError(String::from("popd: directory stack empty"))),
+        }
+    }
+
+    /// List the current directory, followed by the directory stack
+    /// from the most recently saved directory to the least
+    pub fn dirs(&mut self) {
+        let dirs = std::iter::once(&self.directory)
+            .chain(self.directory_stack.iter().rev())
+            .map(|dir| Value::string(to_string(dir)))
+            .collect();
+        self.machine.push(Ref::new(Value::List(dirs)));
+    }
+
+    /// The file that bookmarks are saved in, so that they
+    /// can be used from any session
+    fn bookmarks_file(&self) -> Result<PathBuf, Error> {
+        if self.sandbox.is_some() {
+            return Err(Error::PermissionDenied(String::from(
+                "bookmarks are disabled in the sandbox",
+            )));
+        }
+        match home_dir() {
+            Some(home) => Ok(home.join(".dune_bookmarks")),
+            None => Err(Error::IoError(String::from("bookmarks: no home directory"))),
+        }
+    }
+
+    /// Read the saved bookmarks, which map names to directories
+    fn read_bookmarks(&self) -> Result<BTreeMap<String, String>, Error> {
+        let file = self.bookmarks_file()?;
+        if !file.exists() {
+            return Ok(BTreeMap::new());
+        }
+        read_to_string(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+            .map_err(|e| Error::IoError(format!("bookmarks: {}: {}", to_string(&file), e)))
+    }
+
+    /// The file that counts the visits to each directory for `z`
+    fn visits_file(&self) -> Result<PathBuf, Error> {
+        if self.sandbox.is_some() {
+            return Err(Error::PermissionDenied(String::from(
+                "z is disabled in the sandbox",
+            )));
+        }
+        match home_dir() {
+            Some(home) => Ok(home.join(".dune_visits")),
+            None => Err(Error::IoError(String::from("z: no home directory"))),
         }
     }
 
-    pub fn run(&mut self) {
-        while !self.is_done {
-            print!("{}$ ", to_string(&self.directory));
-            let mut command = String::from("");
-            let mut user_input = input::<String>().get();
-            command += &user_input;
-            while !program().parse(&command).is_ok() && !(user_input.trim() == "") {
-                user_input = input()
-                    .msg(" ".repeat(to_string(&self.directory).len()) + "> ")
-                    .get();
-                command += &user_input;
-            }
+    /// Read how often and how recently each directory was visited
+    fn read_visits(&self) -> Result<Visits, Error> {
+        let file = self.visits_file()?;
+        if !file.exists() {
+            return Ok(Visits::new());
+        }
+        read_to_string(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+            .map_err(|e| Error::IoError(format!("z: {}: {}", to_string(&file), e)))
+    }
 
-            match program().parse(&command) {
-                Ok(v) => {
-                    match v.execute(self) {
-                        _ => {}
-                    };
-                    self.print_stack();
-                    self.clear_stack();
-                }
-                Err(e) => println!("Error: {:?}", e),
-            };
+    /// Count a visit to the current directory when the REPL enters it.
+    /// Counting visits is only for `z`, so if they can't be saved, the
+    /// user is told once and they aren't counted anymore.
+    fn record_visit(&mut self) {
+        if self.sandbox.is_some()
+            || !self.counts_visits
+            || self.visited.as_ref() == Some(&self.directory)
+        {
+            return;
+        }
+        self.visited = Some(self.directory.clone());
+        let result = self.read_visits().and_then(|mut visits| {
+            record_visit(&mut visits, &to_string(&self.directory), now());
+            let file = self.visits_file()?;
+            let text = serde_json::to_string(&visits).unwrap_or_default();
+            write(&file, text)
+                .map_err(|e| Error::IoError(format!("z: {}: {}", to_string(&file), e)))
+        });
+        if let Err(e) = result {
+            self.counts_visits = false;
+            self.write_err(&format!("dune: visits aren't being counted for z: {}\n", e));
         }
     }
 
-    pub fn print_stack(&mut self) {
-        while let Some(value) = self.machine.pop() {
-            println!("{}", value);
+    /// Go to the directory that best matches a query, out of the directories
+    /// visited in the REPL, favoring the ones visited often and recently.
+    /// Without a query, the visited directories are listed with their scores.
+    pub fn z(&mut self, words: &[String]) -> Result<(), Error> {
+        let visits = self.read_visits()?;
+        let mut matches = ranked_matches(&visits, words, now());
+        if words.is_empty() {
+            let list = matches
+                .into_iter()
+                .map(|(dir, score)| {
+                    let mut info = BTreeMap::new();
+                    info.insert(String::from("dir"), Value::string(to_string(&dir)));
+                    info.insert(String::from("score"), Value::number(score));
+                    Ref::new(Value::Tree(info))
+                })
+                .collect();
+            self.machine.push(Ref::new(Value::List(list)));
+            return Ok(());
+        }
+        // Going to the directory the shell is already in wouldn't help
+        matches.retain(|(dir, _)| *dir != self.directory);
+        match matches.first() {
+            Some((dir, _)) => self.cd(Some(&to_string(dir))),
+            None => Err(Error::IoError(format!(
+                "z: no visited directory matches {}",
+                words.join(" ")
+            ))),
         }
     }
 
-    pub fn clear_stack(&mut self) {
-        while let Some(_) = self.machine.pop() {}
+    /// Save the current directory under a name
+    pub fn bookmark(&mut self, name: &str) -> Result<(), Error> {
+        let mut bookmarks = self.read_bookmarks()?;
+        bookmarks.insert(name.to_string(), to_string(&self.directory));
+        let file = self.bookmarks_file()?;
+        let text = serde_json::to_string_pretty(&bookmarks).unwrap_or_default();
+        write(&file, text)
+            .map_err(|e| Error::IoError(format!("bookmark: {}: {}", to_string(&file), e)))
     }
 
-    pub fn wd(&mut self) {
-        self.machine.push(Value::string(to_string(&self.directory)));
+    /// Change to a directory saved with `bookmark`
+    pub fn goto(&mut self, name: &str) -> Result<(), Error> {
+        match self.read_bookmarks()?.get(name) {
+            Some(dir) => self.cd(Some(dir)),
+            None => Err(Error::IoError(format!("goto: no bookmark named {}", name))),
+        }
     }
 
-    pub fn mv(&self, old: &str, new: &str) {
-        let mut old_dir = self.directory.clone();
-        old_dir.push(old);
-        let mut new_dir = self.directory.clone();
-        new_dir.push(new);
-        match rename(old_dir, new_dir) {
-            _ => {}
-        };
+    /// Get a dictionary of the saved bookmarks
+    pub fn bookmarks(&mut self) -> Result<(), Error> {
+        let bookmarks = self
+            .read_bookmarks()?
+            .into_iter()
+            .map(|(name, dir)| (name, Value::string(dir)))
+            .collect();
+        self.machine.push(Ref::new(Value::Tree(bookmarks)));
+        Ok(())
     }
 
-    pub fn rm(&self, path: &str) {
-        if path == "" {
-            return;
+    /// Start a program without waiting for it to finish, and
+    /// push a handle that can wait for it, kill it, or get its output
+    pub fn spawn(&mut self, words: &[String]) -> Result<(), Error> {
+        if self.sandbox.is_some() {
+            return Err(Error::PermissionDenied(String::from(
+                "spawn is disabled in the sandbox",
+            )));
         }
-        let directory = {
-            let mut result = self.directory.clone();
-            result.push(path);
-            result
+        let (name, args) = match words.split_first() {
+            Some(command) => command,
+            None => return Err(Error::ProcessError(String::from("spawn: no command given"))),
         };
+        let child = Command::new(name)
+            .args(args)
+            .current_dir(&self.directory)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| Error::ProcessError(format!("{}: {}", name, e)))?;
+        let handle = job_handle(name, child, &self.machine);
+        self.machine.push(handle);
+        Ok(())
+    }
 
-        match remove_dir_all(directory.clone()) {
-            _ => {}
-        };
-        match remove_file(directory) {
-            _ => {}
+    /// Load a WebAssembly plugin, and push a dictionary of its builtins
+    pub fn plugin(&mut self, path: &str) -> Result<(), Error> {
+        let file = self.resolve(path)?;
+        let wasm = read(&file)
+            .map_err(|e| Error::IoError(format!("plugin: {}: {}", to_string(&file), e)))?;
+        let plugin = load_plugin(&to_string(&file), &wasm, &self.machine)?;
+        self.machine.push(plugin);
+        Ok(())
+    }
+
+    /// Call a function, and stop it if it takes longer than a number
+    /// of seconds. Whether or not the function finished is pushed.
+    /// Programs run by the function are not stopped, but the function
+    /// stops as soon as they exit.
+    pub fn timeout_call(&mut self, seconds: f64, function: Ref<Value>) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout_duration(seconds)?;
+        let outer = set_deadline(deadline);
+        self.machine.push(function);
+        let result = self.call();
+        restore_deadline(outer);
+        let finished = match result {
+            Ok(()) => true,
+            // The function was stopped by this timeout, rather than by
+            // Ctrl-C or by the timeout of an enclosing call
+            Err(e)
+                if matches!(e.cause(), Error::Interrupted)
+                    && Instant::now() >= deadline
+                    && !is_ctrl_c_pressed() =>
+            {
+                false
+            }
+            Err(e) => return Err(e),
         };
+        // Anything the function returned is not needed
+        self.machine.stack.clear();
+        self.machine.push(Value::number(finished as i32));
+        Ok(())
     }
 
-    pub fn mkdir(&self, path: &str) {
-        if path == "" {
-            return;
+    /// Run a program, and kill it if it takes longer than a number of
+    /// seconds. Whether or not the program finished is pushed.
+    pub fn timeout_program(&mut self, seconds: f64, words: &[String]) -> Result<(), Error> {
+        if self.sandbox.is_some() {
+            return Err(Error::PermissionDenied(String::from(
+                "timeout is disabled in the sandbox",
+            )));
         }
-        let directory = {
-            let mut result = self.directory.clone();
-            result.push(path);
-            result
+        let deadline = Instant::now() + timeout_duration(seconds)?;
+        let (name, args) = match words.split_first() {
+            Some(command) => command,
+            None => {
+                return Err(Error::ProcessError(String::from(
+                    "timeout: no command given",
+                )))
+            }
         };
-
-        match create_dir_all(directory) {
-            _ => {}
+        let error = |e: std::io::Error| Error::ProcessError(format!("{}: {}", name, e));
+        let mut child = Command::new(name)
+            .args(args)
+            .current_dir(&self.directory)
+            .spawn()
+            .map_err(error)?;
+        let finished = loop {
+            if child.try_wait().map_err(error)?.is_some() {
+                break true;
+            }
+            if Instant::now() >= deadline {
+                child.kill().map_err(error)?;
+                child.wait().map_err(error)?;
+                break false;
+            }
+            sleep(Duration::from_millis(10));
         };
+        self.machine.push(Value::number(finished as i32));
+        Ok(())
     }
 
-    pub fn mkf(&self, path: &str) {
-        if path == "" {
-            return;
+    /// Get a list of the processes running on the system
+    pub fn ps(&mut self) -> Result<(), Error> {
+        if self.sandbox.is_some() {
+            return Err(Error::PermissionDenied(String::from(
+                "ps is disabled in the sandbox",
+            )));
+        }
+        let processes = processes()?
+            .into_iter()
+            .map(|process| {
+                let mut info = BTreeMap::new();
+                info.insert(String::from("pid"), Value::number(process.pid as f64));
+                info.insert(String::from("name"), Value::string(process.name));
+                info.insert(String::from("cpu"), Value::number(process.cpu));
+                info.insert(String::from("mem"), Value::number(process.mem));
+                Ref::new(Value::Tree(info))
+            })
+            .collect();
+        self.machine.push(Ref::new(Value::List(processes)));
+        Ok(())
+    }
+
+    /// Send a signal to a process, which is either the number
+    /// of a signal or its name. The default signal is `TERM`.
+    pub fn kill(&mut self, pid: f64, signal: Option<Value>) -> Result<(), Error> {
+        if self.sandbox.is_some() {
+            return Err(Error::PermissionDenied(String::from(
+                "kill is disabled in the sandbox",
+            )));
+        }
+        // Zero and negative numbers would signal whole groups of processes
+        if pid < 1.0 || pid > i32::MAX as f64 || pid.fract() != 0.0 {
+            return Err(Error::ProcessError(format!("kill: invalid pid {}", pid)));
         }
-        let directory = {
-            let mut result = self.directory.clone();
-            result.push(path);
-            result
+        let signal = match signal {
+            None => DEFAULT_SIGNAL,
+            Some(Value::Number(n)) => n as i32,
+            Some(Value::String(name)) => match signal_number(&name) {
+                Some(signal) => signal,
+                None => {
+                    return Err(Error::ProcessError(format!(
+                        "kill: unknown signal {}",
+                        name
+                    )))
+                }
+            },
+            Some(other) => {
+                return Err(Error::TypeError(format!(
+                    "kill: a signal must be a number or a name, not {}",
+                    other
+                )))
+            }
         };
+        kill(pid as u32, signal)
+    }
 
-        match write(directory, "") {
-            _ => {}
+    /// Run an external program in the current directory
+    pub fn run_program(&mut self, name: &str, args: &[String]) -> Result<(), Error> {
+        if self.sandbox.is_some() {
+            return Err(Error::PermissionDenied(format!(
+                "{} can't be run in the sandbox",
+                name
+            )));
+        }
+        let program = match find_program(&self.directory, name) {
+            Some(program) => program,
+            None => {
+                return Err(Error::ProcessError(match self.similar_command(name) {
+                    Some(similar) => {
+                        format!("{}: command not found, did you mean `{}`?", name, similar)
+                    }
+                    None => format!("{}: command not found", name),
+                }))
+            }
         };
+        let status = self.run_command(name, Command::new(program).args(args))?;
+        self.check_status(name, status)
     }
 
-    pub fn ls(&mut self, dir: Option<String>) {
-        let directory = match dir {
-            Some(d) => {
-                let mut result_dir = self.directory.clone();
-                result_dir.push(d);
-                result_dir
+    /// Run a program and wait for it to finish. Its output goes straight
+    /// to the terminal if the shell writes there, and otherwise it's passed
+    /// along as it arrives, so long commands show progress either way.
+    /// Errors go straight to the terminal.
+    fn run_command(&self, name: &str, command: &mut Command) -> Result<ExitStatus, Error> {
+        let error = |e: std::io::Error| Error::ProcessError(format!("{}: {}", name, e));
+        command
+            .current_dir(&self.directory)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        if self.io.borrow().writes_to_stdout() {
+            return command.stdout(Stdio::inherit()).status().map_err(error);
+        }
+        let mut child = command.stdout(Stdio::piped()).spawn().map_err(error)?;
+        if let Some(mut stdout) = child.stdout.take() {
+            let mut buffer = [0; 4096];
+            // Bytes at the end of a read that are only part of a character
+            let mut pending = vec![];
+            loop {
+                let count = match stdout.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(count) => count,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(error(e)),
+                };
+                pending.extend_from_slice(&buffer[..count]);
+                let complete = match from_utf8(&pending) {
+                    Ok(_) => pending.len(),
+                    Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                    Err(_) => pending.len(),
+                };
+                let rest = pending.split_off(complete);
+                self.write(&String::from_utf8_lossy(&pending));
+                pending = rest;
+            }
+            self.write(&String::from_utf8_lossy(&pending));
+        }
+        child.wait().map_err(error)
+    }
+
+    /// Keep the status that a program exited with in `status`, which is
+    /// -1 if it was stopped by a signal. In strict mode, a program that
+    /// exits with a nonzero status is an error, like a builtin that fails.
+    fn check_status(&mut self, name: &str, status: ExitStatus) -> Result<(), Error> {
+        self.register_const("status", Value::Number(status.code().unwrap_or(-1) as f64));
+        if !self.is_strict || status.success() {
+            return Ok(());
+        }
+        Err(Error::ProcessError(match status.code() {
+            Some(code) => format!("{} exited with status {}", name, code),
+            None => format!("{} was stopped by a signal", name),
+        }))
+    }
+
+    /// Run a program, given its name followed by its arguments
+    pub fn sh(&mut self, words: &[String]) -> Result<(), Error> {
+        if self.sandbox.is_some() {
+            return Err(Error::PermissionDenied(String::from(
+                "sh is disabled in the sandbox",
+            )));
+        }
+        if let Some((name, args)) = words.split_first() {
+            let status = self.run_command(name, Command::new(name).args(args))?;
+            self.check_status(name, status)?;
+        }
+        Ok(())
+    }
+
+    /// Clear the terminal and move the cursor to the top of it. Without
+    /// a terminal that understands ANSI codes, the old output is pushed
+    /// out of view with blank lines instead.
+    pub fn clear(&mut self) {
+        let dumb = var("TERM").map_or(true, |term| term == "dumb");
+        if stdout().is_terminal() && !dumb {
+            self.write("\x1b[2J\x1b[H");
+        } else {
+            self.write(&"\n".repeat(201));
+        }
+    }
+
+    /// Stop the shell once the current command is finished,
+    /// so that the program running it exits with a status
+    pub fn exit(&mut self, status: i32) {
+        self.is_done = true;
+        self.exit_status = status;
+    }
+}
+
+/// Sets up how a shell starts, for programs that embed dune and for the
+/// command line. Without any settings, the shell starts in the home
+/// directory, reads and writes the terminal, and loads the standard library.
+pub struct ShellBuilder {
+    directory: Option<PathBuf>,
+    is_sandboxed: bool,
+    env: Vec<(String, String)>,
+    variables: Vec<(String, DuneValue)>,
+    functions: Vec<(String, MachineFn)>,
+    io: Option<Box<dyn ShellIo>>,
+    stdlib: bool,
+}
+
+impl Shell {
+    pub fn builder() -> ShellBuilder {
+        ShellBuilder {
+            directory: None,
+            is_sandboxed: false,
+            env: vec![],
+            variables: vec![],
+            functions: vec![],
+            io: None,
+            stdlib: true,
+        }
+    }
+}
+
+impl ShellBuilder {
+    /// Start the shell in a directory instead of the home directory
+    pub fn directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.directory = Some(directory.into());
+        self
+    }
+
+    /// Only let the shell access the files inside of the directory it
+    /// starts in, and don't let it run other programs, like `sandboxed`
+    pub fn sandboxed(mut self, is_sandboxed: bool) -> Self {
+        self.is_sandboxed = is_sandboxed;
+        self
+    }
+
+    /// Set an environment variable when the shell is built
+    pub fn env(mut self, name: &str, value: &str) -> Self {
+        self.env.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Define a variable holding data, which scripts can use and change
+    pub fn variable(mut self, name: &str, data: DuneValue) -> Self {
+        self.variables.push((name.to_string(), data));
+        self
+    }
+
+    /// Define a builtin function written in Rust
+    pub fn function(mut self, name: &str, function: MachineFn) -> Self {
+        self.functions.push((name.to_string(), function));
+        self
+    }
+
+    /// Read input and write output with something other than the terminal
+    pub fn io(mut self, io: impl ShellIo + 'static) -> Self {
+        self.io = Some(Box::new(io));
+        self
+    }
+
+    /// Choose whether the parts of the standard library
+    /// that are written in dune are loaded
+    pub fn stdlib(mut self, stdlib: bool) -> Self {
+        self.stdlib = stdlib;
+        self
+    }
+
+    /// Make the shell, which fails if the standard library can't be loaded
+    pub fn build(self) -> Result<Shell, Error> {
+        let directory = self.directory.or_else(home_dir).unwrap_or_default();
+        let mut shell = if self.is_sandboxed {
+            Shell::sandboxed(directory)
+        } else {
+            Shell {
+                directory,
+                ..Shell::new()
             }
-            None => self.directory.clone(),
         };
+        if let Some(io) = self.io {
+            *shell.io.borrow_mut() = io;
+        }
+        for (name, value) in self.env {
+            check_env_var("env", &name, &value)?;
+            set_var(name, value);
+        }
+        for (name, function) in self.functions {
+            shell.register_fn(&name, function);
+        }
+        if self.stdlib {
+            shell.load_stdlib()?;
+        }
+        // Variables are defined last, so that they aren't replaced
+        for (name, data) in self.variables {
+            shell.set_data(&name, data);
+        }
+        Ok(shell)
+    }
+}
 
-        let mut result = vec![];
-        match read_dir(directory) {
-            Ok(dir) => {
-                for name in dir {
-                    result.push(Value::string(
-                        name.unwrap().path().file_name().unwrap().to_str().unwrap(),
-                    ));
+/// The time in seconds since the Unix epoch
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Describe why a builtin couldn't work on a file, like
+/// `rm: /etc/hosts: permission denied`
+fn file_error(builtin: &str, path: &Path, e: std::io::Error) -> Error {
+    let reason = match e.kind() {
+        ErrorKind::NotFound => String::from("no such file or directory"),
+        ErrorKind::PermissionDenied => String::from("permission denied"),
+        ErrorKind::AlreadyExists => String::from("already exists"),
+        ErrorKind::NotADirectory => String::from("not a directory"),
+        ErrorKind::IsADirectory => String::from("is a directory"),
+        ErrorKind::DirectoryNotEmpty => String::from("directory not empty"),
+        _ => e.to_string(),
+    };
+    Error::IoError(format!("{}: {}: {}", builtin, to_string(path), reason))
+}
+
+/// Remove the `.` and `..` components of a path without
+/// looking at the files it refers to
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+/// Resolve the `.` and `..` components and the symbolic links
+/// in a path, even if the file it refers to doesn't exist yet
+fn real_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            other => result.push(other),
+        }
+        if let Ok(real) = result.canonicalize() {
+            result = real;
+        }
+    }
+    result
+}
+
+/// Describe a file with a dictionary of its name, size,
+/// modification time, type, and permissions. A symbolic link is
+/// described by the file it points to, along with the link's target.
+fn file_info(path: &Path) -> std::io::Result<Ref<Value>> {
+    let link = path.symlink_metadata()?;
+    // A link to a file that doesn't exist can only describe itself
+    let metadata = path.metadata().unwrap_or_else(|_| link.clone());
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|time| time.as_secs_f64())
+        .unwrap_or(0.0);
+
+    let mut info = BTreeMap::new();
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => to_string(path),
+    };
+    info.insert(String::from("name"), Value::string(name));
+    info.insert(String::from("size"), Value::number(metadata.len() as f64));
+    info.insert(String::from("modified"), Value::number(modified));
+    info.insert(
+        String::from("is_dir"),
+        Value::number(metadata.is_dir() as i32),
+    );
+    info.insert(
+        String::from("is_symlink"),
+        Value::number(link.file_type().is_symlink() as i32),
+    );
+    info.insert(
+        String::from("target"),
+        match path.read_link() {
+            Ok(target) => Value::string(to_string(&target)),
+            Err(_) => Value::none(),
+        },
+    );
+    info.insert(
+        String::from("permissions"),
+        Value::string(permissions(&metadata)),
+    );
+    info.insert(
+        String::from("mode"),
+        Value::string(format!("{:o}", mode_bits(&metadata))),
+    );
+    Ok(Ref::new(Value::Tree(info)))
+}
+
+/// Describe a file with a dictionary of everything `stat` knows about
+/// it: its size, type, permissions, owner, and when it was created,
+/// modified, and accessed. A symbolic link is described rather than
+/// the file it points to. Times that the system doesn't record are none.
+fn file_stat(path: &Path) -> std::io::Result<Ref<Value>> {
+    let metadata = path.symlink_metadata()?;
+    let time = |time: std::io::Result<std::time::SystemTime>| match time
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+    {
+        Some(time) => Value::number(time.as_secs_f64()),
+        None => Value::none(),
+    };
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "dir"
+    } else if file_type.is_file() {
+        "file"
+    } else {
+        "other"
+    };
+
+    let mut info = BTreeMap::new();
+    info.insert(String::from("path"), Value::string(to_string(path)));
+    info.insert(String::from("size"), Value::number(metadata.len() as f64));
+    info.insert(String::from("type"), Value::string(kind));
+    info.insert(String::from("created"), time(metadata.created()));
+    info.insert(String::from("modified"), time(metadata.modified()));
+    info.insert(String::from("accessed"), time(metadata.accessed()));
+    info.insert(
+        String::from("permissions"),
+        Value::string(permissions(&metadata)),
+    );
+    info.insert(
+        String::from("mode"),
+        Value::string(format!("{:o}", mode_bits(&metadata))),
+    );
+    info.insert(
+        String::from("owner"),
+        match owner(&metadata) {
+            Some(owner) => Value::string(owner),
+            None => Value::none(),
+        },
+    );
+    Ok(Ref::new(Value::Tree(info)))
+}
+
+/// Make a symbolic link
+#[cfg(unix)]
+fn make_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Make a symbolic link. Windows has different kinds of links
+/// for files and directories, so the target must already exist.
+#[cfg(windows)]
+fn make_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+
+    let pointee = link.parent().unwrap_or(link).join(target);
+    if pointee.is_dir() {
+        symlink_dir(target, link)
+    } else {
+        symlink_file(target, link)
+    }
+}
+
+/// Symbolic links can't be made on this platform
+#[cfg(not(any(unix, windows)))]
+fn make_symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        ErrorKind::Unsupported,
+        "symbolic links are not supported on this platform",
+    ))
+}
+
+/// Get the name of the user that owns a file, or their
+/// ID if they have no name
+#[cfg(unix)]
+fn owner(metadata: &Metadata) -> Option<String> {
+    use std::ffi::CStr;
+    use std::os::unix::fs::MetadataExt;
+
+    let uid = metadata.uid();
+    let mut user: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut found = std::ptr::null_mut();
+    unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut user,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        );
+    }
+    if found.is_null() {
+        return Some(uid.to_string());
+    }
+    let name = unsafe { CStr::from_ptr(user.pw_name) };
+    Some(name.to_string_lossy().to_string())
+}
+
+/// Files have no owners on this platform
+#[cfg(not(unix))]
+fn owner(_metadata: &Metadata) -> Option<String> {
+    None
+}
+
+/// Find the entries that are different between two maps,
+/// along with the value each of them had in the first map
+fn changes<K: Clone + Ord, V: Clone + PartialEq>(
+    old: &BTreeMap<K, V>,
+    new: &BTreeMap<K, V>,
+) -> Vec<(K, Option<V>)> {
+    old.keys()
+        .chain(new.keys().filter(|key| !old.contains_key(key)))
+        .filter(|key| old.get(key) != new.get(key))
+        .map(|key| (key.clone(), old.get(key).cloned()))
+        .collect()
+}
+
+/// Check that an environment variable can be set to a value, since
+/// the system refuses names that are empty or contain `=`, and names
+/// or values that contain NUL characters
+fn check_env_var(builtin: &str, name: &str, value: &str) -> Result<(), Error> {
+    if name.is_empty() || name.contains(['=', '\0']) {
+        Err(Error::TypeError(format!(
+            "{}: {:?} is not the name of an environment variable",
+            builtin, name
+        )))
+    } else if value.contains('\0') {
+        Err(Error::TypeError(format!(
+            "{}: the value of {} contains a NUL character",
+            builtin, name
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check whether a builtin is being called by a sandboxed shell, and
+/// raise an error if it is. The environment is shared with the programs
+/// that are run outside of the sandbox, and can hold secrets, so it
+/// can't be read or changed in the sandbox.
+fn is_disabled_in_sandbox(builtin: &str) -> bool {
+    let is_sandboxed = Shell::for_call().sandbox.is_some();
+    if is_sandboxed {
+        Error::PermissionDenied(format!("{} is disabled in the sandbox", builtin)).raise();
+    }
+    is_sandboxed
+}
+
+/// Describe an amount of time, such as `1m 3.25s` or `0.50s`
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    let minutes = (seconds / 60.0).floor();
+    let hours = (minutes / 60.0).floor();
+    let seconds = seconds - minutes * 60.0;
+    let minutes = minutes - hours * 60.0;
+    if hours > 0.0 {
+        format!("{}h {}m {:.2}s", hours, minutes, seconds)
+    } else if minutes > 0.0 {
+        format!("{}m {:.2}s", minutes, seconds)
+    } else {
+        format!("{:.2}s", seconds)
+    }
+}
+
+/// Check the number of seconds given to `timeout`
+fn timeout_duration(seconds: f64) -> Result<Duration, Error> {
+    if seconds.is_finite() && seconds >= 0.0 {
+        Ok(Duration::from_secs_f64(seconds))
+    } else {
+        Err(Error::TypeError(format!(
+            "timeout: {} is not a number of seconds",
+            seconds
+        )))
+    }
+}
+
+/// Get the words of a command given to a builtin like `sh`. A single
+/// string is split into words, but several arguments or a list are
+/// used as the words themselves.
+pub fn command_words(args: Vec<Value>) -> Result<Vec<String>, Error> {
+    Ok(match args.as_slice() {
+        [Value::String(command)] => split_words(command)?,
+        _ => args
+            .into_iter()
+            .flat_map(|arg| match arg {
+                Value::List(items) => items
+                    .into_iter()
+                    .map(|item| (*item).clone().to_string())
+                    .collect(),
+                arg => vec![arg.to_string()],
+            })
+            .collect(),
+    })
+}
+
+/// Split a command into words like other shells do. Whitespace separates
+/// words, unless it is inside of quotes or escaped with a backslash.
+/// Nothing is special inside of single quotes, and inside of double
+/// quotes a backslash only escapes a double quote or another backslash.
+pub fn split_words(command: &str) -> Result<Vec<String>, Error> {
+    let mut words = vec![];
+    let mut word = String::new();
+    // Whether a word has been started, so that `''` is an empty word
+    let mut in_word = false;
+    let mut chars = command.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                in_word = true;
+                if let Some(escaped) = chars.next() {
+                    word.push(escaped);
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(ch) => word.push(ch),
+                        None => return Err(unterminated(command, '\'')),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.peek() {
+                            Some(&escaped) if escaped == '"' || escaped == '\\' => {
+                                word.push(escaped);
+                                chars.next();
+                            }
+                            _ => word.push('\\'),
+                        },
+                        Some(ch) => word.push(ch),
+                        None => return Err(unterminated(command, '"')),
+                    }
                 }
             }
-            _ => {}
+            ch if ch.is_whitespace() => {
+                if in_word {
+                    words.push(take(&mut word));
+                    in_word = false;
+                }
+            }
+            ch => {
+                in_word = true;
+                word.push(ch);
+            }
         }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
 
-        self.machine.push(Ref::new(Value::List(result)));
+fn unterminated(command: &str, quote: char) -> Error {
+    Error::ParseError(format!("missing closing {} in `{}`", quote, command))
+}
+
+/// Find an executable program with a name. A name with a slash is
+/// a path, and any other name is looked for in each directory of `PATH`.
+pub fn find_program(dir: &Path, name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = dir.join(name);
+        return Some(path).filter(|path| is_executable(path));
     }
+    split_paths(&var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(path))
+}
 
-    pub fn cd(&mut self, dir: &str) {
-        let mut result = self.directory.clone();
-        result.push(dir);
-        self.directory = match result.canonicalize() {
-            Ok(dir) => dir,
-            _ => self.directory.clone(),
-        };
+/// List the names of the programs in each directory of `PATH`
+pub fn program_names() -> Vec<String> {
+    let mut names = BTreeSet::new();
+    for dir in var_os("PATH").iter().flat_map(split_paths) {
+        for entry in read_dir(dir).into_iter().flatten().flatten() {
+            if is_executable(&entry.path()) {
+                names.insert(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+    names.into_iter().collect()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    match path.metadata() {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Show the names of files in a directory in columns that fit the
+/// terminal, colored by whether they are directories, links, or programs
+fn show_listing(names: &[Ref<Value>], dir: &Path) -> String {
+    let mut names = names
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    let items = names
+        .into_iter()
+        .map(|name| {
+            let path = dir.join(&name);
+            let codes: &[u8] = match path.symlink_metadata() {
+                Ok(metadata) if metadata.file_type().is_symlink() => &[1, 36],
+                Ok(metadata) if metadata.is_dir() => &[1, 34],
+                _ if is_executable(&path) => &[1, 32],
+                _ => &[],
+            };
+            let width = name.chars().count();
+            (paint(&name, codes), width)
+        })
+        .collect::<Vec<_>>();
+    columns(&items, terminal_width())
+}
+
+/// Render file permissions like `ls -l` does
+#[cfg(unix)]
+fn permissions(metadata: &Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = metadata.permissions().mode();
+    let mut result = String::new();
+    for (i, ch) in "rwxrwxrwx".chars().enumerate() {
+        result.push(if mode & (0o400 >> i) != 0 { ch } else { '-' });
+    }
+    result
+}
+
+/// Render file permissions like `ls -l` does
+#[cfg(not(unix))]
+fn permissions(metadata: &Metadata) -> String {
+    String::from(if metadata.permissions().readonly() {
+        "r--r--r--"
+    } else {
+        "rw-rw-rw-"
+    })
+}
+
+/// Collect every path under a directory in sorted order, listing each
+/// directory before its contents. Symbolic links are not followed.
+fn walk_dir(dir: &Path, depth: Option<usize>, paths: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if depth == Some(0) {
+        return Ok(());
     }
 
-    pub fn sh(&mut self, cmd: &str) {
-        let components = cmd.split_whitespace().collect::<Vec<&str>>();
-        if !components.is_empty() {
-            match Command::new(components[0])
-                .args(components[1..].iter())
-                .stdin(Stdio::inherit())
-                .stdout(Stdio::inherit())
-                .output() { _ => {} };
+    let mut entries = read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        paths.push(entry.path());
+        if entry.file_type()?.is_dir() {
+            walk_dir(&entry.path(), depth.map(|d| d - 1), paths)?;
         }
     }
+    Ok(())
+}
 
-    pub fn clear(&mut self) {
-        println!("{}", "\n".repeat(200));
+/// Move a file or directory, even to a different file system
+fn move_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    match rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {}
+        result => return result,
+    }
+    if from.is_dir() {
+        copy_dir(from, to)?;
+        remove_dir_all(from)
+    } else {
+        copy(from, to)?;
+        remove_file(from)
     }
+}
 
-    pub fn exit(&mut self) {
-        self.is_done = true;
+/// Recursively copy a directory and everything inside of it
+fn copy_dir(src: &Path, dst: &Path) -> std::io::Result<()> {
+    create_dir_all(dst)?;
+    for entry in read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compile a regular expression, raising an error if it is invalid
+fn regex(pattern: &str) -> Option<Regex> {
+    match Regex::new(pattern) {
+        Ok(re) => Some(re),
+        Err(e) => {
+            Error::TypeError(format!("invalid regular expression: {}", e)).raise();
+            None
+        }
     }
 }
 
-fn add_fn(m: &mut Machine, function: fn(&mut Machine) -> (), name: &str) {
-    m.push(Value::function(function, &m));
+/// Get the text of the whole match followed by each capture group.
+/// Groups that did not participate in the match are none.
+fn captures_list(captures: &Captures) -> Ref<Value> {
+    Ref::new(Value::List(
+        captures
+            .iter()
+            .map(|group| match group {
+                Some(group) => Value::string(group.as_str()),
+                None => Value::none(),
+            })
+            .collect(),
+    ))
+}
+
+fn add_fn(m: &mut Machine, function: impl 'static + Fn(&mut Machine), name: &str) {
+    m.push(Value::function(function, m));
     m.push(Value::string(name));
     m.store();
 }
 
+/// Find the position in a list that an index refers to.
+/// Negative indices count backwards from the end of the list.
+fn list_index(len: usize, index: f64) -> Option<usize> {
+    let index = if index < 0.0 {
+        len as f64 + index
+    } else {
+        index
+    };
+    if index >= 0.0 && (index as usize) < len {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+/// Order numbers before strings, and leave any
+/// other kinds of values in the order they are in
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Number(_), Value::String(_)) => Ordering::Less,
+        (Value::String(_), Value::Number(_)) => Ordering::Greater,
+        _ => Ordering::Equal,
+    }
+}
+
+/// Store a dictionary of functions under a name, so that
+/// related builtins can be grouped together like `str.split`
+fn add_namespace(m: &mut Machine, functions: &[(&str, MachineFn)], name: &str) {
+    let mut namespace = BTreeMap::new();
+    for (function_name, function) in functions {
+        namespace.insert(function_name.to_string(), Value::function(*function, m));
+    }
+    add_const(m, Value::Tree(namespace), name);
+}
+
+/// Make a string of part of a path, or none if the path doesn't have it
+fn path_part(part: Option<&std::ffi::OsStr>) -> Ref<Value> {
+    match part {
+        Some(part) => Value::string(part.to_string_lossy()),
+        None => Value::none(),
+    }
+}
+
+/// Get the rows given to `table`, if they are all dictionaries
+fn table_rows(m: &mut Machine) -> Option<Vec<BTreeMap<String, Ref<Value>>>> {
+    match m.pop().as_deref() {
+        Some(Value::List(rows)) => rows
+            .iter()
+            .map(|row| match &**row {
+                Value::Tree(row) => Some(row.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
 fn add_const(m: &mut Machine, value: impl Into<Value>, name: &str) {
     m.push(Ref::new(value.into()));
     m.push(Value::string(name));
     m.store();
 }
 
-fn machine() -> Machine {
+fn machine(io: &SharedIo) -> Machine {
     let m = &mut Machine::new();
-    add_const(m, 1, "true");
-    add_const(m, 0, "false");
+    let out = Rc::clone(io);
     add_fn(
         m,
-        |m| {
-            print!(
+        move |m| {
+            out.borrow_mut().write(&format!(
                 "{}",
                 match m.pop() {
                     Some(v) => v,
                     None => Value::string(""),
                 }
-            );
+            ));
         },
         "print",
     );
+    let out = Rc::clone(io);
+    add_fn(
+        m,
+        move |m| {
+            out.borrow_mut().write(&format!(
+                "{}\n",
+                match m.pop() {
+                    Some(v) => v,
+                    None => Value::string(""),
+                }
+            ));
+        },
+        "println",
+    );
+    add_fn(
+        m,
+        |m| {
+            m.push(Value::tree());
+        },
+        "dict",
+    );
+    add_fn(
+        m,
+        |m| {
+            let dict = m.get_arg::<BTreeMap<String, Ref<Value>>>();
+            m.push(Ref::new(Value::List(
+                dict.keys().map(Value::string).collect(),
+            )));
+        },
+        "keys",
+    );
+    add_fn(
+        m,
+        |m| {
+            let dict = m.get_arg::<BTreeMap<String, Ref<Value>>>();
+            m.push(Ref::new(Value::List(dict.values().cloned().collect())));
+        },
+        "values",
+    );
+    add_fn(
+        m,
+        |m| {
+            let dict = m.get_arg::<BTreeMap<String, Ref<Value>>>();
+            let key = match m.pop() {
+                Some(v) => v.to_string(),
+                None => String::new(),
+            };
+            m.push(Value::number(dict.contains_key(&key) as i32));
+        },
+        "has_key",
+    );
+    add_fn(
+        m,
+        |m| {
+            let is_none = match m.pop() {
+                Some(v) => matches!(*v, Value::None),
+                None => true,
+            };
+            m.push(Value::number(is_none as i32));
+        },
+        "is_none",
+    );
     add_fn(
         m,
         |m| {
-            println!(
-                "{}",
-                match m.pop() {
-                    Some(v) => v,
-                    None => Value::string(""),
-                }
-            );
+            let mut dict = m.get_arg::<BTreeMap<String, Ref<Value>>>();
+            if let Some(key) = m.pop() {
+                dict.remove(&key.to_string());
+            }
+            m.push(Ref::new(Value::Tree(dict)));
         },
-        "println",
+        "remove_key",
     );
     add_fn(
         m,
         |m| {
-            m.push(Value::tree());
+            // The entries of the second dictionary
+            // replace the entries of the first
+            let mut dict = m.get_arg::<BTreeMap<String, Ref<Value>>>();
+            let other = m.get_arg::<BTreeMap<String, Ref<Value>>>();
+            dict.extend(other);
+            m.push(Ref::new(Value::Tree(dict)));
         },
-        "dict",
+        "merge",
     );
     add_fn(
         m,
         |m| {
             let function = match m.pop() {
                 Some(f) => f,
-                None => Value::function(|_| {}, &m),
+                None => Value::function(|_| {}, m),
             };
 
             let list = m.get_arg::<Vec<Ref<xmachine::Value>>>();
@@ -251,11 +2857,8 @@ fn machine() -> Machine {
     add_fn(
         m,
         |m| {
-            let a = match m.pop() {
-                Some(v) => (*v).clone(),
-                _ => return,
-            };
-            m.return_value(!a);
+            let a = m.pop().is_some_and(|v| is_true(&v));
+            m.push(Value::number(!a as i32));
         },
         "not",
     );
@@ -388,75 +2991,1113 @@ fn machine() -> Machine {
         },
         "rem",
     );
+    let input = Rc::clone(io);
     add_fn(
         m,
-        |m| {
-            print!(
-                "{}",
-                match m.pop() {
-                    Some(v) => v,
-                    None => Value::string(""),
-                }
-            );
+        move |m| {
+            let prompt = match m.pop() {
+                Some(v) => v.to_string(),
+                None => String::new(),
+            };
 
-            m.push(Value::string(input::<String>().get().trim()));
+            let line = input.borrow_mut().read_line(&prompt);
+            match line {
+                Line::Text(line) => m.push(Value::string(line.trim())),
+                Line::Interrupted => Error::Interrupted.raise(),
+                Line::End => m.push(Value::none()),
+            }
         },
         "input",
     );
+    let menu = Rc::clone(io);
+    add_fn(
+        m,
+        move |m| {
+            let items = m.get_arg::<Vec<Ref<Value>>>();
+            let choices = items
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<String>>();
+            let choice = match select(&choices) {
+                Ok(choice) => choice,
+                // Without a terminal to show a menu in,
+                // the choices are numbered instead
+                Err(_) => {
+                    let mut io = menu.borrow_mut();
+                    for (i, choice) in choices.iter().enumerate() {
+                        io.write(&format!("{}) {}\n", i + 1, choice));
+                    }
+                    match io.read_line("? ") {
+                        Line::Text(line) => line
+                            .trim()
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|n| (1..=items.len()).contains(n))
+                            .map(|n| n - 1),
+                        Line::Interrupted => return Error::Interrupted.raise(),
+                        Line::End => None,
+                    }
+                }
+            };
+            m.push(match choice {
+                Some(i) => Ref::clone(&items[i]),
+                None => Value::none(),
+            });
+        },
+        "select",
+    );
+    let ask = Rc::clone(io);
+    add_fn(
+        m,
+        move |m| {
+            let question = match m.pop() {
+                Some(v) => v.to_string(),
+                None => String::from("Are you sure?"),
+            };
+            // Anything other than yes is taken as no, so that
+            // nothing is done by accident
+            let line = ask.borrow_mut().read_line(&format!("{} [y/N] ", question));
+            match line {
+                Line::Text(answer) => {
+                    let answer = answer.trim().to_lowercase();
+                    m.push(Value::number((answer == "y" || answer == "yes") as i32));
+                }
+                Line::Interrupted => Error::Interrupted.raise(),
+                Line::End => m.push(Value::number(0)),
+            }
+        },
+        "confirm",
+    );
+    let secret = Rc::clone(io);
+    add_fn(
+        m,
+        move |m| {
+            let prompt = match m.pop() {
+                Some(v) => v.to_string(),
+                None => String::from("Password: "),
+            };
+            // Without a terminal, there is nothing to hide the input from
+            let line = match read_password(&prompt) {
+                Ok(line) => Line::Text(line),
+                Err(_) => secret.borrow_mut().read_line(&prompt),
+            };
+            match line {
+                Line::Text(line) => m.push(Value::string(line)),
+                Line::Interrupted => Error::Interrupted.raise(),
+                Line::End => m.push(Value::none()),
+            }
+        },
+        "password",
+    );
+    // This isn't a builtin so that `z` can still be used as a
+    // variable, but it works on the shell that calls it
     add_fn(
         m,
         |m| {
-            let command = match m.pop() {
-                Some(v) => (*v).clone(),
-                _ => return,
+            let mut words = vec![];
+            while let Some(word) = m.pop() {
+                words.push(word.to_string());
+            }
+            let shell = &mut Shell::for_call();
+            if let Err(e) = shell.z(&words) {
+                return e.raise();
+            }
+            shell.return_to_caller();
+            if let Some(value) = shell.machine.pop() {
+                m.push(value);
+            }
+        },
+        "z",
+    );
+    add_fn(
+        m,
+        |m| {
+            if is_disabled_in_sandbox("env") {
+                return;
+            }
+            let mut vars = BTreeMap::new();
+            for (name, value) in vars_os() {
+                vars.insert(
+                    name.to_string_lossy().to_string(),
+                    Value::string(value.to_string_lossy()),
+                );
+            }
+            m.push(Ref::new(Value::Tree(vars)));
+        },
+        "env",
+    );
+    add_fn(
+        m,
+        |m| {
+            if is_disabled_in_sandbox("getenv") {
+                return;
+            }
+            let name = m.get_arg::<String>();
+            m.push(match var(name) {
+                Ok(value) => Value::string(value),
+                Err(_) => Value::none(),
+            });
+        },
+        "getenv",
+    );
+    add_fn(
+        m,
+        |m| {
+            if is_disabled_in_sandbox("setenv") {
+                return;
+            }
+            let name = m.get_arg::<String>();
+            let value = match m.pop() {
+                Some(v) => v.to_string(),
+                None => String::new(),
             };
+            match check_env_var("setenv", &name, &value) {
+                Ok(()) => set_var(name, value),
+                Err(e) => e.raise(),
+            }
+        },
+        "setenv",
+    );
+    add_fn(
+        m,
+        |m| {
+            if is_disabled_in_sandbox("unsetenv") {
+                return;
+            }
+            let name = m.get_arg::<String>();
+            match check_env_var("unsetenv", &name, "") {
+                Ok(()) => remove_var(name),
+                Err(e) => e.raise(),
+            }
+        },
+        "unsetenv",
+    );
+    add_fn(
+        m,
+        |m| {
+            let pattern = m.get_arg::<String>();
+            let text = m.get_arg::<String>();
+            if let Some(re) = regex(&pattern) {
+                m.push(match re.captures(&text) {
+                    Some(captures) => captures_list(&captures),
+                    None => Value::none(),
+                });
+            }
+        },
+        "re_match",
+    );
+    add_fn(
+        m,
+        |m| {
+            let pattern = m.get_arg::<String>();
+            let text = m.get_arg::<String>();
+            if let Some(re) = regex(&pattern) {
+                // Patterns without groups find the matching text,
+                // otherwise each match is a list of its groups
+                let result = if re.captures_len() > 1 {
+                    re.captures_iter(&text)
+                        .map(|captures| captures_list(&captures))
+                        .collect()
+                } else {
+                    re.find_iter(&text)
+                        .map(|found| Value::string(found.as_str()))
+                        .collect()
+                };
+                m.push(Ref::new(Value::List(result)));
+            }
+        },
+        "re_find_all",
+    );
+    add_fn(
+        m,
+        |m| {
+            let pattern = m.get_arg::<String>();
+            let text = m.get_arg::<String>();
+            let replacement = m.get_arg::<String>();
+            if let Some(re) = regex(&pattern) {
+                m.push(Value::string(re.replace_all(&text, replacement.as_str())));
+            }
+        },
+        "re_replace",
+    );
+    add_namespace(
+        m,
+        &[
+            ("split", |m| {
+                let text = m.get_arg::<String>();
+                let separator = m.get_arg::<String>();
+                // Without a separator, split on any whitespace
+                let parts: Vec<Ref<Value>> = if separator.is_empty() {
+                    text.split_whitespace().map(Value::string).collect()
+                } else {
+                    text.split(separator.as_str()).map(Value::string).collect()
+                };
+                m.push(Ref::new(Value::List(parts)));
+            }),
+            ("join", |m| {
+                let list = m.get_arg::<Vec<Ref<Value>>>();
+                let separator = m.get_arg::<String>();
+                let parts = list.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                m.push(Value::string(parts.join(&separator)));
+            }),
+            ("trim", |m| {
+                let text = m.get_arg::<String>();
+                m.push(Value::string(text.trim()));
+            }),
+            ("upper", |m| {
+                let text = m.get_arg::<String>();
+                m.push(Value::string(text.to_uppercase()));
+            }),
+            ("lower", |m| {
+                let text = m.get_arg::<String>();
+                m.push(Value::string(text.to_lowercase()));
+            }),
+            ("replace", |m| {
+                let text = m.get_arg::<String>();
+                let from = m.get_arg::<String>();
+                let to = m.get_arg::<String>();
+                m.push(Value::string(if from.is_empty() {
+                    text
+                } else {
+                    text.replace(&from, &to)
+                }));
+            }),
+            ("contains", |m| {
+                let text = m.get_arg::<String>();
+                let pattern = m.get_arg::<String>();
+                m.push(Value::number(text.contains(&pattern) as i32));
+            }),
+            ("starts_with", |m| {
+                let text = m.get_arg::<String>();
+                let prefix = m.get_arg::<String>();
+                m.push(Value::number(text.starts_with(&prefix) as i32));
+            }),
+            ("ends_with", |m| {
+                let text = m.get_arg::<String>();
+                let suffix = m.get_arg::<String>();
+                m.push(Value::number(text.ends_with(&suffix) as i32));
+            }),
+            ("len", |m| {
+                let text = m.get_arg::<String>();
+                m.push(Value::number(text.chars().count() as f64));
+            }),
+            ("substr", |m| {
+                // Indices count characters, and the end is
+                // the end of the string if it is not given
+                let text = m.get_arg::<String>();
+                let start = m.get_arg::<f64>().max(0.0) as usize;
+                let end = match m.pop() {
+                    Some(v) => f64::from((*v).clone()).max(0.0) as usize,
+                    None => text.chars().count(),
+                };
+                let result = text
+                    .chars()
+                    .skip(start)
+                    .take(end.saturating_sub(start))
+                    .collect::<String>();
+                m.push(Value::string(result));
+            }),
+        ],
+        "str",
+    );
+    // Parts of a path that it doesn't have, like the
+    // extension of a directory, are none
+    add_namespace(
+        m,
+        &[
+            ("join", |m| {
+                let mut result = PathBuf::from(m.get_arg::<String>());
+                while let Some(part) = m.pop() {
+                    result.push(part.to_string());
+                }
+                m.push(Value::string(to_string(&result)));
+            }),
+            ("parent", |m| {
+                let path = m.get_arg::<String>();
+                m.push(match Path::new(&path).parent() {
+                    Some(parent) if parent.as_os_str().is_empty() => Value::string("."),
+                    Some(parent) => Value::string(to_string(parent)),
+                    None => Value::none(),
+                });
+            }),
+            ("filename", |m| {
+                let path = m.get_arg::<String>();
+                m.push(path_part(Path::new(&path).file_name()));
+            }),
+            ("extension", |m| {
+                let path = m.get_arg::<String>();
+                m.push(path_part(Path::new(&path).extension()));
+            }),
+            ("stem", |m| {
+                let path = m.get_arg::<String>();
+                m.push(path_part(Path::new(&path).file_stem()));
+            }),
+            ("is_absolute", |m| {
+                let path = m.get_arg::<String>();
+                m.push(Value::number(Path::new(&path).is_absolute() as i32));
+            }),
+            ("exists", |m| {
+                // Relative paths are relative to the directory of the caller
+                let path = m.get_arg::<String>();
+                match Shell::for_call().resolve(&path) {
+                    Ok(path) => m.push(Value::number(path.exists() as i32)),
+                    Err(e) => e.raise(),
+                }
+            }),
+        ],
+        "path",
+    );
+    add_const(m, std::f64::consts::PI, "pi");
+    add_const(m, std::f64::consts::E, "e");
+    add_namespace(
+        m,
+        &[
+            ("sqrt", |m| {
+                let x = m.get_arg::<f64>();
+                m.push(Value::number(x.sqrt()));
+            }),
+            ("pow", |m| {
+                let x = m.get_arg::<f64>();
+                let y = m.get_arg::<f64>();
+                m.push(Value::number(x.powf(y)));
+            }),
+            ("abs", |m| {
+                let x = m.get_arg::<f64>();
+                m.push(Value::number(x.abs()));
+            }),
+            ("floor", |m| {
+                let x = m.get_arg::<f64>();
+                m.push(Value::number(x.floor()));
+            }),
+            ("ceil", |m| {
+                let x = m.get_arg::<f64>();
+                m.push(Value::number(x.ceil()));
+            }),
+            ("round", |m| {
+                let x = m.get_arg::<f64>();
+                m.push(Value::number(x.round()));
+            }),
+            ("min", |m| {
+                let x = m.get_arg::<f64>();
+                let y = m.get_arg::<f64>();
+                m.push(Value::number(x.min(y)));
+            }),
+            ("max", |m| {
+                let x = m.get_arg::<f64>();
+                let y = m.get_arg::<f64>();
+                m.push(Value::number(x.max(y)));
+            }),
+            ("log", |m| {
+                // Without a base, this is the natural logarithm
+                let x = m.get_arg::<f64>();
+                let result = match m.pop() {
+                    Some(base) => x.log(f64::from((*base).clone())),
+                    None => x.ln(),
+                };
+                m.push(Value::number(result));
+            }),
+            ("sin", |m| {
+                let x = m.get_arg::<f64>();
+                m.push(Value::number(x.sin()));
+            }),
+            ("cos", |m| {
+                let x = m.get_arg::<f64>();
+                m.push(Value::number(x.cos()));
+            }),
+            ("tan", |m| {
+                let x = m.get_arg::<f64>();
+                m.push(Value::number(x.tan()));
+            }),
+            ("asin", |m| {
+                let x = m.get_arg::<f64>();
+                m.push(Value::number(x.asin()));
+            }),
+            ("acos", |m| {
+                let x = m.get_arg::<f64>();
+                m.push(Value::number(x.acos()));
+            }),
+            ("atan", |m| {
+                let x = m.get_arg::<f64>();
+                m.push(Value::number(x.atan()));
+            }),
+        ],
+        "math",
+    );
+    // Values cannot be changed in place, so the functions
+    // that modify a list give back a new list instead
+    add_namespace(
+        m,
+        &[
+            ("len", |m| {
+                let list = m.get_arg::<Vec<Ref<Value>>>();
+                m.push(Value::number(list.len() as f64));
+            }),
+            ("push", |m| {
+                let mut list = m.get_arg::<Vec<Ref<Value>>>();
+                if let Some(item) = m.pop() {
+                    list.push(item);
+                }
+                m.push(Ref::new(Value::List(list)));
+            }),
+            ("pop", |m| {
+                let mut list = m.get_arg::<Vec<Ref<Value>>>();
+                list.pop();
+                m.push(Ref::new(Value::List(list)));
+            }),
+            ("get", |m| {
+                let list = m.get_arg::<Vec<Ref<Value>>>();
+                let index = m.get_arg::<f64>();
+                m.push(match list_index(list.len(), index) {
+                    Some(i) => Ref::clone(&list[i]),
+                    None => Value::none(),
+                });
+            }),
+            ("set", |m| {
+                let mut list = m.get_arg::<Vec<Ref<Value>>>();
+                let index = m.get_arg::<f64>();
+                let item = m.pop().unwrap_or_else(Value::none);
+                match list_index(list.len(), index) {
+                    Some(i) => list[i] = item,
+                    None => {
+                        return Error::TypeError(format!(
+                            "index {} is out of bounds for a list of length {}",
+                            index,
+                            list.len()
+                        ))
+                        .raise()
+                    }
+                }
+                m.push(Ref::new(Value::List(list)));
+            }),
+            ("sort", |m| {
+                let mut list = m.get_arg::<Vec<Ref<Value>>>();
+                list.sort_by(|a, b| compare(a, b));
+                m.push(Ref::new(Value::List(list)));
+            }),
+            ("reverse", |m| {
+                let mut list = m.get_arg::<Vec<Ref<Value>>>();
+                list.reverse();
+                m.push(Ref::new(Value::List(list)));
+            }),
+            ("filter", |m| {
+                let list = m.get_arg::<Vec<Ref<Value>>>();
+                let function = match m.pop() {
+                    Some(f) => f,
+                    None => return m.push(Ref::new(Value::List(list))),
+                };
 
-            match program().parse(&format!("{}", command)) {
-                Ok(v) => {
-                    let shell = &mut Shell::new();
-                    match v.execute(shell) {
-                        _ => {}
-                    };
-                    shell.print_stack();
-                    shell.clear_stack();
+                let mut result = vec![];
+                for item in list {
+                    m.push(Ref::clone(&item));
+                    m.push(Ref::clone(&function));
+                    m.call();
+                    if m.get_arg::<bool>() {
+                        result.push(item);
+                    }
                 }
-                Err(e) => println!("Error: {:?}", e),
-            };
+                m.push(Ref::new(Value::List(result)));
+            }),
+            ("reduce", |m| {
+                // Without an initial value, the
+                // first item of the list is used
+                let mut list = m.get_arg::<Vec<Ref<Value>>>().into_iter();
+                let function = m.pop();
+                let initial = m.pop();
+                let function = match function {
+                    Some(f) => f,
+                    None => return m.push(initial.unwrap_or_else(Value::none)),
+                };
+
+                let mut result = match initial.or_else(|| list.next()) {
+                    Some(value) => value,
+                    None => return m.push(Value::none()),
+                };
+                for item in list {
+                    m.push(item);
+                    m.push(result);
+                    m.push(Ref::clone(&function));
+                    m.call();
+                    result = m.pop().unwrap_or_else(Value::none);
+                }
+                m.push(result);
+            }),
+            ("contains", |m| {
+                let list = m.get_arg::<Vec<Ref<Value>>>();
+                let item = m.pop();
+                let found = match item {
+                    Some(item) => list.contains(&item),
+                    None => false,
+                };
+                m.push(Value::number(found as i32));
+            }),
+            ("slice", |m| {
+                // The end is the end of the list if it is not given
+                let list = m.get_arg::<Vec<Ref<Value>>>();
+                let start = m.get_arg::<f64>().max(0.0) as usize;
+                let end = match m.pop() {
+                    Some(v) => f64::from((*v).clone()).max(0.0) as usize,
+                    None => list.len(),
+                };
+                let result = list
+                    .into_iter()
+                    .skip(start)
+                    .take(end.saturating_sub(start))
+                    .collect();
+                m.push(Ref::new(Value::List(result)));
+            }),
+        ],
+        "list",
+    );
+    add_fn(
+        m,
+        |m| {
+            m.push(Value::number(rand::thread_rng().gen::<f64>()));
         },
-        "eval",
+        "random",
     );
     add_fn(
         m,
         |m| {
-            println!("{}", INFO);
-            println!("{}", m);
+            // Both of the bounds can be picked
+            let lo = m.get_arg::<f64>().round() as i64;
+            let hi = m.get_arg::<f64>().round() as i64;
+            let n = rand::thread_rng().gen_range(lo.min(hi)..=lo.max(hi));
+            m.push(Value::number(n as f64));
         },
-        "help",
+        "random_int",
     );
     add_fn(
         m,
         |m| {
-            println!("{}", INFO);
-            println!("{}", m);
+            let mut list = m.get_arg::<Vec<Ref<Value>>>();
+            list.shuffle(&mut rand::thread_rng());
+            m.push(Ref::new(Value::List(list)));
         },
-        "debug",
+        "shuffle",
+    );
+    add_fn(
+        m,
+        |m| {
+            let list = m.get_arg::<Vec<Ref<Value>>>();
+            m.push(match list.choose(&mut rand::thread_rng()) {
+                Some(item) => Ref::clone(item),
+                None => Value::none(),
+            });
+        },
+        "choice",
+    );
+    add_fn(
+        m,
+        |m| {
+            m.push(Value::string(uuid::Uuid::new_v4()));
+        },
+        "uuid",
+    );
+    add_fn(
+        m,
+        |m| {
+            let text = m.get_arg::<String>();
+            match serde_json::from_str::<DuneValue>(&text) {
+                Ok(data) => m.push(data.into_value()),
+                Err(e) => Error::ParseError(format!("invalid json: {}", e)).raise(),
+            }
+        },
+        "json_parse",
+    );
+    add_fn(
+        m,
+        |m| {
+            let value = m.pop().unwrap_or_else(Value::none);
+            let result = DuneValue::from_value(&value).and_then(|data| {
+                serde_json::to_string(&data).map_err(|e| Error::TypeError(e.to_string()))
+            });
+            match result {
+                Ok(json) => m.push(Value::string(json)),
+                Err(e) => e.raise(),
+            }
+        },
+        "json_string",
+    );
+    add_fn(
+        m,
+        |m| {
+            let text = m.get_arg::<String>();
+            match toml::from_str(&text) {
+                Ok(toml) => m.push(DuneValue::from_toml(toml).into_value()),
+                Err(e) => Error::ParseError(format!("invalid toml: {}", e)).raise(),
+            }
+        },
+        "toml_parse",
+    );
+    add_fn(
+        m,
+        |m| {
+            let value = m.pop().unwrap_or_else(Value::none);
+            let result = DuneValue::from_value(&value).and_then(|data| {
+                toml::to_string(&data).map_err(|e| Error::TypeError(format!("invalid toml: {}", e)))
+            });
+            match result {
+                Ok(toml) => m.push(Value::string(toml)),
+                Err(e) => e.raise(),
+            }
+        },
+        "toml_string",
+    );
+    add_fn(
+        m,
+        |m| {
+            let text = m.get_arg::<String>();
+            match serde_yaml::from_str::<DuneValue>(&text) {
+                Ok(data) => m.push(data.into_value()),
+                Err(e) => Error::ParseError(format!("invalid yaml: {}", e)).raise(),
+            }
+        },
+        "yaml_parse",
+    );
+    add_fn(
+        m,
+        |m| {
+            let value = m.pop().unwrap_or_else(Value::none);
+            let result = DuneValue::from_value(&value).and_then(|data| {
+                serde_yaml::to_string(&data)
+                    .map_err(|e| Error::TypeError(format!("invalid yaml: {}", e)))
+            });
+            match result {
+                Ok(yaml) => m.push(Value::string(yaml)),
+                Err(e) => e.raise(),
+            }
+        },
+        "yaml_string",
     );
+    // Each color and style has a function that wraps text in it
+    for (name, code) in COLORS.iter().chain(STYLES) {
+        add_fn(
+            m,
+            move |m| {
+                let text = m.pop().map(|v| v.to_string()).unwrap_or_default();
+                m.push(Value::string(paint(&text, &[*code])));
+            },
+            name,
+        );
+    }
     add_fn(
         m,
         |m| {
-            println!("{}", INFO);
-            println!("{}", m);
+            let text = m.pop().map(|v| v.to_string()).unwrap_or_default();
+            let options = match m.pop().as_deref() {
+                Some(Value::Tree(options)) => options.clone(),
+                _ => BTreeMap::new(),
+            };
+            // Colors are given by name, and styles are turned on with true
+            let mut codes = vec![];
+            for (option, value) in options {
+                let code = match option.as_str() {
+                    "fg" | "color" => color_code(&value.to_string(), false),
+                    "bg" => color_code(&value.to_string(), true),
+                    style if is_true(&value) => style_code(style),
+                    style if style_code(style).is_some() => continue,
+                    _ => None,
+                };
+                match code {
+                    Some(code) => codes.push(code),
+                    None => {
+                        return Error::TypeError(format!(
+                            "style: unknown option {} = {}",
+                            option, value
+                        ))
+                        .raise()
+                    }
+                }
+            }
+            m.push(Value::string(paint(&text, &codes)));
+        },
+        "style",
+    );
+    let out = Rc::clone(io);
+    add_fn(
+        m,
+        move |m| match table_rows(m) {
+            Some(rows) => out.borrow_mut().write(&table(&rows, colors_enabled())),
+            None => {
+                Error::TypeError(String::from("table: expected a list of dictionaries")).raise()
+            }
+        },
+        "table",
+    );
+    add_fn(
+        m,
+        |m| match table_rows(m) {
+            Some(rows) => m.push(Value::string(table(&rows, false))),
+            None => Error::TypeError(String::from(
+                "table_string: expected a list of dictionaries",
+            ))
+            .raise(),
+        },
+        "table_string",
+    );
+    let out = Rc::clone(io);
+    add_fn(
+        m,
+        move |m| {
+            let text = match m.pop() {
+                Some(topic) => match help_topic(&topic.to_string()) {
+                    Ok(text) => text,
+                    Err(e) => return e.raise(),
+                },
+                None => format!(
+                    "{}\nUse `help(\"name\")` to learn more about a builtin, or `help(\"str\")` for a group or namespace.\n\n",
+                    INFO
+                ) + &list_all_help(),
+            };
+            out.borrow_mut().write(&text);
+        },
+        "help",
+    );
+    let out = Rc::clone(io);
+    add_fn(
+        m,
+        move |m| {
+            out.borrow_mut().write(&format!("{}\n{}\n", INFO, m));
+        },
+        "debug",
+    );
+    let out = Rc::clone(io);
+    add_fn(
+        m,
+        move |m| {
+            out.borrow_mut().write(&format!("{}\n{}\n", INFO, m));
         },
         "info",
     );
+    let out = Rc::clone(io);
     add_fn(
         m,
-        |_| {
-            println!("{}", INFO);
-            println!("{}", LOGO);
+        move |_| {
+            out.borrow_mut().write(&format!("{}\n{}\n", INFO, LOGO));
         },
         "logo",
     );
 
     m.clone()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{command_words, split_words, to_string, Shell};
+    use crate::hash::hash_reader;
+    use crate::input::{Line, ShellIo};
+    use crate::tokens::Error;
+    use std::cell::RefCell;
+    use std::env::{set_var, temp_dir, var_os};
+    use std::fs::{create_dir_all, write, File};
+    use std::path::PathBuf;
+    use std::rc::Rc;
+    use std::sync::Once;
+    use xmachine::Value;
+
+    /// Output that is kept for a test to check, with no input
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<String>>);
+
+    impl ShellIo for Output {
+        fn read_line(&mut self, _prompt: &str) -> Line {
+            Line::End
+        }
+
+        fn write(&mut self, text: &str) {
+            self.0.borrow_mut().push_str(text);
+        }
+
+        fn write_err(&mut self, text: &str) {
+            self.0.borrow_mut().push_str(text);
+        }
+    }
+
+    /// Make an empty directory for a test to use
+    fn scratch_dir() -> PathBuf {
+        let dir = temp_dir().join(format!("dune-test-{}", uuid::Uuid::new_v4()));
+        create_dir_all(&dir).expect("the directory should be made");
+        dir
+    }
+
+    /// Give the tests a home directory of their own, so that they
+    /// don't read or change the files in the user's home directory
+    fn scratch_home() -> PathBuf {
+        static HOME: Once = Once::new();
+        HOME.call_once(|| set_var("HOME", scratch_dir()));
+        dirs::home_dir().expect("the tests should have a home directory")
+    }
+
+    #[test]
+    fn env_vars_the_system_refuses_are_type_errors() {
+        let mut shell = Shell::new();
+        for source in [
+            r#"setenv("", "x")"#,
+            r#"setenv("DUNE_TEST=A", "x")"#,
+            r#"setenv("DUNE_TEST\0A", "x")"#,
+            r#"setenv("DUNE_TEST_NUL", "a\0b")"#,
+            r#"unsetenv("DUNE_TEST=A")"#,
+        ] {
+            match shell.eval(source).map_err(|e| e.cause().clone()) {
+                Err(Error::TypeError(_)) => {}
+                result => panic!("{} should be a type error, not {:?}", source, result),
+            }
+        }
+        assert_eq!(var_os("DUNE_TEST_NUL"), None);
+    }
+
+    #[test]
+    fn dir_env_changed_in_a_function_is_undone_when_leaving() {
+        let home = scratch_home();
+        let dir = scratch_dir();
+        let file = dir.join(".dune-env");
+        write(&file, r#"setenv("DUNE_TEST_DIR_ENV", "entered")"#).unwrap();
+        let hash = hash_reader("sha256", File::open(&file).unwrap()).unwrap();
+        write(
+            home.join(".dune_allowed"),
+            format!("{} {}\n", hash, to_string(&file)),
+        )
+        .unwrap();
+
+        let mut shell = Shell::new();
+        shell.directory = dir.parent().unwrap().to_path_buf();
+        let enter = format!("fn enter() {{ cd({:?}) }} enter()", to_string(&dir));
+        shell.eval(&enter).unwrap();
+        assert_eq!(shell.directory, dir);
+        assert_eq!(var_os("DUNE_TEST_DIR_ENV"), Some("entered".into()));
+        assert_eq!(shell.dir_envs.len(), 1);
+
+        shell.eval(r#"fn leave() { cd("..") } leave()"#).unwrap();
+        assert_eq!(var_os("DUNE_TEST_DIR_ENV"), None);
+        assert!(shell.dir_envs.is_empty());
+    }
+
+    #[test]
+    fn environment_is_disabled_in_the_sandbox() {
+        set_var("DUNE_TEST_SECRET", "secret");
+        let mut shell = Shell::sandboxed(scratch_dir());
+        for source in [
+            "env()",
+            r#"getenv("DUNE_TEST_SECRET")"#,
+            r#"setenv("DUNE_TEST_SECRET", "changed")"#,
+            r#"unsetenv("DUNE_TEST_SECRET")"#,
+        ] {
+            match shell.eval(source).map_err(|e| e.cause().clone()) {
+                Err(Error::PermissionDenied(_)) => {}
+                result => panic!("{} should be denied, not {:?}", source, result),
+            }
+        }
+        assert_eq!(var_os("DUNE_TEST_SECRET"), Some("secret".into()));
+    }
+
+    /// Names that aren't valid unicode are shown with their bad bytes
+    /// replaced, instead of stopping the shell
+    #[cfg(unix)]
+    #[test]
+    fn paths_that_are_not_unicode_are_listed() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = scratch_dir();
+        write(dir.join(OsStr::from_bytes(b"bad\xffname")), "").unwrap();
+        let mut shell = Shell::new();
+        shell.directory = dir;
+        for source in ["ls", r#"walk(".")"#, r#"find(".", fn(path) { true })"#] {
+            let listed = shell.eval(source).unwrap().pop().unwrap().to_string();
+            assert!(
+                listed.contains("bad\u{fffd}name"),
+                "{} gave {}",
+                source,
+                listed
+            );
+        }
+        match shell
+            .eval(r#"ls("missing")"#)
+            .map_err(|e| e.cause().clone())
+        {
+            Err(Error::IoError(message)) => assert!(message.starts_with("ls: "), "{}", message),
+            result => panic!("ls of a missing directory should fail, not {:?}", result),
+        }
+    }
+
+    #[test]
+    fn visits_that_cant_be_saved_are_reported_once() {
+        // The file of visits can't be read or written if it's a directory
+        create_dir_all(scratch_home().join(".dune_visits")).unwrap();
+        let output = Output::default();
+        let mut shell = Shell::new();
+        shell.set_io(output.clone());
+        for dir in [scratch_dir(), scratch_dir()] {
+            shell.directory = dir;
+            shell.record_visit();
+        }
+        let written = output.0.borrow();
+        assert_eq!(written.matches("visits aren't being counted").count(), 1);
+    }
+
+    /// Check that running some code in a shell is denied
+    fn assert_denied(shell: &mut Shell, source: &str) {
+        match shell.eval(source).map_err(|e| e.cause().clone()) {
+            Err(Error::PermissionDenied(_)) => {}
+            result => panic!("{} should be denied, not {:?}", source, result),
+        }
+    }
+
+    #[test]
+    fn sandbox_refuses_paths_outside_of_it() {
+        let outside = scratch_dir();
+        let root = outside.join("root");
+        create_dir_all(root.join("dir")).unwrap();
+        write(outside.join("secret"), "secret").unwrap();
+        let secret = to_string(&outside.join("secret"));
+
+        let mut shell = Shell::sandboxed(&root);
+        for path in [
+            "..",
+            "../secret",
+            "dir/../../secret",
+            "~/../secret",
+            "/",
+            &secret,
+        ] {
+            match shell.resolve(path) {
+                Err(Error::PermissionDenied(_)) => {}
+                result => panic!("{} should be outside, not {:?}", path, result),
+            }
+        }
+        for path in [
+            ".",
+            "dir",
+            "~/dir",
+            "dir/../new.txt",
+            "dir/missing/../new.txt",
+        ] {
+            let resolved = shell.resolve(path).unwrap();
+            assert!(resolved.starts_with(&shell.directory), "{:?}", resolved);
+        }
+        assert_denied(&mut shell, r#"cat("../secret")"#);
+        assert_denied(&mut shell, r#"cd("..")"#);
+        assert_denied(&mut shell, r#"sh("cat ../secret")"#);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sandbox_refuses_links_to_outside_of_it() {
+        let outside = scratch_dir();
+        let root = outside.join("root");
+        create_dir_all(&root).unwrap();
+        write(outside.join("secret"), "secret").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+        let mut shell = Shell::sandboxed(&root);
+        assert_denied(&mut shell, r#"cat("link/secret")"#);
+        assert_denied(&mut shell, r#"symlink("../secret", "escape")"#);
+        assert!(!root.join("escape").exists());
+    }
+
+    #[test]
+    fn commands_are_split_into_words_like_other_shells() {
+        let cases: &[(&str, &[&str])] = &[
+            ("  ls   -la\tdir  ", &["ls", "-la", "dir"]),
+            ("echo 'a  b' \"c  d\"", &["echo", "a  b", "c  d"]),
+            ("echo a' 'b\"c\"d", &["echo", "a bcd"]),
+            ("echo '' \"\"", &["echo", "", ""]),
+            (r"echo a\ b \'c\'", &["echo", "a b", "'c'"]),
+            (r"echo 'a\b' \\", &["echo", r"a\b", r"\"]),
+            (r#"echo "a\"b\\c\d""#, &["echo", r#"a"b\c\d"#]),
+            ("", &[]),
+        ];
+        for (command, words) in cases {
+            assert_eq!(split_words(command).unwrap(), *words, "{}", command);
+        }
+        for command in ["echo 'a", "echo \"a", "echo \"a\\\""] {
+            assert!(split_words(command).is_err(), "{}", command);
+        }
+    }
+
+    #[test]
+    fn command_words_are_only_split_from_a_single_string() {
+        let string = |text: &str| Value::String(text.to_string());
+        let words = command_words(vec![string("ls 'my dir'")]).unwrap();
+        assert_eq!(words, ["ls", "my dir"]);
+        let words = command_words(vec![string("ls"), string("my dir")]).unwrap();
+        assert_eq!(words, ["ls", "my dir"]);
+        let list = Value::List(vec![Value::string("ls"), Value::string("my dir")]);
+        assert_eq!(command_words(vec![list]).unwrap(), ["ls", "my dir"]);
+    }
+
+    #[test]
+    fn rm_moves_files_to_the_trash_to_be_restored() {
+        scratch_home();
+        let dir = scratch_dir();
+        let file = dir.join("notes.txt");
+        write(&file, "notes").unwrap();
+        let mut shell = Shell::new();
+        shell.directory = dir;
+
+        shell.eval(r#"RM_TRASH = true; rm("notes.txt")"#).unwrap();
+        assert!(!file.exists());
+        shell.eval(r#"restore("notes.txt")"#).unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "notes");
+    }
+
+    #[test]
+    fn rm_only_removes_a_full_directory_from_a_script_with_force() {
+        let dir = scratch_dir();
+        create_dir_all(dir.join("full")).unwrap();
+        write(dir.join("full/file.txt"), "").unwrap();
+        let mut shell = Shell::new();
+        shell.directory = dir.clone();
+
+        assert!(shell.eval(r#"rm("full")"#).is_err());
+        assert!(dir.join("full/file.txt").exists());
+        shell.eval(r#"rm("full", true)"#).unwrap();
+        assert!(!dir.join("full").exists());
+    }
+
+    #[test]
+    fn rm_to_the_trash_is_denied_in_the_sandbox() {
+        let root = scratch_dir();
+        write(root.join("file.txt"), "").unwrap();
+        let mut shell = Shell::sandboxed(&root);
+        assert_denied(&mut shell, r#"RM_TRASH = true; rm("file.txt")"#);
+        assert!(root.join("file.txt").exists());
+    }
+
+    #[test]
+    fn imports_are_found_next_to_the_file_importing_them() {
+        let dir = scratch_dir();
+        create_dir_all(dir.join("lib")).unwrap();
+        write(dir.join("lib/paths.dune"), r#"home = "/home""#).unwrap();
+        write(dir.join("lib/chain.dune"), r#"import "paths.dune""#).unwrap();
+        write(dir.join("lib/cycle.dune"), r#"import "cycle.dune""#).unwrap();
+        let mut shell = Shell::new();
+        shell.directory = dir;
+        shell.set_script("main.dune");
+        let mut get = |source: &str| shell.eval(source).unwrap().pop().unwrap().to_string();
+
+        assert_eq!(
+            get(r#"import "lib/paths.dune" as paths; paths.home"#),
+            "/home"
+        );
+        assert_eq!(get(r#"import "lib/chain.dune"; home"#), "/home");
+        match shell
+            .eval(r#"import "lib/cycle.dune""#)
+            .map_err(|e| e.cause().clone())
+        {
+            Err(Error::ImportError(_)) => {}
+            result => panic!("an import cycle should be an error, not {:?}", result),
+        }
+        assert!(shell.eval(r#"import "lib/missing.dune""#).is_err());
+    }
+
+    #[test]
+    fn sandbox_refuses_imports_from_outside_of_it() {
+        let outside = scratch_dir();
+        let root = outside.join("root");
+        create_dir_all(&root).unwrap();
+        write(outside.join("secret.dune"), r#"secret = "secret""#).unwrap();
+        let mut shell = Shell::sandboxed(&root);
+        assert_denied(&mut shell, r#"import "../secret.dune""#);
+    }
+}
@@ -0,0 +1,39 @@
+/// The name and source of each file of the standard library that is
+/// written in dune itself. They are run when the shell starts, unless
+/// it is given `--no-stdlib`.
+pub const STDLIB: &[(&str, &str)] = &[
+    ("strings.dune", include_str!("strings.dune")),
+    ("lists.dune", include_str!("lists.dune")),
+    ("paths.dune", include_str!("paths.dune")),
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::Shell;
+
+    /// Call a function of the standard library with a string
+    fn call(shell: &mut Shell, function: &str, arg: &str) -> String {
+        let source = format!("{}({:?})", function, arg);
+        match shell.eval(&source).map(|mut values| values.pop()) {
+            Ok(Some(value)) => value.to_string(),
+            result => panic!("{} gave {:?}", source, result),
+        }
+    }
+
+    #[test]
+    fn basename_and_dirname_split_paths_on_slashes() {
+        let mut shell = Shell::new();
+        shell.load_stdlib().unwrap();
+        let cases = [
+            ("a/b/c.txt", "c.txt", "a/b"),
+            ("a/b/", "", "a/b"),
+            ("c.txt", "c.txt", "."),
+            ("/a", "a", "/"),
+            ("/", "", "/"),
+        ];
+        for (path, basename, dirname) in cases {
+            assert_eq!(call(&mut shell, "basename", path), basename, "{}", path);
+            assert_eq!(call(&mut shell, "dirname", path), dirname, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::ops::Deref;
+use std::rc::Rc;
+
+thread_local! {
+    /// Every name that a symbol has been made for
+    static SYMBOLS: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
+
+/// A name, like that of a variable, which is only stored once no matter
+/// how many times it's used. Copying a symbol doesn't allocate, so a
+/// loop that assigns to a variable doesn't copy its name every time.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(Rc<str>);
+
+impl Symbol {
+    /// Get the symbol for a name, storing the name if it's new
+    pub fn new(name: &str) -> Self {
+        SYMBOLS.with(|symbols| {
+            let mut symbols = symbols.borrow_mut();
+            match symbols.get(name) {
+                Some(symbol) => Self(Rc::clone(symbol)),
+                None => {
+                    let symbol = Rc::<str>::from(name);
+                    symbols.insert(Rc::clone(&symbol));
+                    Self(symbol)
+                }
+            }
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{:?}", self.0)
+    }
+}
@@ -1,7 +1,228 @@
-use crate::shell::Shell;
+use crate::archive::{create_tar, create_zip, extract_tar, extract_zip};
+use crate::compiler::{compile, LoopCode};
+use crate::debugger::reach_statement;
+use crate::interrupt::is_interrupted;
+use crate::parser::parse;
+use crate::profiler::{profile_call, record_statement};
+use crate::scope::Scope;
+use crate::shell::{command_words, find_program, Frame, Shell};
+use crate::symbol::Symbol;
+use crate::visit::{walk_expr, Visit};
+use std::cell::{OnceCell, RefCell};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::mem::{replace, take};
+use std::rc::Rc;
 
-#[derive(Debug)]
-pub enum Error {}
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    IoError(String),
+    /// A variable that isn't defined, and the most similar name that is
+    UndefinedVariable(String, Option<String>),
+    TypeError(String),
+    ParseError(String),
+    ProcessError(String),
+    PermissionDenied(String),
+    ImportError(String),
+    Interrupted,
+    /// An error caused by the code at a location in the source
+    Located(Span, Box<Error>),
+    /// An error that happened inside of a function or an imported file,
+    /// along with the name of the function or file and the line of the error
+    InFunction(String, Option<usize>, Box<Error>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::IoError(msg) => write!(f, "io error: {}", msg),
+            Self::UndefinedVariable(name, None) => write!(f, "undefined variable `{}`", name),
+            Self::UndefinedVariable(name, Some(similar)) => write!(
+                f,
+                "undefined variable `{}`, did you mean `{}`?",
+                name, similar
+            ),
+            Self::TypeError(msg) => write!(f, "type error: {}", msg),
+            Self::ParseError(msg) => write!(f, "parse error: {}", msg),
+            Self::ProcessError(msg) => write!(f, "process error: {}", msg),
+            Self::PermissionDenied(msg) => write!(f, "permission denied: {}", msg),
+            Self::ImportError(msg) => write!(f, "import error: {}", msg),
+            Self::Interrupted => write!(f, "interrupted"),
+            Self::Located(_, error) | Self::InFunction(_, _, error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::IoError(e.to_string())
+    }
+}
+
+thread_local! {
+    /// Functions called by the machine cannot return a Result,
+    /// so the errors they raise are stored here until the
+    /// function call that invoked them picks them up.
+    static RAISED: RefCell<Option<Error>> = const { RefCell::new(None) };
+}
+
+impl Error {
+    /// Mark this error as caused by the code at a location, unless
+    /// it was already found to be caused by code inside of it
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            Self::Located(..) => self,
+            error => Self::Located(span, Box::new(error)),
+        }
+    }
+
+    /// Forget where this error happened. This is used when an error
+    /// leaves code that was parsed from a different source.
+    pub fn without_span(self) -> Self {
+        match self {
+            Self::Located(_, error) => *error,
+            error => error,
+        }
+    }
+
+    /// Record that this error happened inside of a function or a file.
+    /// Where it happened is kept, so that it can be shown if the function
+    /// is in the same source as the code that reports the error.
+    pub fn in_function(self, function: &str) -> Self {
+        let line = match &self {
+            Self::Located(span, _) => Some(span.line),
+            _ => None,
+        };
+        Self::InFunction(function.to_string(), line, Box::new(self))
+    }
+
+    /// The error that caused this one, without where it happened
+    pub fn cause(&self) -> &Self {
+        match self {
+            Self::Located(_, error) | Self::InFunction(_, _, error) => error.cause(),
+            error => error,
+        }
+    }
+
+    /// Describe this error, show where it happened in the
+    /// source, and list the functions it happened inside of
+    pub fn report(&self, source: &str) -> String {
+        let mut report = self.to_string();
+
+        // The error is shown at the innermost code in this source that
+        // caused it. Inside of a function from another source, that is
+        // the call to the function.
+        let this_source = match self {
+            Self::Located(span, _) => Some(span.source),
+            _ => None,
+        };
+        let mut location = None;
+        let mut trace = vec![];
+        let mut error = self;
+        loop {
+            match error {
+                Self::Located(span, inner) => {
+                    if Some(span.source) == this_source {
+                        location = Some(span);
+                    }
+                    error = inner;
+                }
+                Self::InFunction(function, line, inner) => {
+                    trace.push(match line {
+                        Some(line) => format!("{}, at line {}", function, line),
+                        None => function.clone(),
+                    });
+                    error = inner;
+                }
+                _ => break,
+            }
+        }
+        if let Some(span) = location {
+            report += &span.show(source);
+        }
+        // The innermost function is listed first
+        trace.reverse();
+
+        // Collapse deep recursion into a single line
+        let mut i = 0;
+        while i < trace.len() {
+            let repeats = trace[i..].iter().take_while(|f| **f == trace[i]).count();
+            report += &format!(
+                "\n  {} {}",
+                if i == 0 { "in" } else { "called from" },
+                trace[i]
+            );
+            if repeats > 1 {
+                report += &format!(" ({} times)", repeats);
+            }
+            i += repeats;
+        }
+        report
+    }
+
+    /// Raise this error from within a function called by the machine
+    pub fn raise(self) {
+        RAISED.with(|raised| {
+            raised.borrow_mut().get_or_insert(self);
+        });
+    }
+
+    /// Take the error raised by the last function call, if any
+    pub fn take_raised() -> Option<Self> {
+        RAISED.with(|raised| raised.borrow_mut().take())
+    }
+}
+
+/// The location of a piece of code, as byte offsets into its source
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    /// The line that the code starts on, counting from one
+    pub line: usize,
+    /// Which source the code was parsed from, so that the spans
+    /// of code from other sources aren't shown in this one
+    pub source: usize,
+}
+
+impl Span {
+    /// The line and column that this span starts at, counting from one
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = source.get(..self.start).unwrap_or(source);
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        (line, before[line_start..].chars().count() + 1)
+    }
+
+    /// Show the line of the source that this span starts on,
+    /// and point out the code in it that the span covers
+    pub fn show(&self, source: &str) -> String {
+        let (line, column) = self.line_col(source);
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        let width = source
+            .get(self.start..self.end)
+            .and_then(|code| code.lines().next())
+            .map(|code| code.chars().count())
+            .unwrap_or(0)
+            .max(1);
+        format!(
+            "\n --> line {}, column {}\n  | {}\n  | {}{}",
+            line,
+            column,
+            text,
+            " ".repeat(column - 1),
+            "^".repeat(width)
+        )
+    }
+}
+
+/// A signal raised by an expression that interrupts
+/// the execution of the suites it is nested in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    Return,
+    Break,
+    Continue,
+}
 
 pub trait Execute {
     fn execute(&self, _: &mut Shell) -> Result<(), Error> {
@@ -13,65 +234,267 @@ pub trait Execute {
 pub enum Literal {
     String(String),
     Number(f64),
+    /// The machine has no boolean values, so `true`
+    /// and `false` are the numbers 1 and 0
+    Bool(bool),
+    /// The absence of a value
+    None,
 }
 
 impl Execute for Literal {
     fn execute(&self, shell: &mut Shell) -> Result<(), Error> {
         shell.machine.push(match self {
             Self::String(s) => xmachine::Value::string(s),
-            Self::Number(n) => xmachine::Value::number(n.clone()),
+            Self::Number(n) => xmachine::Value::number(*n),
+            Self::Bool(b) => xmachine::Value::number(*b as i32),
+            Self::None => xmachine::Value::none(),
         });
         Ok(())
     }
 }
 
+/// Whether a value counts as true in a condition, such as in an `if`
+/// or a `while`. Zero, none, errors, and empty strings, lists, and
+/// dictionaries are false, and everything else is true. Comparisons
+/// give 1 for true and 0 for false.
+pub fn is_true(value: &xmachine::Value) -> bool {
+    bool::from(value.clone())
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct FnCall(pub Box<Value>, pub Vec<Value>);
 
-impl Execute for FnCall {
-    fn execute(&self, shell: &mut Shell) -> Result<(), Error> {
-        let FnCall(function, mut arguments) = self.clone();
-        arguments.reverse();
-        for arg in arguments {
+impl FnCall {
+    /// Run a call that is at a span in the source, which errors
+    /// inside of the function show as where it was called
+    pub fn execute_at(&self, shell: &mut Shell, span: Span) -> Result<(), Error> {
+        // Functions can take a varying number of arguments, so they
+        // must not be able to see the values below their arguments
+        let outer = take(&mut shell.machine.stack);
+        let result = self.call(shell, span);
+        let mut inner = replace(&mut shell.machine.stack, outer);
+        shell.machine.stack.append(&mut inner);
+        result
+    }
+
+    /// Push the arguments in reverse order so that the
+    /// first one is on top, and then call the function
+    fn call(&self, shell: &mut Shell, span: Span) -> Result<(), Error> {
+        let FnCall(function, arguments) = self;
+        for arg in arguments.iter().rev() {
             arg.execute(shell)?;
         }
-        function.execute(shell)?;
 
-        if let Value::Builtin(_) = (*function).clone() {
-        } else {
-            shell.machine.call();
+        match &**function {
+            Value::Builtin(builtin) => builtin.execute(shell),
+            function => {
+                function.execute(shell)?;
+                shell.call_in_frame(Frame {
+                    function: function.callee_name(),
+                    span,
+                })
+            }
         }
+    }
+}
 
-        Ok(())
+impl Execute for FnCall {
+    fn execute(&self, shell: &mut Shell) -> Result<(), Error> {
+        self.execute_at(shell, self.0.span())
     }
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
-pub struct Identifier(pub String);
+pub struct Identifier(pub Symbol);
 
 impl Execute for Identifier {
     fn execute(&self, shell: &mut Shell) -> Result<(), Error> {
         let Identifier(name) = self;
-        shell.machine.push(xmachine::Value::string(name));
-        shell.machine.load();
+        match shell.get_var(name) {
+            Some(value) => shell.machine.push(value),
+            None => {
+                let similar = most_similar(name, shell.names());
+                return Err(Error::UndefinedVariable(name.to_string(), similar));
+            }
+        }
         Ok(())
     }
 }
 
+/// Find the name that is closest to a misspelled name, if any are close enough
+pub fn most_similar(name: &str, names: Vec<String>) -> Option<String> {
+    // Allow one mistake for every three characters
+    let len = name.chars().count();
+    let max_distance = len / 3 + 1;
+    names
+        .into_iter()
+        .map(|other| (edit_distance(name, &other), other))
+        .filter(|(distance, _)| *distance <= max_distance)
+        // Between names that are as close, a typo more likely kept the length
+        .min_by_key(|(distance, other)| (*distance, other.chars().count().abs_diff(len)))
+        .map(|(_, other)| other)
+}
+
+/// The number of characters that must be inserted, removed, replaced,
+/// or swapped with the next one to turn one string into another
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<char>>();
+    let b = b.chars().collect::<Vec<char>>();
+    // The distances from the first characters of `a` to each start of `b`
+    let mut before = vec![0; b.len() + 1];
+    let mut above = (0..=b.len()).collect::<Vec<usize>>();
+    for i in 0..a.len() {
+        let mut row = vec![i + 1; b.len() + 1];
+        for j in 0..b.len() {
+            row[j + 1] = if a[i] == b[j] {
+                above[j]
+            } else {
+                1 + above[j].min(above[j + 1]).min(row[j])
+            };
+            if i > 0 && j > 0 && a[i] == b[j - 1] && a[i - 1] == b[j] {
+                row[j + 1] = row[j + 1].min(before[j - 1] + 1);
+            }
+        }
+        before = replace(&mut above, row);
+    }
+    above[b.len()]
+}
+
+/// Pop all of the arguments given to a builtin, in order
+fn pop_args(shell: &mut Shell) -> Vec<xmachine::Value> {
+    let mut args = vec![];
+    while let Some(arg) = shell.machine.pop() {
+        args.push((*arg).clone());
+    }
+    args
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum Builtin {
     List,
+    ListEach,
+    ListLong,
+    Stat,
+    Sha256,
+    Md5,
+    HashFile,
+    Zip,
+    Unzip,
+    Tar,
+    Untar,
+    Symlink,
+    ReadLink,
+    IsSymlink,
+    Chmod,
+    Chown,
+    DiskUsage,
+    DiskFree,
+    Head,
+    Tail,
+    CountLines,
+    RealPath,
+    Walk,
+    Find,
+    Grep,
     ChangeDir,
+    TryChangeDir,
+    PushDir,
+    PopDir,
+    Dirs,
+    Bookmark,
+    Goto,
+    Bookmarks,
     Move,
+    Copy,
     Clear,
     Remove,
+    Restore,
     MakeDir,
     MakeFile,
+    ReadFile,
+    ReadCsv,
+    WriteCsv,
     ShellOut,
+    Spawn,
+    Timeout,
+    Processes,
+    Kill,
     WorkingDir,
+    Exists,
+    Eval,
+    Plugin,
+    Profile,
+    SetStrict,
     Exit,
 }
 
+/// The words that can be used to call each builtin
+pub const BUILTINS: &[(&str, Builtin)] = &[
+    ("ls", Builtin::List),
+    ("dir", Builtin::List),
+    ("ls_each", Builtin::ListEach),
+    ("lsl", Builtin::ListLong),
+    ("stat", Builtin::Stat),
+    ("sha256", Builtin::Sha256),
+    ("md5", Builtin::Md5),
+    ("hash_file", Builtin::HashFile),
+    ("zip", Builtin::Zip),
+    ("unzip", Builtin::Unzip),
+    ("tar", Builtin::Tar),
+    ("untar", Builtin::Untar),
+    ("symlink", Builtin::Symlink),
+    ("readlink", Builtin::ReadLink),
+    ("is_symlink", Builtin::IsSymlink),
+    ("chmod", Builtin::Chmod),
+    ("chown", Builtin::Chown),
+    ("du", Builtin::DiskUsage),
+    ("df", Builtin::DiskFree),
+    ("head", Builtin::Head),
+    ("tail", Builtin::Tail),
+    ("count_lines", Builtin::CountLines),
+    ("realpath", Builtin::RealPath),
+    ("walk", Builtin::Walk),
+    ("tree", Builtin::Walk),
+    ("find", Builtin::Find),
+    ("grep", Builtin::Grep),
+    ("clear", Builtin::Clear),
+    ("cls", Builtin::Clear),
+    ("sh", Builtin::ShellOut),
+    ("cmd", Builtin::ShellOut),
+    ("spawn", Builtin::Spawn),
+    ("timeout", Builtin::Timeout),
+    ("ps", Builtin::Processes),
+    ("kill", Builtin::Kill),
+    ("mv", Builtin::Move),
+    ("cp", Builtin::Copy),
+    ("cd", Builtin::ChangeDir),
+    ("try_cd", Builtin::TryChangeDir),
+    ("pushd", Builtin::PushDir),
+    ("popd", Builtin::PopDir),
+    ("dirs", Builtin::Dirs),
+    ("bookmark", Builtin::Bookmark),
+    ("goto", Builtin::Goto),
+    ("bookmarks", Builtin::Bookmarks),
+    ("rm", Builtin::Remove),
+    ("restore", Builtin::Restore),
+    ("mkdir", Builtin::MakeDir),
+    ("mkf", Builtin::MakeFile),
+    ("touch", Builtin::MakeFile),
+    ("cat", Builtin::ReadFile),
+    ("csv_read", Builtin::ReadCsv),
+    ("csv_write", Builtin::WriteCsv),
+    ("pwd", Builtin::WorkingDir),
+    ("cwd", Builtin::WorkingDir),
+    ("exists", Builtin::Exists),
+    ("eval", Builtin::Eval),
+    ("plugin", Builtin::Plugin),
+    ("profile", Builtin::Profile),
+    ("set_strict", Builtin::SetStrict),
+    ("exit", Builtin::Exit),
+    ("quit", Builtin::Exit),
+    ("bye", Builtin::Exit),
+];
+
 impl Execute for Builtin {
     fn execute(&self, shell: &mut Shell) -> Result<(), Error> {
         match self {
@@ -80,37 +503,270 @@ impl Execute for Builtin {
             }
             Self::List => {
                 let arg = shell.machine.pop().map(|v| (*v).clone().to_string());
-                shell.ls(arg);
+                shell.ls(arg)?;
+            }
+            Self::ListEach => {
+                let dir = shell.machine.get_arg::<String>();
+                let function = shell.machine.pop().unwrap_or_else(xmachine::Value::none);
+                shell.ls_each(&dir, function)?;
+            }
+            Self::ListLong => {
+                let arg = shell.machine.pop().map(|v| (*v).clone().to_string());
+                shell.lsl(arg)?;
+            }
+            Self::Stat => {
+                let path = shell.machine.get_arg::<String>();
+                shell.stat(&path)?;
+            }
+            Self::Sha256 => {
+                let target = shell.machine.get_arg::<String>();
+                shell.hash("sha256", &target)?;
+            }
+            Self::Md5 => {
+                let target = shell.machine.get_arg::<String>();
+                shell.hash("md5", &target)?;
+            }
+            Self::HashFile => {
+                let path = shell.machine.get_arg::<String>();
+                let algorithm = shell
+                    .machine
+                    .pop()
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|| String::from("sha256"));
+                shell.hash_file(&algorithm, &path)?;
+            }
+            Self::Zip => {
+                let src = shell.machine.get_arg::<String>();
+                let dest = shell.machine.get_arg::<String>();
+                shell.archive("zip", create_zip, &src, Some(&dest))?;
+            }
+            Self::Unzip => {
+                let archive = shell.machine.get_arg::<String>();
+                let dest = shell.machine.pop().map(|v| v.to_string());
+                shell.archive("unzip", extract_zip, &archive, dest.as_deref())?;
+            }
+            Self::Tar => {
+                let src = shell.machine.get_arg::<String>();
+                let dest = shell.machine.get_arg::<String>();
+                shell.archive("tar", create_tar, &src, Some(&dest))?;
+            }
+            Self::Untar => {
+                let archive = shell.machine.get_arg::<String>();
+                let dest = shell.machine.pop().map(|v| v.to_string());
+                shell.archive("untar", extract_tar, &archive, dest.as_deref())?;
+            }
+            Self::Symlink => {
+                let target = shell.machine.get_arg::<String>();
+                let link = shell.machine.get_arg::<String>();
+                shell.symlink(&target, &link)?;
+            }
+            Self::ReadLink => {
+                let path = shell.machine.get_arg::<String>();
+                shell.readlink(&path)?;
+            }
+            Self::IsSymlink => {
+                let path = shell.machine.get_arg::<String>();
+                shell.is_symlink(&path)?;
+            }
+            Self::Chmod => {
+                let path = shell.machine.get_arg::<String>();
+                // Octal modes can be written as numbers, like `chmod "run.sh" 755`
+                let mode = shell
+                    .machine
+                    .pop()
+                    .map(|v| v.to_string())
+                    .unwrap_or_default();
+                shell.chmod(&path, &mode)?;
+            }
+            Self::Chown => {
+                let path = shell.machine.get_arg::<String>();
+                let owner = shell.machine.get_arg::<String>();
+                shell.chown(&path, &owner)?;
+            }
+            Self::DiskUsage => {
+                let path = shell.machine.pop().map(|v| v.to_string());
+                shell.du(path)?;
+            }
+            Self::DiskFree => {
+                let path = shell.machine.pop().map(|v| v.to_string());
+                shell.df(path)?;
+            }
+            Self::Head => {
+                let target = shell.machine.get_arg::<String>();
+                let n = shell
+                    .machine
+                    .pop()
+                    .map(|v| f64::from((*v).clone()).max(0.0) as usize)
+                    .unwrap_or(10);
+                shell.head(&target, n)?;
+            }
+            Self::Tail => {
+                let target = shell.machine.get_arg::<String>();
+                let n = shell
+                    .machine
+                    .pop()
+                    .map(|v| f64::from((*v).clone()).max(0.0) as usize)
+                    .unwrap_or(10);
+                shell.tail(&target, n)?;
+            }
+            Self::CountLines => {
+                let target = shell.machine.get_arg::<String>();
+                shell.count_lines(&target)?;
+            }
+            Self::RealPath => {
+                let path = shell.machine.get_arg::<String>();
+                shell.realpath(&path)?;
+            }
+            Self::Walk => {
+                let dir = shell.machine.pop().map(|v| (*v).clone().to_string());
+                let depth = shell
+                    .machine
+                    .pop()
+                    .map(|v| f64::from((*v).clone()) as usize);
+                shell.walk(dir, depth)?;
+            }
+            Self::Find => {
+                let dir = shell.machine.pop().map(|v| (*v).clone().to_string());
+                let predicate = shell.machine.pop();
+                shell.find(dir, predicate)?;
+            }
+            Self::Grep => {
+                let pattern = shell.machine.get_arg::<String>();
+                let target = shell.machine.get_arg::<String>();
+                shell.grep(&pattern, &target)?;
             }
             Self::ChangeDir => {
-                let arg = shell.machine.get_arg::<String>();
-                shell.cd(&arg);
+                let arg = shell.machine.pop().map(|v| (*v).clone().to_string());
+                shell.cd(arg.as_deref())?;
+            }
+            Self::TryChangeDir => {
+                let arg = shell.machine.pop().map(|v| (*v).clone().to_string());
+                let changed = match shell.cd(arg.as_deref()) {
+                    Err(Error::Interrupted) => return Err(Error::Interrupted),
+                    result => result.is_ok(),
+                };
+                shell.machine.push(xmachine::Value::number(changed as i32));
+            }
+            Self::PushDir => {
+                let arg = shell.machine.pop().map(|v| (*v).clone().to_string());
+                shell.pushd(arg.as_deref())?;
+            }
+            Self::PopDir => shell.popd()?,
+            Self::Dirs => shell.dirs(),
+            Self::Bookmark => {
+                let name = shell.machine.get_arg::<String>();
+                shell.bookmark(&name)?;
             }
+            Self::Goto => {
+                let name = shell.machine.get_arg::<String>();
+                shell.goto(&name)?;
+            }
+            Self::Bookmarks => shell.bookmarks()?,
             Self::Move => {
                 let old = shell.machine.get_arg::<String>();
                 let new = shell.machine.get_arg::<String>();
-                shell.mv(&old, &new);
+                shell.mv(&old, &new)?;
+            }
+            Self::Copy => {
+                let src = shell.machine.get_arg::<String>();
+                let dst = shell.machine.get_arg::<String>();
+                shell.cp(&src, &dst)?;
             }
             Self::Remove => {
                 let path = shell.machine.get_arg::<String>();
-                shell.rm(&path);
+                // Either a true value or `-f` skips asking before
+                // removing a directory that isn't empty
+                let force = shell.machine.pop().is_some_and(|force| match &*force {
+                    xmachine::Value::String(flag) => flag == "-f" || flag == "force",
+                    force => is_true(force),
+                });
+                shell.rm(&path, force)?;
+            }
+            Self::Restore => {
+                let path = shell.machine.get_arg::<String>();
+                shell.restore(&path)?;
             }
             Self::MakeDir => {
                 let path = shell.machine.get_arg::<String>();
-                shell.mkdir(&path);
+                shell.mkdir(&path)?;
             }
             Self::MakeFile => {
                 let path = shell.machine.get_arg::<String>();
-                shell.mkf(&path);
+                shell.mkf(&path)?;
+            }
+            Self::ReadFile => {
+                let path = shell.machine.get_arg::<String>();
+                shell.cat(&path)?;
+            }
+            Self::ReadCsv => {
+                let path = shell.machine.get_arg::<String>();
+                shell.csv_read(&path)?;
+            }
+            Self::WriteCsv => {
+                let path = shell.machine.get_arg::<String>();
+                let rows = shell
+                    .machine
+                    .get_arg::<Vec<xmachine::Ref<xmachine::Value>>>();
+                shell.csv_write(&path, &rows)?;
             }
             Self::ShellOut => {
-                let arg = shell.machine.get_arg::<String>();
-                shell.sh(&arg);
+                let words = command_words(pop_args(shell))?;
+                shell.sh(&words)?;
+            }
+            Self::Spawn => {
+                let words = command_words(pop_args(shell))?;
+                shell.spawn(&words)?;
+            }
+            Self::Timeout => {
+                let seconds = shell.machine.get_arg::<f64>();
+                let args = pop_args(shell);
+                match args.as_slice() {
+                    [xmachine::Value::Function(_)] => {
+                        shell.timeout_call(seconds, xmachine::Ref::new(args[0].clone()))?
+                    }
+                    _ => shell.timeout_program(seconds, &command_words(args)?)?,
+                }
+            }
+            Self::Processes => shell.ps()?,
+            Self::Kill => {
+                let pid = shell.machine.get_arg::<f64>();
+                let signal = shell.machine.pop().map(|v| (*v).clone());
+                shell.kill(pid, signal)?;
             }
             Self::WorkingDir => {
                 shell.wd();
             }
-            Self::Exit => shell.exit(),
+            Self::Exists => {
+                let name = shell.machine.get_arg::<String>();
+                shell.exists(&name);
+            }
+            Self::Eval => {
+                // The program runs in this shell, so it can use and
+                // change its variables, and leave values behind
+                let source = shell.machine.get_arg::<String>();
+                parse(&source)?
+                    .execute(shell)
+                    .map_err(Error::without_span)?;
+            }
+            Self::Plugin => {
+                let path = shell.machine.get_arg::<String>();
+                shell.plugin(&path)?;
+            }
+            Self::Profile => {
+                let function = shell.machine.pop().unwrap_or_else(xmachine::Value::none);
+                profile_call(shell, function)?;
+            }
+            Self::SetStrict => {
+                shell.is_strict = shell.machine.get_arg::<bool>();
+            }
+            Self::Exit => {
+                let status = shell
+                    .machine
+                    .pop()
+                    .map(|v| f64::from((*v).clone()) as i32)
+                    .unwrap_or(0);
+                shell.exit(status);
+            }
         };
 
         Ok(())
@@ -124,6 +780,46 @@ pub enum Value {
     FnCall(FnCall),
     Builtin(Builtin),
     Function(Function),
+    And(Box<Value>, Box<Value>),
+    Or(Box<Value>, Box<Value>),
+    /// A value and where it is in the source
+    Spanned(Span, Box<Value>),
+}
+
+impl Value {
+    /// Where this value is in the source, if it is known
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Spanned(span, _) => *span,
+            _ => Span::default(),
+        }
+    }
+
+    /// The name that this value is, if it is just a name
+    pub fn as_identifier(&self) -> Option<&str> {
+        match self {
+            Self::Spanned(_, value) => value.as_identifier(),
+            Self::Name(Name::Name(Identifier(name))) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// The name that a function being called is referred to by
+    pub fn callee_name(&self) -> String {
+        match self {
+            Self::Spanned(_, value) => value.callee_name(),
+            Self::Name(Name::Name(Identifier(name))) => name.to_string(),
+            Self::Name(Name::DotName(head, identifiers)) => {
+                let mut name = head.callee_name();
+                for Identifier(ident) in identifiers {
+                    name += ".";
+                    name += ident;
+                }
+                name
+            }
+            _ => String::from("anonymous function"),
+        }
+    }
 }
 
 impl Execute for Value {
@@ -132,8 +828,54 @@ impl Execute for Value {
             Self::Name(name) => name.execute(shell)?,
             Self::Literal(literal) => literal.execute(shell)?,
             Self::FnCall(call) => call.execute(shell)?,
-            Self::Builtin(call) => call.execute(shell)?,
+            // A builtin by itself is called without arguments, rather
+            // than with the values that happen to be on the stack
+            Self::Builtin(call) => {
+                let outer = take(&mut shell.machine.stack);
+                let result = call.execute(shell);
+                let mut inner = replace(&mut shell.machine.stack, outer);
+                shell.machine.stack.append(&mut inner);
+                result?
+            }
             Self::Function(func) => func.execute(shell)?,
+            Self::Spanned(span, value) => match &**value {
+                Self::FnCall(call) => call.execute_at(shell, *span),
+                value => value.execute(shell),
+            }
+            .map_err(|e| e.at(*span))?,
+            // The right hand side of a logical operator is only
+            // evaluated when the left hand side does not decide
+            // the result on its own
+            Self::And(lhs, rhs) => {
+                lhs.execute(shell)?;
+                let is_true = match shell.machine.pop() {
+                    Some(v) if is_true(&v) => true,
+                    Some(v) => {
+                        shell.machine.push(v);
+                        false
+                    }
+                    None => {
+                        shell.machine.return_value(0);
+                        false
+                    }
+                };
+                if is_true {
+                    rhs.execute(shell)?;
+                }
+            }
+            Self::Or(lhs, rhs) => {
+                lhs.execute(shell)?;
+                let is_true = match shell.machine.pop() {
+                    Some(v) if is_true(&v) => {
+                        shell.machine.push(v);
+                        true
+                    }
+                    _ => false,
+                };
+                if !is_true {
+                    rhs.execute(shell)?;
+                }
+            }
         };
         Ok(())
     }
@@ -156,6 +898,7 @@ impl Execute for Name {
                     let Identifier(name) = ident;
                     shell.machine.push(xmachine::Value::string(name));
                     shell.machine.index();
+                    check_index(shell)?;
                 }
             }
             Self::IndexName(head, values) => {
@@ -163,6 +906,7 @@ impl Execute for Name {
                 for value in values {
                     value.execute(shell)?;
                     shell.machine.index();
+                    check_index(shell)?;
                 }
             }
         };
@@ -170,63 +914,187 @@ impl Execute for Name {
     }
 }
 
+impl Name {
+    /// Store the value on top of the stack in this name
+    pub fn assign(&self, shell: &mut Shell) -> Result<(), Error> {
+        match self {
+            Self::Name(Identifier(store)) => {
+                let value = shell.machine.pop().unwrap_or_else(xmachine::Value::none);
+                shell.scope.define(store.clone(), value);
+            }
+            dotname => {
+                dotname.execute(shell)?;
+                shell.machine.assign();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The machine pushes an error value when something that is
+/// not a list or a dictionary is indexed, so turn that into a real error
+fn check_index(shell: &mut Shell) -> Result<(), Error> {
+    if let Some(value) = shell.machine.stack.last() {
+        if let xmachine::Value::Error(msg) = &**value {
+            return Err(Error::TypeError(msg.clone()));
+        }
+    }
+    Ok(())
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum Expr {
     Assignment(Name, Value),
-    WhileLoop(Value, Suite),
+    WhileLoop(Value, Suite, LoopCode),
+    ForLoop(Identifier, Value, Suite, LoopCode),
     IfThenElse(Value, Suite, Suite),
+    Match(Value, Vec<(Pattern, Suite)>),
+    Import(String, Option<Identifier>),
+    Alias(Identifier, String),
+    Command(Identifier, Vec<String>),
+    Unalias(Identifier),
     FunctionDef(FunctionDef),
+    Return(Value),
+    Break,
+    Continue,
     Value(Value),
+    /// A value followed by `;`, which is computed for what it does
+    /// rather than its result. At the top level of a script or the
+    /// prompt its result isn't shown, but in a function it's the
+    /// same as the value without `;`, so the function still returns it.
+    Silent(Value),
+    /// An expression and where it is in the source
+    Spanned(Span, Box<Expr>),
 }
 
 impl Execute for Expr {
     fn execute(&self, shell: &mut Shell) -> Result<(), Error> {
         match self {
-            Self::Assignment(name, value) => match name {
-                Name::Name(ident) => {
-                    let Identifier(store) = ident;
-                    value.execute(shell)?;
-                    shell.machine.push(xmachine::Value::string(store));
-                    shell.machine.store();
-                }
-                dotname => {
-                    value.execute(shell)?;
-                    dotname.execute(shell)?;
-                    shell.machine.assign();
-                }
-            },
-            Self::WhileLoop(value, body) => {
-                let ret_val = |shell: &mut Shell| match shell.machine.pop() {
-                    Some(v) => bool::from((*v).clone()),
-                    _ => false,
-                };
-
+            Self::Assignment(name, value) => {
                 value.execute(shell)?;
-                while ret_val(shell) {
-                    body.execute(shell)?;
-                    value.execute(shell)?;
-                }
+                name.assign(shell)?;
             }
+            // Loops are compiled, since their bodies are run many times
+            Self::WhileLoop(.., code) | Self::ForLoop(.., code) => code.run(self, shell)?,
             Self::IfThenElse(value, then_body, else_body) => {
-                let ret_val = |shell: &mut Shell| match shell.machine.pop() {
-                    Some(v) => bool::from((*v).clone()),
-                    _ => false,
-                };
-
                 value.execute(shell)?;
-                if ret_val(shell) {
+                if shell.machine.pop().is_some_and(|v| is_true(&v)) {
                     then_body.execute(shell)?;
                 } else {
                     else_body.execute(shell)?;
                 }
             }
+            Self::Match(value, arms) => {
+                value.execute(shell)?;
+                let value = shell.machine.pop().unwrap_or_else(xmachine::Value::none);
+                if let Some((_, body)) = arms.iter().find(|(pattern, _)| pattern.matches(&value)) {
+                    body.execute(shell)?;
+                }
+            }
+            Self::Import(path, name) => {
+                let name = name.as_ref().map(|Identifier(name)| name.as_str());
+                shell.import(path, name)?;
+            }
+            Self::Command(name, args) => match shell.get_var(&name.0) {
+                // Functions can be called like commands, with words as arguments
+                Some(var) if matches!(*var, xmachine::Value::Function(_)) => {
+                    let args = args
+                        .iter()
+                        .map(|arg| Value::Literal(Literal::String(arg.clone())))
+                        .collect();
+                    FnCall(Box::new(Value::Name(Name::Name(name.clone()))), args).execute(shell)?;
+                }
+                // Other variables can't be, so a line like `x - 1` isn't run
+                Some(_) => {
+                    return Err(Error::TypeError(format!(
+                        "`{}` is not a function, so `{} {}` can't be run",
+                        name.0,
+                        name.0,
+                        args.join(" ")
+                    )))
+                }
+                None => shell.run_program(&name.0, args)?,
+            },
+            Self::Alias(Identifier(name), command) => shell.alias(name, command),
+            Self::Unalias(Identifier(name)) => shell.unalias(name),
             Self::FunctionDef(func_def) => func_def.execute(shell)?,
-            Self::Value(v) => v.execute(shell)?,
+            Self::Return(value) => {
+                value.execute(shell)?;
+                shell.signal = Some(Signal::Return);
+            }
+            Self::Break => shell.signal = Some(Signal::Break),
+            Self::Continue => shell.signal = Some(Signal::Continue),
+            Self::Value(value) => run_value(value, shell)?,
+            Self::Silent(value) => {
+                let height = shell.machine.stack.len();
+                run_value(value, shell)?;
+                shell.machine.stack.truncate(height);
+            }
+            Self::Spanned(span, expr) => match &**expr {
+                // A loop is compiled with where it is, so that the
+                // debugger can stop at its first line each time around
+                Self::WhileLoop(.., code) | Self::ForLoop(.., code) => code.run(self, shell)?,
+                _ => {
+                    record_statement(*span);
+                    reach_statement(shell, *span)?;
+                    expr.execute(shell).map_err(|e| e.at(*span))?
+                }
+            },
         };
         Ok(())
     }
 }
 
+/// Run a value written as a statement by itself
+fn run_value(value: &Value, shell: &mut Shell) -> Result<(), Error> {
+    match value.as_identifier() {
+        // A name by itself that isn't defined can be a program to run
+        Some(name) if shell.get_var(name).is_none() => {
+            if find_program(&shell.directory, name).is_some() {
+                shell.run_program(name, &[])
+            } else if shell.auto_cd_enabled() && shell.resolve(name).is_ok_and(|path| path.is_dir())
+            {
+                // The name of a directory by itself goes into it
+                shell.machine.push(xmachine::Value::string(name));
+                Ok(())
+            } else {
+                let similar = shell.similar_command(name);
+                Err(Error::UndefinedVariable(name.to_string(), similar))
+            }
+        }
+        _ => value.execute(shell),
+    }
+}
+
+/// A pattern that a value can be matched against
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum Pattern {
+    /// Matches a string or a number equal to the literal
+    Literal(Literal),
+    /// Matches a list with one item for each pattern, that match in order
+    List(Vec<Pattern>),
+    /// Matches any value
+    Wildcard,
+}
+
+impl Pattern {
+    /// Whether or not a value matches this pattern
+    pub fn matches(&self, value: &xmachine::Value) -> bool {
+        match (self, value) {
+            (Self::Wildcard, _) => true,
+            (Self::Literal(Literal::String(a)), xmachine::Value::String(b)) => a == b,
+            (Self::Literal(Literal::Number(a)), xmachine::Value::Number(b)) => a == b,
+            (Self::Literal(Literal::Bool(a)), xmachine::Value::Number(b)) => *a as i32 as f64 == *b,
+            (Self::Literal(Literal::None), xmachine::Value::None) => true,
+            (Self::List(patterns), xmachine::Value::List(items)) => {
+                patterns.len() == items.len()
+                    && patterns.iter().zip(items).all(|(p, item)| p.matches(item))
+            }
+            _ => false,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct Suite(pub Vec<Expr>);
 
@@ -234,45 +1102,102 @@ impl Execute for Suite {
     fn execute(&self, shell: &mut Shell) -> Result<(), Error> {
         let Suite(exprs) = self;
         for expr in exprs {
+            // Stop running if the user pressed Ctrl-C
+            if is_interrupted() {
+                return Err(Error::Interrupted);
+            }
             expr.execute(shell)?;
+            if shell.signal.is_some() || shell.is_done {
+                break;
+            }
         }
 
         Ok(())
     }
 }
 
+impl Suite {
+    /// Where each statement in this code is, including the statements
+    /// in the blocks and functions written inside of it
+    pub fn statements(&self) -> Vec<Span> {
+        let mut statements = Statements(vec![]);
+        statements.visit_suite(self);
+        statements.0
+    }
+}
+
+/// Finds where each statement is
+struct Statements(Vec<Span>);
+
+impl Visit for Statements {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Spanned(span, _) = expr {
+            self.0.push(*span);
+        }
+        walk_expr(self, expr);
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct FunctionDef(pub Name, pub Function);
 
 impl Execute for FunctionDef {
     fn execute(&self, shell: &mut Shell) -> Result<(), Error> {
         let FunctionDef(name, func) = self;
-
-        Expr::Assignment(name.clone(), Value::Function(func.clone())).execute(shell)?;
-
-        Ok(())
+        func.execute(shell)?;
+        name.assign(shell)
     }
 }
 
+/// A function's parameters and body. The body is shared by every
+/// function value made from it, so that it isn't copied each time.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
-pub struct Function(pub Vec<Identifier>, pub Suite);
+pub struct Function(pub Vec<Identifier>, pub Rc<Suite>);
 
 impl Execute for Function {
     fn execute(&self, shell: &mut Shell) -> Result<(), Error> {
-        let Function(args, suite) = self.clone();
+        let Function(args, suite) = self;
+        let args = args.clone();
+        let suite = Rc::clone(suite);
+        // The body is compiled the first time the function is called
+        let code = OnceCell::new();
+        // The function uses the variables where it is defined,
+        // rather than the variables where it is called
+        let scope = Rc::clone(&shell.scope);
         shell.machine.push(xmachine::Value::function(
             move |m| {
-                let shell = &mut Shell::new();
+                let shell = &mut Shell::for_call();
                 shell.machine.stack = m.stack.clone();
                 shell.machine.registers = m.registers.clone();
-                for arg in args.clone() {
-                    let Identifier(store) = arg;
-                    shell.machine.push(xmachine::Value::string(store));
-                    shell.machine.store();
+                shell.scope = Scope::new(Some(Rc::clone(&scope)));
+                for Identifier(store) in &args {
+                    let value = shell.machine.pop().unwrap_or_else(xmachine::Value::none);
+                    shell.scope.define(store.clone(), value);
+                }
+                // Everything below this height belongs to the caller
+                let base = shell.machine.stack.len();
+                let code = code.get_or_init(|| compile(&suite));
+                if let Err(e) = code.execute(shell) {
+                    // To the caller, the error happened where the function was called
+                    let error = match shell.call_stack.last() {
+                        Some(frame) if frame.span != Span::default() => {
+                            e.in_function(&frame.function).at(frame.span)
+                        }
+                        Some(frame) => e.in_function(&frame.function),
+                        None => e.in_function("anonymous function"),
+                    };
+                    error.raise();
+                }
+                shell.return_to_caller();
+                if let Some(Signal::Return) = shell.signal.take() {
+                    // Discard any values leaked by the function body
+                    // so that only the returned value is left behind
+                    let result = shell.machine.pop();
+                    shell.machine.stack.truncate(base);
+                    if let Some(value) = result {
+                        shell.machine.push(value);
+                    }
                 }
-                match suite.execute(shell) {
-                    _ => {}
-                };
                 m.stack = shell.machine.stack.clone();
             },
             &shell.machine,
@@ -281,3 +1206,92 @@ impl Execute for Function {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::Shell;
+
+    /// Run a program, and get the values left by its top level statements
+    fn run(source: &str) -> Vec<String> {
+        match Shell::new().eval(source) {
+            Ok(values) => values.iter().map(ToString::to_string).collect(),
+            Err(e) => panic!("{} failed: {}", source, e.report(source)),
+        }
+    }
+
+    /// Run a program, and get the report of the error it stops with
+    fn report(shell: &mut Shell, source: &str) -> String {
+        match shell.eval(source) {
+            Ok(_) => panic!("{} should fail", source),
+            Err(e) => e.report(source),
+        }
+    }
+
+    #[test]
+    fn error_is_shown_inside_of_the_innermost_function() {
+        let source = "fn a(){undefined_thing} fn b(){a()} b()";
+        assert_eq!(
+            report(&mut Shell::new(), source),
+            "undefined variable `undefined_thing`
+ --> line 1, column 8
+  | fn a(){undefined_thing} fn b(){a()} b()
+  |        ^^^^^^^^^^^^^^^
+  in a, at line 1
+  called from b, at line 1"
+        );
+    }
+
+    #[test]
+    fn error_in_another_source_is_shown_at_the_whole_call() {
+        let mut shell = Shell::new();
+        shell.eval("fn a(x) {\n    undefined_thing\n}").unwrap();
+        assert_eq!(
+            report(&mut shell, "y = a(  1  )"),
+            "undefined variable `undefined_thing`
+ --> line 1, column 5
+  | y = a(  1  )
+  |     ^^^^^^^^
+  in a, at line 2"
+        );
+    }
+
+    #[test]
+    fn patterns_match_literals_lists_and_anything() {
+        let source = r#"
+            fn kind(x) {
+                match x {
+                    "rs" => { return "rust" }
+                    1 => { return "one" }
+                    none => { return "nothing" }
+                    ["a", _] => { return "a pair starting with a" }
+                    _ => { return "something else" }
+                }
+            }
+            kind("rs")
+            kind(1)
+            kind(none)
+            kind(str.split("a b", " "))
+            kind(str.split("a b c", " "))
+            kind(2)
+            match 1 < 2 { false => { x = "false" }, true => { x = "true" } }
+            match 1 { 1 => { y = "first" } _ => { y = "second" } }
+            z = "unchanged"
+            match "no arm" { "arm" => { z = "changed" } }
+            x
+            y
+            z
+        "#;
+        let expected = [
+            "rust",
+            "one",
+            "nothing",
+            "a pair starting with a",
+            "something else",
+            "something else",
+            "true",
+            "first",
+            "unchanged",
+        ];
+        assert_eq!(run(source), expected);
+    }
+}
@@ -0,0 +1,224 @@
+use crate::tokens::{Expr, FnCall, Function, FunctionDef, Identifier, Name, Suite, Value};
+use std::rc::Rc;
+
+/// Looks at each part of a program's syntax tree. By default, each method
+/// goes on to the parts inside of the one it's given, so a visitor only
+/// overrides the methods for what it's looking for. An overriding method
+/// can call the `walk_` function of the same name to keep going inside.
+pub trait Visit {
+    fn visit_suite(&mut self, suite: &Suite) {
+        walk_suite(self, suite);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_value(&mut self, value: &Value) {
+        walk_value(self, value);
+    }
+
+    /// Visit a name that is assigned to or used
+    fn visit_name(&mut self, name: &Name) {
+        walk_name(self, name);
+    }
+
+    /// Visit the parameters and body of a function, whether it's
+    /// defined with `fn name` or written as a value
+    fn visit_function(&mut self, params: &[Identifier], body: &Suite) {
+        walk_function(self, params, body);
+    }
+}
+
+pub fn walk_suite<V: Visit + ?Sized>(visitor: &mut V, Suite(exprs): &Suite) {
+    for expr in exprs {
+        visitor.visit_expr(expr);
+    }
+}
+
+/// Visit the parts of an expression in the order that they run
+pub fn walk_expr<V: Visit + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Spanned(_, expr) => visitor.visit_expr(expr),
+        Expr::Assignment(name, value) => {
+            visitor.visit_value(value);
+            visitor.visit_name(name);
+        }
+        Expr::WhileLoop(value, body, _) | Expr::ForLoop(_, value, body, _) => {
+            visitor.visit_value(value);
+            visitor.visit_suite(body);
+        }
+        Expr::IfThenElse(value, then_body, else_body) => {
+            visitor.visit_value(value);
+            visitor.visit_suite(then_body);
+            visitor.visit_suite(else_body);
+        }
+        Expr::Match(value, arms) => {
+            visitor.visit_value(value);
+            for (_, body) in arms {
+                visitor.visit_suite(body);
+            }
+        }
+        Expr::FunctionDef(FunctionDef(name, Function(params, body))) => {
+            visitor.visit_name(name);
+            visitor.visit_function(params, body);
+        }
+        Expr::Return(value) | Expr::Value(value) | Expr::Silent(value) => {
+            visitor.visit_value(value)
+        }
+        Expr::Import(..)
+        | Expr::Alias(..)
+        | Expr::Command(..)
+        | Expr::Unalias(_)
+        | Expr::Break
+        | Expr::Continue => {}
+    }
+}
+
+pub fn walk_value<V: Visit + ?Sized>(visitor: &mut V, value: &Value) {
+    match value {
+        Value::Spanned(_, value) => visitor.visit_value(value),
+        Value::Name(name) => visitor.visit_name(name),
+        Value::FnCall(FnCall(function, args)) => {
+            visitor.visit_value(function);
+            for arg in args {
+                visitor.visit_value(arg);
+            }
+        }
+        Value::Function(Function(params, body)) => visitor.visit_function(params, body),
+        Value::And(lhs, rhs) | Value::Or(lhs, rhs) => {
+            visitor.visit_value(lhs);
+            visitor.visit_value(rhs);
+        }
+        Value::Literal(_) | Value::Builtin(_) => {}
+    }
+}
+
+/// Visit the values that a dotted or indexed name is made of
+pub fn walk_name<V: Visit + ?Sized>(visitor: &mut V, name: &Name) {
+    match name {
+        Name::Name(_) => {}
+        Name::DotName(head, _) => visitor.visit_value(head),
+        Name::IndexName(head, indices) => {
+            visitor.visit_value(head);
+            for index in indices {
+                visitor.visit_value(index);
+            }
+        }
+    }
+}
+
+pub fn walk_function<V: Visit + ?Sized>(visitor: &mut V, _params: &[Identifier], body: &Suite) {
+    visitor.visit_suite(body);
+}
+
+/// Changes the parts of a program's syntax tree, like `Visit` does to
+/// look at them. The body of a function is shared by the copies of the
+/// function, so changing it makes a copy for the function being changed.
+pub trait VisitMut {
+    fn visit_suite_mut(&mut self, suite: &mut Suite) {
+        walk_suite_mut(self, suite);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_value_mut(&mut self, value: &mut Value) {
+        walk_value_mut(self, value);
+    }
+
+    fn visit_name_mut(&mut self, name: &mut Name) {
+        walk_name_mut(self, name);
+    }
+
+    fn visit_function_mut(&mut self, params: &mut Vec<Identifier>, body: &mut Suite) {
+        walk_function_mut(self, params, body);
+    }
+}
+
+pub fn walk_suite_mut<V: VisitMut + ?Sized>(visitor: &mut V, Suite(exprs): &mut Suite) {
+    for expr in exprs {
+        visitor.visit_expr_mut(expr);
+    }
+}
+
+pub fn walk_expr_mut<V: VisitMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Spanned(_, expr) => visitor.visit_expr_mut(expr),
+        Expr::Assignment(name, value) => {
+            visitor.visit_value_mut(value);
+            visitor.visit_name_mut(name);
+        }
+        Expr::WhileLoop(value, body, _) | Expr::ForLoop(_, value, body, _) => {
+            visitor.visit_value_mut(value);
+            visitor.visit_suite_mut(body);
+        }
+        Expr::IfThenElse(value, then_body, else_body) => {
+            visitor.visit_value_mut(value);
+            visitor.visit_suite_mut(then_body);
+            visitor.visit_suite_mut(else_body);
+        }
+        Expr::Match(value, arms) => {
+            visitor.visit_value_mut(value);
+            for (_, body) in arms {
+                visitor.visit_suite_mut(body);
+            }
+        }
+        Expr::FunctionDef(FunctionDef(name, Function(params, body))) => {
+            visitor.visit_name_mut(name);
+            visitor.visit_function_mut(params, Rc::make_mut(body));
+        }
+        Expr::Return(value) | Expr::Value(value) | Expr::Silent(value) => {
+            visitor.visit_value_mut(value)
+        }
+        Expr::Import(..)
+        | Expr::Alias(..)
+        | Expr::Command(..)
+        | Expr::Unalias(_)
+        | Expr::Break
+        | Expr::Continue => {}
+    }
+}
+
+pub fn walk_value_mut<V: VisitMut + ?Sized>(visitor: &mut V, value: &mut Value) {
+    match value {
+        Value::Spanned(_, value) => visitor.visit_value_mut(value),
+        Value::Name(name) => visitor.visit_name_mut(name),
+        Value::FnCall(FnCall(function, args)) => {
+            visitor.visit_value_mut(function);
+            for arg in args {
+                visitor.visit_value_mut(arg);
+            }
+        }
+        Value::Function(Function(params, body)) => {
+            visitor.visit_function_mut(params, Rc::make_mut(body))
+        }
+        Value::And(lhs, rhs) | Value::Or(lhs, rhs) => {
+            visitor.visit_value_mut(lhs);
+            visitor.visit_value_mut(rhs);
+        }
+        Value::Literal(_) | Value::Builtin(_) => {}
+    }
+}
+
+pub fn walk_name_mut<V: VisitMut + ?Sized>(visitor: &mut V, name: &mut Name) {
+    match name {
+        Name::Name(_) => {}
+        Name::DotName(head, _) => visitor.visit_value_mut(head),
+        Name::IndexName(head, indices) => {
+            visitor.visit_value_mut(head);
+            for index in indices {
+                visitor.visit_value_mut(index);
+            }
+        }
+    }
+}
+
+pub fn walk_function_mut<V: VisitMut + ?Sized>(
+    visitor: &mut V,
+    _params: &mut Vec<Identifier>,
+    body: &mut Suite,
+) {
+    visitor.visit_suite_mut(body);
+}